@@ -0,0 +1,70 @@
+//! Server throughput with a growing number of connections, each one sending SET and GET
+//! back to back. With a single shard every command waits on the same lock, with the default
+//! shard count commands on different keys run in parallel. The difference only shows on a
+//! machine with several cores, on a single core both run at the same speed.
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::future::join_all;
+use mini_projects::{
+    myredis::{
+        client::Client,
+        db::{Db, DEFAULT_SHARDS},
+        server,
+    },
+    timer_future::Clock,
+};
+use tokio::{net::TcpListener, runtime::Runtime};
+
+/// The SET + GET pairs each connection sends per iteration
+const REQUESTS: usize = 100;
+
+fn throughput(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("myredis throughput");
+    group.sample_size(10);
+
+    for shards in [1, DEFAULT_SHARDS] {
+        let addr = rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let db = Db::with_shards(shards, Clock::system());
+            tokio::spawn(async move { server::run(&listener, db).await });
+            addr
+        });
+
+        for connections in [1, 4, 16, 64] {
+            let mut clients = rt.block_on(async {
+                let clients = (0..connections).map(|_| Client::connect(addr));
+                join_all(clients)
+                    .await
+                    .into_iter()
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            });
+
+            group.throughput(Throughput::Elements((connections * REQUESTS * 2) as u64));
+            let id = BenchmarkId::new(format!("{} shards", shards), connections);
+            group.bench_function(id, |b| {
+                b.iter(|| {
+                    rt.block_on(join_all(clients.iter_mut().enumerate().map(
+                        |(n, client)| async move {
+                            for i in 0..REQUESTS {
+                                let key = format!("key:{}:{}", n, i);
+                                client
+                                    .set(&key, Bytes::from_static(b"value"))
+                                    .await
+                                    .unwrap();
+                                client.get(&key).await.unwrap();
+                            }
+                        },
+                    )))
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,117 @@
+//! Decoding `SET key <value>` commands with large values, the whole command at once and as it
+//! arrives in 4KB reads. `check + parse` is how a connection used to parse frames: it checks the
+//! buffer from the start after each read, parses the frame once it is complete and copies the
+//! value out of the buffer. The decoder resumes where the last read stopped and splits the value
+//! off the buffer.
+
+use std::io::Cursor;
+
+use bytes::{Buf, BytesMut};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use mini_projects::myredis::{
+    decoder::Decoder,
+    frame::{self, Frame, Limits, Protocol},
+};
+
+/// The size of each read of a chunked command
+const READ_SIZE: usize = 4 * 1024;
+
+fn command(len: usize) -> Vec<u8> {
+    let frame = Frame::bulks([b"SET".to_vec(), b"key".to_vec(), vec![b'x'; len]]);
+    let mut encoded = Vec::new();
+    frame.encode(Protocol::Resp2, &mut encoded);
+    encoded
+}
+
+/// A command with many small arguments
+fn mset(keys: usize) -> Vec<u8> {
+    let mut args = vec![b"MSET".to_vec()];
+    for i in 0..keys {
+        args.push(format!("key:{}", i).into_bytes());
+        args.push(b"value".to_vec());
+    }
+    let mut encoded = Vec::new();
+    Frame::bulks(args).encode(Protocol::Resp2, &mut encoded);
+    encoded
+}
+
+fn check_parse(buf: &mut BytesMut, limits: &Limits) -> Option<Frame> {
+    let mut cursor = Cursor::new(&buf[..]);
+    match Frame::check(&mut cursor, limits) {
+        Ok(()) => {
+            let len = cursor.position() as usize;
+            cursor.set_position(0);
+            let frame = Frame::parse(&mut cursor).unwrap();
+            buf.advance(len);
+            Some(frame)
+        }
+        Err(frame::Error::Incomplete) => None,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Feed `input` to `buf` `READ_SIZE` bytes at a time until `parse` returns a frame
+fn chunked(input: &[u8], mut parse: impl FnMut(&mut BytesMut) -> Option<Frame>) -> Frame {
+    let mut buf = BytesMut::with_capacity(READ_SIZE);
+    for chunk in input.chunks(READ_SIZE) {
+        buf.extend_from_slice(chunk);
+        if let Some(frame) = parse(&mut buf) {
+            return frame;
+        }
+    }
+    panic!("incomplete frame");
+}
+
+fn decode(c: &mut Criterion) {
+    let limits = Limits::default();
+    let inputs = [
+        ("1KB", command(1024)),
+        ("64KB", command(64 * 1024)),
+        ("1MB", command(1024 * 1024)),
+        ("10k args", mset(5000)),
+    ];
+
+    let mut group = c.benchmark_group("myredis frame");
+    for (name, input) in &inputs {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("check + parse", name),
+            input,
+            |b, input| {
+                b.iter_batched(
+                    || BytesMut::from(&input[..]),
+                    |mut buf| check_parse(&mut buf, &limits).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("decoder", name), input, |b, input| {
+            b.iter_batched(
+                || BytesMut::from(&input[..]),
+                |mut buf| Decoder::new().decode(&mut buf, &limits).unwrap().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("check + parse, 4KB reads", name),
+            input,
+            |b, input| b.iter(|| chunked(input, |buf| check_parse(buf, &limits))),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("decoder, 4KB reads", name),
+            input,
+            |b, input| {
+                b.iter(|| {
+                    let mut decoder = Decoder::new();
+                    chunked(input, |buf| decoder.decode(buf, &limits).unwrap())
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+//! A thousand SET commands sent one at a time, waiting for each reply, and sent as a single
+//! pipeline. The server executes all the commands it has already received before flushing
+//! their replies, so the pipeline is answered with a few writes instead of one per command,
+//! which `strace -c -f` on the server shows.
+//!
+//! The replies are also written to a stream that counts the writes reaching it, flushing after
+//! each reply and once after all of them. The counts are printed before the benchmark, whose
+//! timings leave out the cost of the syscalls: a write to the counting stream costs nothing.
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use mini_projects::myredis::{client::Client, db::Db, frame::Frame, server, Connection};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+};
+
+/// The commands in each pipeline
+const COMMANDS: usize = 1000;
+
+fn pipeline(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let addr = rt.block_on(async {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let db = Db::new();
+        tokio::spawn(async move { server::run(&listener, db).await });
+        addr
+    });
+
+    let mut group = c.benchmark_group("myredis pipeline");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+
+    let mut client = rt.block_on(Client::connect(addr)).unwrap();
+    group.bench_function("request/response", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for i in 0..COMMANDS {
+                    let key = format!("key:{}", i);
+                    client
+                        .set(&key, Bytes::from_static(b"value"))
+                        .await
+                        .unwrap();
+                }
+            })
+        })
+    });
+
+    let mut connection =
+        rt.block_on(async { Connection::new(TcpStream::connect(addr).await.unwrap()) });
+    group.bench_function("pipelined", |b| {
+        b.iter(|| {
+            rt.block_on(async {
+                for i in 0..COMMANDS {
+                    let frame = Frame::bulks([
+                        Bytes::from_static(b"SET"),
+                        Bytes::from(format!("key:{}", i)),
+                        Bytes::from_static(b"value"),
+                    ]);
+                    connection.write_frame_buffered(&frame).await.unwrap();
+                }
+                connection.flush().await.unwrap();
+                for _ in 0..COMMANDS {
+                    match connection.read_frame().await.unwrap() {
+                        Some(Frame::Simple(_)) => {}
+                        frame => panic!("unexpected reply: {:?}", frame),
+                    }
+                }
+            })
+        })
+    });
+    group.finish();
+}
+
+/// A stream that accepts every write and counts them, each would be a syscall on a socket.
+/// Reading from it returns end of file.
+#[derive(Clone, Default)]
+struct Counting {
+    writes: Arc<AtomicUsize>,
+}
+
+impl Counting {
+    fn writes(&self) -> usize {
+        self.writes.load(Ordering::Relaxed)
+    }
+}
+
+impl AsyncRead for Counting {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Counting {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Write the replies to a pipeline, flushing after each of them or only after the last one.
+/// Returns the number of writes that reached the stream.
+async fn write_replies(
+    connection: &mut Connection<Counting>,
+    stream: &Counting,
+    batched: bool,
+) -> usize {
+    let before = stream.writes();
+    let reply = Frame::Simple("OK".to_string());
+    for _ in 0..COMMANDS {
+        if batched {
+            connection.write_frame_buffered(&reply).await.unwrap();
+        } else {
+            connection.write_frame(&reply).await.unwrap();
+        }
+    }
+    connection.flush().await.unwrap();
+    stream.writes() - before
+}
+
+fn writes(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let stream = Counting::default();
+    let mut connection = Connection::new(stream.clone());
+    let per_frame = rt.block_on(write_replies(&mut connection, &stream, false));
+    let batched = rt.block_on(write_replies(&mut connection, &stream, true));
+    // 每个回复刷新一次就是一次写入，一起刷新时只有缓冲区写满时才写入
+    assert_eq!(per_frame, COMMANDS);
+    assert!(batched * 100 < per_frame, "{} batched writes", batched);
+    println!(
+        "{} replies: {} writes flushing each, {} writes flushing once",
+        COMMANDS, per_frame, batched
+    );
+
+    let mut group = c.benchmark_group("myredis pipeline writes");
+    group.throughput(Throughput::Elements(COMMANDS as u64));
+    group.bench_function("flush each reply", |b| {
+        b.iter(|| rt.block_on(write_replies(&mut connection, &stream, false)))
+    });
+    group.bench_function("flush once", |b| {
+        b.iter(|| rt.block_on(write_replies(&mut connection, &stream, true)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline, writes);
+criterion_main!(benches);
@@ -0,0 +1,150 @@
+//! Poll-heavy timer workloads: timers sit on every request path once timeouts are used,
+//! and a pending timer is typically polled many times before it fires.
+//!
+//! Each workload compares the lock-free `TimerFuture` with the `Mutex` implementation it replaced.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    time::Duration,
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mini_projects::timer_future::TimerFuture;
+
+/// A reference counted waker with a single static vtable like the ones real executors hand out:
+/// cloning it is not free, and `Waker::will_wake` recognises its clones.
+fn task_waker() -> Waker {
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, drop, noop, drop);
+
+    unsafe fn clone(data: *const ()) -> RawWaker {
+        Arc::increment_strong_count(data as *const Task);
+        RawWaker::new(data, &VTABLE)
+    }
+    unsafe fn drop(data: *const ()) {
+        Arc::decrement_strong_count(data as *const Task);
+    }
+    unsafe fn noop(_data: *const ()) {}
+
+    struct Task;
+    let data = Arc::into_raw(Arc::new(Task)) as *const ();
+    unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) }
+}
+
+fn poll_pending(c: &mut Criterion) {
+    let waker = task_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut group = c.benchmark_group("poll pending timer");
+
+    let mut timer = MutexTimerFuture::pending();
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            assert!(Pin::new(&mut timer).poll(&mut cx).is_pending());
+        })
+    });
+
+    let mut timer = TimerFuture::new(Duration::from_secs(3600));
+    group.bench_function("lock-free", |b| {
+        b.iter(|| {
+            assert!(Pin::new(&mut timer).poll(&mut cx).is_pending());
+        })
+    });
+    group.finish();
+}
+
+fn poll_pending_many(c: &mut Criterion) {
+    let waker = task_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut group = c.benchmark_group("poll 1000 pending timers");
+
+    let mut timers: Vec<_> = (0..1_000).map(|_| MutexTimerFuture::pending()).collect();
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            for timer in timers.iter_mut() {
+                assert!(Pin::new(timer).poll(&mut cx).is_pending());
+            }
+        })
+    });
+
+    let mut timers: Vec<_> = (0..1_000)
+        .map(|_| TimerFuture::new(Duration::from_secs(3600)))
+        .collect();
+    group.bench_function("lock-free", |b| {
+        b.iter(|| {
+            for timer in timers.iter_mut() {
+                assert!(Pin::new(timer).poll(&mut cx).is_pending());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn poll_completed(c: &mut Criterion) {
+    let waker = task_waker();
+    let mut cx = Context::from_waker(&waker);
+    let mut group = c.benchmark_group("poll completed timer");
+
+    let mut timer = MutexTimerFuture::completed();
+    group.bench_function("mutex", |b| {
+        b.iter(|| {
+            assert!(Pin::new(&mut timer).poll(&mut cx).is_ready());
+        })
+    });
+
+    let mut timer = TimerFuture::new(Duration::ZERO);
+    while Pin::new(&mut timer).poll(&mut cx) == Poll::Pending {}
+    group.bench_function("lock-free", |b| {
+        b.iter(|| {
+            assert!(Pin::new(&mut timer).poll(&mut cx).is_ready());
+        })
+    });
+    group.finish();
+}
+
+/// The `TimerFuture` before it was made lock-free, as the baseline: the state it shares with the
+/// driver is behind a `Mutex`, and each poll of a pending timer clones the waker. Polling never
+/// involved the driver, so the baseline is not registered with one.
+struct MutexTimerFuture {
+    shared_state: Arc<Mutex<SharedState>>,
+}
+
+struct SharedState {
+    completed: bool,
+    waker: Option<Waker>,
+}
+
+impl MutexTimerFuture {
+    fn pending() -> Self {
+        MutexTimerFuture {
+            shared_state: Arc::new(Mutex::new(SharedState {
+                completed: false,
+                waker: None,
+            })),
+        }
+    }
+
+    fn completed() -> Self {
+        let timer = MutexTimerFuture::pending();
+        timer.shared_state.lock().unwrap().completed = true;
+        timer
+    }
+}
+
+impl Future for MutexTimerFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared_state.lock().unwrap();
+        if state.completed {
+            Poll::Ready(())
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+criterion_group!(benches, poll_pending, poll_pending_many, poll_completed);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+//! The decoder fed the input a few bytes at a time must decode the same frames as checking and
+//! parsing the whole input, the way connections parsed frames before the decoder.
+//!
+//! The first byte of the input is the size of the reads. Run with `cargo fuzz run decoder` from
+//! the repository root.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use bytes::{Buf, BytesMut};
+use libfuzzer_sys::fuzz_target;
+use mini_projects::myredis::{
+    decoder::Decoder,
+    frame::{self, Frame, Limits, Protocol},
+};
+
+const LIMITS: Limits = Limits {
+    max_bulk_len: 1024,
+    max_array_len: 64,
+    max_depth: 8,
+    max_buffer_len: 4096,
+};
+
+/// The next frame of `buf` and its length, skipping blank inline lines
+fn check_parse(mut buf: &[u8]) -> Result<Option<(Frame, usize)>, frame::Error> {
+    let mut skipped = 0;
+    loop {
+        let mut cursor = Cursor::new(buf);
+        let inline = buf.first().is_some_and(|&b| !Frame::is_type_byte(b));
+        let res = match inline {
+            true => Frame::parse_inline(&mut cursor),
+            false => Frame::check(&mut cursor, &LIMITS).and_then(|_| {
+                cursor.set_position(0);
+                Frame::parse(&mut cursor)
+            }),
+        };
+        let len = cursor.position() as usize;
+        match res {
+            Ok(frame) if inline && frame == Frame::Array(Vec::new()) => {
+                buf = &buf[len..];
+                skipped += len;
+            }
+            Ok(frame) => return Ok(Some((frame, skipped + len))),
+            Err(frame::Error::Incomplete) => return Ok(None),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// The next frame of `input` fed to a decoder `read_size` bytes at a time, and its length
+fn decode(input: &[u8], read_size: usize) -> Result<Option<(Frame, usize)>, frame::Error> {
+    let mut decoder = Decoder::new();
+    let mut buf = BytesMut::new();
+    let mut fed = 0;
+    for chunk in input.chunks(read_size) {
+        buf.extend_from_slice(chunk);
+        fed += chunk.len();
+        if let Some(frame) = decoder.decode(&mut buf, &LIMITS)? {
+            return Ok(Some((frame, fed - buf.len())));
+        }
+        // 跳过的空行不算在内
+        assert!(
+            buf.remaining() + decoder.pending_len() <= fed,
+            "the decoder holds more data than it was fed"
+        );
+    }
+    Ok(None)
+}
+
+fn encode(frame: &Frame) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    frame.encode(Protocol::Resp3, &mut encoded);
+    encoded
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&read_size, input)) = data.split_first() else {
+        return;
+    };
+    let read_size = (read_size as usize).max(1);
+
+    match (check_parse(input), decode(input, read_size)) {
+        (Ok(Some((expected, expected_len))), Ok(Some((frame, len)))) => {
+            assert_eq!(encode(&expected), encode(&frame));
+            assert_eq!(expected_len, len, "the frames have different lengths");
+        }
+        (Ok(Some(_)), res) => panic!("the decoder did not decode the frame: {:?}", res.err()),
+        (res, Ok(Some(_))) => panic!("the decoder decoded an invalid frame: {:?}", res.err()),
+        // 帧不完整的时候，解码器可能已经解析到了后面的错误，例如不是 UTF-8 的简单字符串，
+        // 而 check 只检查帧的结构
+        _ => {}
+    }
+});
@@ -0,0 +1,53 @@
+//! Frames read from untrusted bytes: checking and parsing them must not panic nor recurse or
+//! allocate beyond the limits, a frame that passes the check must parse, and the encoding of a
+//! parsed frame must parse back to the same encoding in both protocols.
+//!
+//! Run with `cargo fuzz run frame` from the repository root.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use mini_projects::myredis::frame::{Frame, Limits, Protocol};
+
+const LIMITS: Limits = Limits {
+    max_bulk_len: 1024,
+    max_array_len: 64,
+    max_depth: 8,
+    max_buffer_len: 4096,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    if Frame::check(&mut cursor, &LIMITS).is_err() {
+        return;
+    }
+    let len = cursor.position();
+
+    cursor.set_position(0);
+    // 类型字节之后的内容 check 不会全部校验，例如 UTF-8 和大整数的数字，解析时才报错
+    let Ok(frame) = Frame::parse(&mut cursor) else {
+        return;
+    };
+    assert_eq!(
+        cursor.position(),
+        len,
+        "check and parse disagree on the frame length"
+    );
+
+    for protocol in [Protocol::Resp2, Protocol::Resp3] {
+        let mut encoded = Vec::new();
+        frame.encode(protocol, &mut encoded);
+
+        let mut cursor = Cursor::new(&encoded[..]);
+        Frame::check(&mut cursor, &LIMITS).expect("an encoded frame is valid");
+        assert_eq!(cursor.position() as usize, encoded.len());
+        cursor.set_position(0);
+        let parsed = Frame::parse(&mut cursor).expect("an encoded frame parses");
+
+        let mut reencoded = Vec::new();
+        parsed.encode(protocol, &mut reencoded);
+        assert_eq!(encoded, reencoded);
+    }
+});
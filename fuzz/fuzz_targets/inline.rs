@@ -0,0 +1,41 @@
+//! Inline commands read from untrusted bytes: parsing them must not panic, and arguments quoted
+//! with `\xHH` escapes must parse back to the same bytes.
+//!
+//! Run with `cargo fuzz run inline` from the repository root.
+
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use mini_projects::myredis::frame::Frame;
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    let Ok(Frame::Array(args)) = Frame::parse_inline(&mut cursor) else {
+        return;
+    };
+    let args: Vec<_> = args
+        .into_iter()
+        .map(|arg| match arg {
+            Frame::Bulk(arg) => arg,
+            frame => panic!("inline argument parsed as {:?}", frame),
+        })
+        .collect();
+
+    // 每个参数都用双引号和十六进制转义写回去
+    let mut line = Vec::new();
+    for arg in &args {
+        line.extend_from_slice(b" \"");
+        for byte in arg.iter() {
+            line.extend_from_slice(format!("\\x{:02x}", byte).as_bytes());
+        }
+        line.push(b'"');
+    }
+    line.extend_from_slice(b"\r\n");
+
+    let mut cursor = Cursor::new(&line[..]);
+    let reparsed = Frame::parse_inline(&mut cursor).expect("quoted arguments parse");
+    assert_eq!(reparsed, Frame::bulks(args));
+    assert_eq!(cursor.position() as usize, line.len());
+});
@@ -1,11 +1,10 @@
 use bytes::Bytes;
-use mini_redis::client;
-use mini_redis::Result;
+use mini_projects::myredis::{client::Client, Result};
 use tokio::{net::ToSocketAddrs, runtime::Runtime};
 
 pub struct BlockingClient {
     // 之前实现的异步客户端
-    inner: client::Client,
+    inner: Client,
 
     // 一个 tokio 运行时
     rt: Runtime,
@@ -21,7 +20,7 @@ pub fn connect<T: ToSocketAddrs>(addr: T) -> Result<BlockingClient> {
         .build()?;
 
     // 使用运行时来调用异步的连接方法
-    let inner = rt.block_on(client::connect(addr))?;
+    let inner = rt.block_on(Client::connect(addr))?;
 
     Ok(BlockingClient { inner, rt })
 }
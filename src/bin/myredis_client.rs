@@ -1,6 +1,14 @@
+use std::{env, path::PathBuf, process};
+
 use bytes::Bytes;
-use mini_redis::{client, Result};
-use tokio::sync::{mpsc, oneshot};
+use mini_projects::myredis::{client::Client, tls::ClientTls, Result};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::{mpsc, oneshot},
+};
+
+const USAGE: &str = "usage: myredis-client [-h <host>] [-p <port>] \
+                     [--tls --cacert <path> [--cert <path> --key <path>]]";
 
 #[derive(Debug)]
 enum Command {
@@ -18,11 +26,80 @@ enum Command {
 // 这是一个发送器，用于将管理任务中从服务端获取到的命令执行结果返回给发送命令的任务
 type Responsor<T> = oneshot::Sender<Result<T>>;
 
+/// The server to connect to
+struct Server {
+    host: String,
+    port: u16,
+    tls: Option<ClientTls>,
+}
+
+/// Parse the options, named after the redis-cli ones. The client connects to 127.0.0.1:6379 by
+/// default, with `--tls` the server certificate must be valid for the host and signed by one of
+/// the certificate authorities of `--cacert`.
+fn server() -> Server {
+    let mut server = Server {
+        host: String::from("127.0.0.1"),
+        port: 6379,
+        tls: None,
+    };
+    let mut tls = false;
+    let (mut cacert, mut cert, mut key) = (None, None, None);
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        // `--tls` 不带参数
+        if arg == "--tls" {
+            tls = true;
+            continue;
+        }
+        let parsed = match (&arg[..], args.next()) {
+            ("-h", Some(host)) => {
+                server.host = host;
+                true
+            }
+            ("-p", Some(port)) => port.parse().map(|p| server.port = p).is_ok(),
+            ("--cacert", Some(path)) => {
+                cacert = Some(PathBuf::from(path));
+                true
+            }
+            ("--cert", Some(path)) => {
+                cert = Some(PathBuf::from(path));
+                true
+            }
+            ("--key", Some(path)) => {
+                key = Some(PathBuf::from(path));
+                true
+            }
+            _ => false,
+        };
+        if !parsed {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+
+    server.tls = match (tls, cacert, cert, key) {
+        (true, Some(ca), Some(cert), Some(key)) => Some(ClientTls {
+            ca,
+            identity: Some((cert, key)),
+        }),
+        (true, Some(ca), None, None) => Some(ClientTls { ca, identity: None }),
+        (false, None, None, None) => None,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    server
+}
+
 #[tokio::main]
 async fn main() {
-    // 使用消息通道 mpsc 发送命令给管理连接的任务，管理任务收到命令后，再调用 mini-redis 客户端发送命令给服务端，并返回结果
+    let server = server();
+
+    // 使用消息通道 mpsc 发送命令给管理连接的任务，管理任务收到命令后，再调用 myredis 客户端发送命令给服务端，并返回结果
     // 创建消息通道
-    let (tx, mut rx) = mpsc::channel(32);
+    let (tx, rx) = mpsc::channel(32);
     let tx2 = tx.clone();
 
     // 发送命令的任务: mpsc 支持多发送方，这里一个用于发送 set 命令，一个用于发送 get 命令
@@ -55,24 +132,10 @@ async fn main() {
 
     // 管理连接的任务
     let manager = tokio::spawn(async move {
-        let mut client = client::connect("127.0.0.1:6379").await.unwrap();
-
-        while let Some(cmd) = rx.recv().await {
-            match cmd {
-                Command::Set {
-                    key,
-                    value,
-                    responsor,
-                } => {
-                    let res = client.set(&key, value).await;
-                    // 返回命令执行结果给发送命令的任务；忽略错误
-                    let _ = responsor.send(res);
-                }
-                Command::Get { key, responsor } => {
-                    let res = client.get(&key).await;
-                    let _ = responsor.send(res);
-                }
-            }
+        let addr = (&server.host[..], server.port);
+        match &server.tls {
+            Some(tls) => manage(Client::connect_tls(addr, &server.host, tls).await, rx).await,
+            None => manage(Client::connect(addr).await, rx).await,
         }
     });
 
@@ -80,3 +143,29 @@ async fn main() {
     t2.await.unwrap();
     manager.await.unwrap();
 }
+
+// 通过客户端执行收到的命令，客户端可以是 TCP 连接，也可以是 TLS 连接
+async fn manage<S: AsyncRead + AsyncWrite + Unpin>(
+    client: Result<Client<S>>,
+    mut rx: mpsc::Receiver<Command>,
+) {
+    let mut client = client.unwrap();
+
+    while let Some(cmd) = rx.recv().await {
+        match cmd {
+            Command::Set {
+                key,
+                value,
+                responsor,
+            } => {
+                let res = client.set(&key, value).await;
+                // 返回命令执行结果给发送命令的任务；忽略错误
+                let _ = responsor.send(res);
+            }
+            Command::Get { key, responsor } => {
+                let res = client.get(&key).await;
+                let _ = responsor.send(res);
+            }
+        }
+    }
+}
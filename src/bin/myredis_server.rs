@@ -1,76 +1,163 @@
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+use std::{env, path::PathBuf, process};
+
+use mini_projects::myredis::{
+    aof::Fsync,
+    db::Db,
+    frame::Limits,
+    server::{self, Config},
+    snapshot,
+    tls::{ClientAuth, ServerTls},
 };
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+const USAGE: &str = "usage: myredis-server [--bind <addr>] [--port <port>] \
+                     [--dbfilename <path>] [--appendonly yes|no] \
+                     [--appendfilename <path>] [--appendfsync always|everysec|no] \
+                     [--proto-max-bulk-len <bytes>] [--client-query-buffer-limit <bytes>] \
+                     [--tls-cert-file <path> --tls-key-file <path> \
+                     [--tls-ca-cert-file <path>] [--tls-auth-clients yes|no|optional]]";
+
+/// The address the server listens on
+struct Listen {
+    bind: String,
+    port: u16,
+}
+
+/// Parse the options, named after the redis configuration. The server listens on 127.0.0.1:6379
+/// by default. The snapshot file defaults to dump.rdb, the append-only file is disabled by default
+/// and defaults to appendonly.aof.
+///
+/// With a certificate and a key the port accepts TLS connections only. The clients are asked for
+/// a certificate signed by the certificate authorities of `--tls-ca-cert-file` when it is given.
+fn config() -> (Listen, Config) {
+    let mut listen = Listen {
+        bind: String::from("127.0.0.1"),
+        port: 6379,
+    };
+    let mut snapshot = PathBuf::from("dump.rdb");
+    let mut appendonly = false;
+    let mut appendfilename = PathBuf::from("appendonly.aof");
+    let mut appendfsync = Fsync::default();
+    let mut limits = Limits::default();
+    let (mut tls_cert, mut tls_key, mut tls_ca) = (None, None, None);
+    let mut tls_auth_clients = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let parsed = match (&arg[..], args.next()) {
+            ("--bind", Some(addr)) => {
+                listen.bind = addr;
+                true
+            }
+            ("--port", Some(port)) => port.parse().map(|p| listen.port = p).is_ok(),
+            ("--dbfilename", Some(path)) => {
+                snapshot = PathBuf::from(path);
+                true
+            }
+            ("--appendonly", Some(enabled)) => match &enabled[..] {
+                "yes" | "no" => {
+                    appendonly = enabled == "yes";
+                    true
+                }
+                _ => false,
+            },
+            ("--appendfilename", Some(path)) => {
+                appendfilename = PathBuf::from(path);
+                true
+            }
+            ("--appendfsync", Some(policy)) => policy.parse().map(|p| appendfsync = p).is_ok(),
+            ("--proto-max-bulk-len", Some(bytes)) => {
+                bytes.parse().map(|b| limits.max_bulk_len = b).is_ok()
+            }
+            ("--client-query-buffer-limit", Some(bytes)) => {
+                bytes.parse().map(|b| limits.max_buffer_len = b).is_ok()
+            }
+            ("--tls-cert-file", Some(path)) => {
+                tls_cert = Some(PathBuf::from(path));
+                true
+            }
+            ("--tls-key-file", Some(path)) => {
+                tls_key = Some(PathBuf::from(path));
+                true
+            }
+            ("--tls-ca-cert-file", Some(path)) => {
+                tls_ca = Some(PathBuf::from(path));
+                true
+            }
+            ("--tls-auth-clients", Some(auth)) => match &auth[..] {
+                "yes" | "no" | "optional" => {
+                    tls_auth_clients = Some(auth);
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if !parsed {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
 
-use bytes::Bytes;
-use mini_redis::Result;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, error, info};
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
+    // 和 redis 一样，给了 CA 证书时默认要求客户端提供证书
+    let client_auth = match (tls_ca, tls_auth_clients.as_deref()) {
+        (_, Some("no")) | (None, None) => Some(ClientAuth::No),
+        (Some(ca), Some("optional")) => Some(ClientAuth::Optional(ca)),
+        (Some(ca), _) => Some(ClientAuth::Required(ca)),
+        (None, Some(_)) => None,
+    };
+    let tls = match (tls_cert, tls_key, client_auth) {
+        (Some(cert), Some(key), Some(client_auth)) => Some(ServerTls {
+            cert,
+            key,
+            client_auth,
+        }),
+        (None, None, Some(ClientAuth::No)) => None,
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let config = Config {
+        snapshot: Some(snapshot),
+        appendonly: appendonly.then_some(appendfilename),
+        appendfsync,
+        limits,
+        tls,
+    };
+    (listen, config)
+}
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
-    let db: Db = Arc::new(Mutex::new(HashMap::new()));
+    let (listen, config) = config();
+    let listener = match TcpListener::bind((&listen.bind[..], listen.port)).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("myredis-server: {}:{}: {}", listen.bind, listen.port, err);
+            process::exit(1);
+        }
+    };
+    let db = Db::new();
 
     tokio::select! {
-        res = run(&listener, &db) => {
+        res = server::run_with(&listener, db.clone(), config.clone()) => {
             if let Err(err) = res {
-                error!(cause = %err, "failed to accept");
+                error!(cause = %err, "server error");
+                eprintln!("myredis-server: {}", err);
+                process::exit(1);
             }
         }
         _ = tokio::signal::ctrl_c() => {
             info!("shutting down");
-        }
-    }
-}
-
-async fn run(listener: &TcpListener, db: &Db) -> Result<()> {
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        debug!("socket addr: {:?}", addr);
-        let db = db.clone();
-        tokio::spawn(async move {
-            if let Err(err) = handle_connection(socket, db).await {
-                error!(cause = ?err, "connection error");
-            }
-        });
-    }
-}
-
-async fn handle_connection(socket: TcpStream, db: Db) -> Result<()> {
-    use mini_projects::Connection;
-    use mini_redis::{Command, Frame};
-
-    // `Connection` 对于 redis 的读写进行了抽象封装，因此我们读到的是一个一个数据帧frame(数据帧 = redis命令 + 数据)，而不是字节流
-    // `Connection` 是在 mini-redis 中定义
-    let mut connection = Connection::new(socket);
-
-    while let Some(frame) = connection.read_frame().await? {
-        println!("Got frame: {:?}", frame);
-
-        let cmd = Command::from_frame(frame)?;
-        println!("cmd: {:?}", cmd);
-
-        let response = match cmd {
-            Command::Get(cmd) => {
-                let db = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone())
-                } else {
-                    Frame::Null
+            // 退出之前保存一次快照，重启之后数据还在
+            if let Some(path) = &config.snapshot {
+                if let Err(err) = snapshot::save(&db, path) {
+                    error!(cause = %err, "failed to save snapshot");
                 }
             }
-            Command::Set(cmd) => {
-                let mut db = db.lock().unwrap();
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
-            }
-            cmd => panic!("unimplemented {:?}", cmd),
-        };
-        connection.write_frame(&response).await?;
+        }
     }
-
-    Ok(())
 }
@@ -38,7 +38,7 @@ fn handle_connection(mut stream: TcpStream) {
         .collect();
     // println!("http request: {:#?}", request);
 
-    let first_line = if let Some(first_line) = request.get(0) {
+    let first_line = if let Some(first_line) = request.first() {
         first_line
     } else {
         return;
@@ -76,7 +76,7 @@ impl Executor {
             if let Some(mut future) = future_slot.take() {
                 // 基于任务自身创建一个 `LocalWaker`
                 let waker = waker_ref(&task);
-                let context = &mut Context::from_waker(&*waker);
+                let context = &mut Context::from_waker(&waker);
                 // `BoxFuture<T>`是`Pin<Box<dyn Future<Output = T> + Send + 'static>>`的类型别名
                 // 通过调用`as_mut`方法，可以将上面的类型转换成`Pin<&mut dyn Future + Send + 'static>`
                 if future.as_mut().poll(context).is_pending() {
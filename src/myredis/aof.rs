@@ -0,0 +1,589 @@
+//! Append-only file persistence.
+//! 每条修改数据库的命令执行之后都以 RESP 格式追加到文件末尾，启动时按顺序重放这些命令恢复数据库。
+//! 命令在持有日志锁时执行并写入文件，所以文件里命令的顺序就是它们执行的顺序。
+//! 相对的过期时间写成绝对的 unix 时间，阻塞的弹出命令写成实际发生的非阻塞弹出，重放的结果不依赖重放的时间。
+//! 交给阻塞的客户端的元素由推入它的命令在同一次持有日志锁时写成弹出命令，中间不会插入其他命令。
+//! 文件会越来越长，BGREWRITEAOF 把数据库当前的内容写成最少的命令，替换掉原来的文件。
+
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Cursor, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{error, info, warn};
+
+use super::{
+    cmd::{Command, ListEnd, SetCondition},
+    db::{Db, ExpireCondition, Expiry, Handoff, Record, Value},
+    frame::{self, Frame, Limits, Protocol},
+    parse::format_float,
+    snapshot::sync_dir,
+    Result,
+};
+
+/// The number of elements per command when a collection is rewritten, as in redis
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// When the append-only file is flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fsync {
+    /// After every write, before replying
+    Always,
+    /// Once per second, a crash loses at most the last second of writes
+    #[default]
+    Everysec,
+    /// Whenever the operating system does
+    No,
+}
+
+/// The append-only file of a database, cloning it gives another handle to the same file
+#[derive(Clone)]
+pub struct Aof {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    path: PathBuf,
+    fsync: Fsync,
+    state: Mutex<State>,
+    rewriting: AtomicBool,
+}
+
+struct State {
+    file: Arc<File>,
+    /// Whether something was written since the last fsync
+    dirty: bool,
+    /// The commands logged while a rewrite runs, appended to the rewritten file
+    rewrite: Option<Vec<u8>>,
+}
+
+/// Exclusive access to the log, held while a write command runs so that commands are logged in
+/// the order they run
+pub struct Log<'a> {
+    state: MutexGuard<'a, State>,
+}
+
+/// How a write command is logged, prepared before it runs since running it consumes it
+pub enum Entry {
+    Request(Frame),
+    /// A blocking pop in a transaction, logged as the pop it turned into. Outside of
+    /// transactions, the pops of blocked clients are logged as handoffs, see `Log::append_handoffs`.
+    Pop(ListEnd),
+    /// BLMOVE in a transaction, logged as the pop and the push it turned into
+    Move {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+    },
+}
+
+impl Aof {
+    /// Open the file at `path` for appending, creating it if needed
+    pub fn open(path: PathBuf, fsync: Fsync) -> io::Result<Aof> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Aof {
+            shared: Arc::new(Shared {
+                path,
+                fsync,
+                state: Mutex::new(State {
+                    file: Arc::new(file),
+                    dirty: false,
+                    rewrite: None,
+                }),
+                rewriting: AtomicBool::new(false),
+            }),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.shared.path
+    }
+
+    pub fn fsync(&self) -> Fsync {
+        self.shared.fsync
+    }
+
+    /// Wait for exclusive access to the log
+    pub async fn lock(&self) -> Log<'_> {
+        Log {
+            state: self.shared.state.lock().await,
+        }
+    }
+
+    /// Flush what was written to disk
+    pub async fn sync(&self) -> io::Result<()> {
+        let file = {
+            let mut state = self.shared.state.lock().await;
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.file.clone()
+        };
+        // fsync 期间不持有锁，其他命令可以继续写入
+        tokio::task::spawn_blocking(move || file.sync_data()).await?
+    }
+
+    /// Flush the file to disk every second, for the `everysec` policy
+    pub async fn sync_every_second(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(err) = self.sync().await {
+                error!(cause = %err, "failed to fsync the append-only file");
+            }
+        }
+    }
+
+    /// Start rewriting the file from the content of `db` in the background. Commands logged
+    /// meanwhile are kept and appended to the new file before it replaces the old one.
+    pub async fn rewrite(&self, db: &Db) -> Result<()> {
+        if self.shared.rewriting.swap(true, Ordering::AcqRel) {
+            return Err("ERR Background append only file rewriting already in progress".into());
+        }
+
+        // 持有日志锁时复制数据库，复制的内容和之后记录的命令不会重叠
+        let records: Vec<Record> = {
+            let mut state = self.shared.state.lock().await;
+            state.rewrite = Some(Vec::new());
+            db.dump().flatten().collect()
+        };
+
+        let aof = self.clone();
+        tokio::task::spawn_blocking(move || {
+            match aof.finish_rewrite(&records) {
+                Ok(()) => info!(path = %aof.path().display(), "append-only file rewritten"),
+                Err(err) => error!(cause = %err, "failed to rewrite the append-only file"),
+            }
+            aof.shared.rewriting.store(false, Ordering::Release);
+        });
+        Ok(())
+    }
+
+    fn finish_rewrite(&self, records: &[Record]) -> io::Result<()> {
+        let mut tmp = OsString::from(self.path().as_os_str());
+        tmp.push(".rewrite");
+        let tmp = PathBuf::from(tmp);
+
+        let res = (|| {
+            let mut file = BufWriter::new(File::create(&tmp)?);
+            write_records(records, &mut file)?;
+            let mut file = file.into_inner()?;
+
+            // 写入复制之后记录的命令，然后替换原来的文件，这期间不能再记录命令
+            let mut state = self.shared.state.blocking_lock();
+            let logged = state.rewrite.take().unwrap_or_default();
+            file.write_all(&logged)?;
+            file.sync_all()?;
+            fs::rename(&tmp, self.path())?;
+            sync_dir(self.path())?;
+            state.file = Arc::new(file);
+            state.dirty = false;
+            Ok(())
+        })();
+
+        if res.is_err() {
+            self.shared.state.blocking_lock().rewrite = None;
+            let _ = fs::remove_file(&tmp);
+        }
+        res
+    }
+}
+
+impl Log<'_> {
+    /// Append the commands of a write that succeeded
+    pub fn append(&mut self, frames: &[Frame]) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for frame in frames {
+            frame.encode(Protocol::Resp2, &mut buf);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+
+        (&*self.state.file).write_all(&buf)?;
+        self.state.dirty = true;
+        if let Some(rewrite) = &mut self.state.rewrite {
+            rewrite.extend_from_slice(&buf);
+        }
+        Ok(())
+    }
+
+    /// Append the changes made to lists for blocked clients since the last call. Whoever holds
+    /// the log while a command serves blocked clients must call it before releasing the log.
+    pub fn append_handoffs(&mut self, db: &Db) -> io::Result<()> {
+        self.append(&handoffs(db))
+    }
+}
+
+/// The commands that replay the changes made to lists for blocked clients since the last call
+pub fn handoffs(db: &Db) -> Vec<Frame> {
+    let handoffs = db.take_handoffs().into_iter();
+    handoffs
+        .map(|handoff| match handoff {
+            Handoff::Pop { key, end } => pop_frame(end, Bytes::from(key)),
+            Handoff::Push { key, end, element } => push_frame(end, Bytes::from(key), element),
+        })
+        .collect()
+}
+
+impl Entry {
+    /// Prepare logging `cmd`, received as `request`
+    pub fn new(cmd: &Command, request: Frame) -> Entry {
+        match cmd {
+            Command::Set(set) => match set.expiry {
+                Some(Expiry::After(after)) => {
+                    let mut args = vec![
+                        Bytes::from_static(b"SET"),
+                        Bytes::from(set.key.clone()),
+                        set.value.clone(),
+                    ];
+                    match set.condition {
+                        Some(SetCondition::NotExists) => args.push(Bytes::from_static(b"NX")),
+                        Some(SetCondition::Exists) => args.push(Bytes::from_static(b"XX")),
+                        None => {}
+                    }
+                    if set.get {
+                        args.push(Bytes::from_static(b"GET"));
+                    }
+                    args.push(Bytes::from_static(b"PXAT"));
+                    args.push(unix_millis(SystemTime::now() + after));
+                    Entry::Request(Frame::bulks(args))
+                }
+                _ => Entry::Request(request),
+            },
+            Command::Expire {
+                key,
+                expiry: Expiry::After(after),
+                condition,
+            } => {
+                let mut args = vec![
+                    Bytes::from_static(b"PEXPIREAT"),
+                    Bytes::from(key.clone()),
+                    unix_millis(SystemTime::now() + *after),
+                ];
+                if let Some(condition) = condition {
+                    args.push(Bytes::from_static(match condition {
+                        ExpireCondition::NotExists => b"NX",
+                        ExpireCondition::Exists => b"XX",
+                        ExpireCondition::Greater => b"GT",
+                        ExpireCondition::Less => b"LT",
+                    }));
+                }
+                Entry::Request(Frame::bulks(args))
+            }
+            Command::BlockingPop { end, .. } => Entry::Pop(*end),
+            Command::Blmove {
+                source,
+                destination,
+                from,
+                to,
+                ..
+            } => Entry::Move {
+                source: source.clone(),
+                destination: destination.clone(),
+                from: *from,
+                to: *to,
+            },
+            _ => Entry::Request(request),
+        }
+    }
+
+    /// The commands to log once the command replied `response`, nothing if it failed
+    pub fn frames(self, response: &Frame) -> Vec<Frame> {
+        match (self, response) {
+            (_, Frame::Error(_)) => vec![],
+            (Entry::Request(request), _) => vec![request],
+            // 阻塞的弹出回复键和元素，超时时回复空值
+            (Entry::Pop(end), Frame::Array(popped)) => match popped.first() {
+                Some(Frame::Bulk(key)) => vec![pop_frame(end, key.clone())],
+                _ => vec![],
+            },
+            (
+                Entry::Move {
+                    source,
+                    destination,
+                    from,
+                    to,
+                },
+                Frame::Bulk(element),
+            ) => vec![
+                pop_frame(from, Bytes::from(source)),
+                push_frame(to, Bytes::from(destination), element.clone()),
+            ],
+            _ => vec![],
+        }
+    }
+}
+
+fn pop_frame(end: ListEnd, key: Bytes) -> Frame {
+    let pop = match end {
+        ListEnd::Left => Bytes::from_static(b"LPOP"),
+        ListEnd::Right => Bytes::from_static(b"RPOP"),
+    };
+    Frame::bulks([pop, key])
+}
+
+fn push_frame(end: ListEnd, key: Bytes, element: Bytes) -> Frame {
+    let push = match end {
+        ListEnd::Left => Bytes::from_static(b"LPUSH"),
+        ListEnd::Right => Bytes::from_static(b"RPUSH"),
+    };
+    Frame::bulks([push, key, element])
+}
+
+fn unix_millis(time: SystemTime) -> Bytes {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis());
+    Bytes::from(millis.to_string())
+}
+
+/// Write `records` as the commands that recreate them
+pub fn write_records(records: &[Record], mut writer: impl Write) -> io::Result<()> {
+    let mut buf = Vec::new();
+    for record in records {
+        let key = Bytes::from(record.key.clone());
+        let mut commands = Vec::new();
+        match &record.value {
+            Value::String(value) => {
+                let mut args = vec![Bytes::from_static(b"SET"), key.clone(), value.clone()];
+                if let Some(expires_at) = record.expires_at {
+                    args.push(Bytes::from_static(b"PXAT"));
+                    args.push(unix_millis(expires_at));
+                }
+                commands.push(args);
+            }
+            Value::List(list) => {
+                let elements: Vec<_> = list.iter().cloned().collect();
+                commands.extend(chunked("RPUSH", &key, elements));
+            }
+            Value::Hash(hash) => {
+                let fields = hash.iter().flat_map(|(field, value)| [field, value]);
+                commands.extend(chunked("HSET", &key, fields.cloned().collect()));
+            }
+            Value::Set(set) => {
+                commands.extend(chunked("SADD", &key, set.iter().cloned().collect()));
+            }
+            Value::SortedSet(zset) => {
+                let members = zset
+                    .iter_from_rank(0, false)
+                    .flat_map(|(member, score)| [Bytes::from(format_float(score)), member.clone()]);
+                commands.extend(chunked("ZADD", &key, members.collect()));
+            }
+        }
+        if let (Some(expires_at), false) =
+            (record.expires_at, matches!(record.value, Value::String(_)))
+        {
+            commands.push(vec![
+                Bytes::from_static(b"PEXPIREAT"),
+                key,
+                unix_millis(expires_at),
+            ]);
+        }
+
+        for args in commands {
+            Frame::bulks(args).encode(Protocol::Resp2, &mut buf);
+        }
+        writer.write_all(&buf)?;
+        buf.clear();
+    }
+    writer.flush()
+}
+
+/// One command per `ITEMS_PER_COMMAND` elements, a hash field and its value or a member and its
+/// score count as one element
+fn chunked(name: &'static str, key: &Bytes, args: Vec<Bytes>) -> Vec<Vec<Bytes>> {
+    let per_element = match name {
+        "HSET" | "ZADD" => 2,
+        _ => 1,
+    };
+    args.chunks(ITEMS_PER_COMMAND * per_element)
+        .map(|chunk| {
+            let mut command = vec![Bytes::from_static(name.as_bytes()), key.clone()];
+            command.extend_from_slice(chunk);
+            command
+        })
+        .collect()
+}
+
+/// Replay the file at `path` on `db`, returning the number of replayed commands. The commands
+/// are read within the `limits` of the connections that sent them.
+///
+/// A command or a transaction cut short at the end of the file, left by a crash in the middle of
+/// a write, is truncated from the file with a warning. Any other malformed content fails.
+pub async fn replay(db: &Db, path: &Path, limits: &Limits) -> io::Result<usize> {
+    let data = fs::read(path)?;
+    let mut cursor = Cursor::new(&data[..]);
+    let mut replayed = 0;
+    // 事务开始的位置和已经读到的命令，读到 EXEC 时才执行
+    let mut transaction: Option<(u64, Vec<Command>)> = None;
+
+    while (cursor.position() as usize) < data.len() {
+        let start = cursor.position();
+        match Frame::check(&mut cursor, limits) {
+            Ok(()) => {}
+            Err(frame::Error::Incomplete) => {
+                cursor.set_position(start);
+                break;
+            }
+            Err(frame::Error::Other(err)) => return Err(corrupt(start, err)),
+        }
+
+        cursor.set_position(start);
+        let frame = Frame::parse(&mut cursor).map_err(|err| corrupt(start, err))?;
+        let cmd = Command::from_frame(frame).map_err(|err| corrupt(start, err))?;
+        match (cmd, &mut transaction) {
+            (Command::Multi, None) => transaction = Some((start, Vec::new())),
+            (Command::Exec, Some(_)) => {
+                let (_, commands) = transaction.take().unwrap();
+                for cmd in commands {
+                    cmd.apply(db).await;
+                    replayed += 1;
+                }
+            }
+            (cmd, _) if !cmd.is_write() || cmd.is_blocking() => {
+                return Err(corrupt(start, format!("unexpected command {:?}", cmd)));
+            }
+            (cmd, Some((_, commands))) => commands.push(cmd),
+            (cmd, None) => {
+                cmd.apply(db).await;
+                replayed += 1;
+            }
+        }
+    }
+
+    // 最后一条命令或者最后一个事务不完整
+    let complete = match &transaction {
+        Some((start, _)) => *start,
+        None => cursor.position(),
+    };
+    if complete < data.len() as u64 {
+        warn!(
+            offset = complete,
+            "append-only file ends with a truncated command, truncating it"
+        );
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete)?;
+    }
+    Ok(replayed)
+}
+
+fn corrupt(offset: u64, err: impl fmt::Display) -> io::Error {
+    let msg = format!("bad append-only file at offset {}: {}", offset, err);
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl FromStr for Fsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Fsync, String> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(Fsync::Always),
+            "everysec" => Ok(Fsync::Everysec),
+            "no" => Ok(Fsync::No),
+            _ => Err(format!("invalid fsync policy `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The commands made of `requests` in RESP
+    fn encode(requests: &[&[&str]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for args in requests {
+            let args = args.iter().map(|arg| Bytes::from(arg.to_string()));
+            Frame::bulks(args).encode(Protocol::Resp2, &mut buf);
+        }
+        buf
+    }
+
+    async fn lrange(db: &Db, key: &str) -> Frame {
+        let args = ["LRANGE", key, "0", "-1"].map(|arg| Bytes::from(arg.to_string()));
+        Command::from_frame(Frame::bulks(args))
+            .unwrap()
+            .apply(db)
+            .await
+    }
+
+    #[tokio::test]
+    async fn torn_command_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let complete = encode(&[&["RPUSH", "l", "a", "b"], &["LPOP", "l"]]);
+        let mut data = complete.clone();
+        data.extend_from_slice(&encode(&[&["RPUSH", "l", "c"]])[..10]);
+        fs::write(&path, &data).unwrap();
+
+        let db = Db::new();
+        assert_eq!(replay(&db, &path, &Limits::default()).await.unwrap(), 2);
+        assert_eq!(lrange(&db, "l").await, Frame::bulks([Bytes::from("b")]));
+        assert_eq!(fs::read(&path).unwrap(), complete);
+
+        // 截断之后的文件可以完整地重放
+        let db = Db::new();
+        assert_eq!(replay(&db, &path, &Limits::default()).await.unwrap(), 2);
+        assert_eq!(lrange(&db, "l").await, Frame::bulks([Bytes::from("b")]));
+    }
+
+    #[tokio::test]
+    async fn torn_transaction_is_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let complete = encode(&[&["RPUSH", "l", "a"]]);
+        let mut data = complete.clone();
+        data.extend_from_slice(&encode(&[&["MULTI"], &["LPOP", "l"], &["RPUSH", "l", "b"]]));
+        fs::write(&path, &data).unwrap();
+
+        // 没有 EXEC 的事务一条命令都不执行
+        let db = Db::new();
+        assert_eq!(replay(&db, &path, &Limits::default()).await.unwrap(), 1);
+        assert_eq!(lrange(&db, "l").await, Frame::bulks([Bytes::from("a")]));
+        assert_eq!(fs::read(&path).unwrap(), complete);
+    }
+
+    #[tokio::test]
+    async fn corrupt_content_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let mut data = encode(&[&["RPUSH", "l", "a"]]);
+        let offset = data.len();
+        data.extend_from_slice(b"?garbage\r\n");
+        data.extend_from_slice(&encode(&[&["RPUSH", "l", "b"]]));
+        fs::write(&path, &data).unwrap();
+
+        let err = replay(&Db::new(), &path, &Limits::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .starts_with(&format!("bad append-only file at offset {}", offset)));
+        // 损坏的文件不会被截断
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        // 只读命令也不应该出现在文件里
+        fs::write(&path, encode(&[&["GET", "l"]])).unwrap();
+        let err = replay(&Db::new(), &path, &Limits::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
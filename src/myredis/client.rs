@@ -0,0 +1,105 @@
+//! A minimal myredis client.
+//! 客户端把命令编码成由 bulk string 组成的数组帧发送给服务端，然后等待服务端的响应帧。
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tokio_rustls::{client::TlsStream, rustls::pki_types::ServerName};
+
+use super::{frame::Frame, tls::ClientTls, Connection, Result};
+
+pub struct Client<S = TcpStream> {
+    connection: Connection<S>,
+}
+
+impl Client {
+    /// Connect to the server listening on `addr`
+    pub async fn connect<T: ToSocketAddrs>(addr: T) -> Result<Client> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Client {
+            connection: Connection::new(socket),
+        })
+    }
+}
+
+impl Client<TlsStream<TcpStream>> {
+    /// Connect to the server listening on `addr` over TLS, its certificate must be valid for
+    /// `server_name`, a DNS name or an IP address
+    pub async fn connect_tls<T: ToSocketAddrs>(
+        addr: T,
+        server_name: &str,
+        tls: &ClientTls,
+    ) -> Result<Self> {
+        let connector = tls.connector()?;
+        let server_name = ServerName::try_from(server_name.to_string())?;
+        let socket = TcpStream::connect(addr).await?;
+        let stream = connector.connect(server_name, socket).await?;
+        Ok(Client {
+            connection: Connection::new(stream),
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Client<S> {
+    pub async fn ping(&mut self, message: Option<Bytes>) -> Result<Bytes> {
+        let mut args = vec![Bytes::from_static(b"PING")];
+        args.extend(message);
+        match self.request(args).await? {
+            Frame::Simple(pong) => Ok(Bytes::from(pong)),
+            Frame::Bulk(message) => Ok(message),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    pub async fn get(&mut self, key: &str) -> Result<Option<Bytes>> {
+        match self.request(args(["GET", key])).await? {
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    pub async fn set(&mut self, key: &str, value: Bytes) -> Result<()> {
+        let mut args = args(["SET", key]);
+        args.push(value);
+        match self.request(args).await? {
+            Frame::Simple(_) => Ok(()),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    /// Publish `message` on `channel`, returning the number of subscribers that received it
+    pub async fn publish(&mut self, channel: &str, message: Bytes) -> Result<i64> {
+        let mut args = args(["PUBLISH", channel]);
+        args.push(message);
+        match self.request(args).await? {
+            Frame::Integer(receivers) => Ok(receivers),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    /// Send a command and wait for its reply, error frames are turned into `Err`
+    pub async fn request(&mut self, args: Vec<Bytes>) -> Result<Frame> {
+        self.connection
+            .write_frame(&Frame::Array(args.into_iter().map(Frame::Bulk).collect()))
+            .await?;
+
+        match self.connection.read_frame().await? {
+            Some(Frame::Error(err)) => Err(err.into()),
+            Some(frame) => Ok(frame),
+            None => Err("connection reset by server".into()),
+        }
+    }
+}
+
+fn args<const N: usize>(args: [&str; N]) -> Vec<Bytes> {
+    args.iter()
+        .map(|arg| Bytes::copy_from_slice(arg.as_bytes()))
+        .collect()
+}
+
+fn unexpected(frame: Frame) -> super::Error {
+    format!("unexpected frame: {}", frame).into()
+}
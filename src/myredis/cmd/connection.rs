@@ -0,0 +1,60 @@
+//! Connection commands.
+
+use super::{Command, Parse, ParseError};
+use crate::myredis::frame::Protocol;
+
+/// HELLO [protover], switch the protocol of the connection to RESP2 or RESP3
+pub(super) fn hello(parse: &mut Parse) -> Result<Command, ParseError> {
+    let protocol = match parse.remaining() {
+        0 => None,
+        _ => match parse.next_int() {
+            Ok(2) => Some(Protocol::Resp2),
+            Ok(3) => Some(Protocol::Resp3),
+            Ok(_) => {
+                let msg = "NOPROTO unsupported protocol version".to_string();
+                return Err(ParseError::Invalid(msg));
+            }
+            Err(_) => {
+                let msg = "ERR Protocol version is not an integer or out of range".to_string();
+                return Err(ParseError::Invalid(msg));
+            }
+        },
+    };
+    Ok(Command::Hello { protocol })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::cmd::tests::parse;
+
+    #[test]
+    fn hello() {
+        assert_eq!(parse(&["hello"]), Ok(Command::Hello { protocol: None }));
+        assert_eq!(
+            parse(&["HELLO", "3"]),
+            Ok(Command::Hello {
+                protocol: Some(Protocol::Resp3)
+            })
+        );
+        assert_eq!(
+            parse(&["hello", "2"]),
+            Ok(Command::Hello {
+                protocol: Some(Protocol::Resp2)
+            })
+        );
+        assert_eq!(
+            parse(&["hello", "4"]),
+            Err("NOPROTO unsupported protocol version".to_string())
+        );
+        assert_eq!(
+            parse(&["hello", "three"]),
+            Err("ERR Protocol version is not an integer or out of range".to_string())
+        );
+        // 不支持 AUTH 和 SETNAME 选项
+        assert_eq!(
+            parse(&["hello", "3", "setname", "x"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Hash commands.
+
+use bytes::Bytes;
+
+use super::{
+    scan::{self, Scan},
+    Command, Parse, ParseError,
+};
+use crate::myredis::{
+    db::Keyspace,
+    frame::Frame,
+    parse::{format_float, parse_float, parse_int},
+    Error,
+};
+
+/// HSET key field value [field value ...]
+pub(super) fn hset(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    if !parse.remaining().is_multiple_of(2) {
+        return Err(ParseError::WrongArity("hset".to_string()));
+    }
+    let mut fields = Vec::with_capacity(parse.remaining() / 2);
+    while parse.remaining() > 0 {
+        fields.push((parse.next_bytes()?, parse.next_bytes()?));
+    }
+    Ok(Command::Hset { key, fields })
+}
+
+/// HGET key field
+pub(super) fn hget(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hget {
+        key: parse.next_string()?,
+        field: parse.next_bytes()?,
+    })
+}
+
+/// HDEL key field [field ...]
+pub(super) fn hdel(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hdel {
+        key: parse.next_string()?,
+        fields: parse.rest_bytes()?,
+    })
+}
+
+/// HGETALL key
+pub(super) fn hgetall(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hgetall {
+        key: parse.next_string()?,
+    })
+}
+
+/// HINCRBY key field increment
+pub(super) fn hincrby(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hincrby {
+        key: parse.next_string()?,
+        field: parse.next_bytes()?,
+        increment: parse.next_int()?,
+    })
+}
+
+/// HINCRBYFLOAT key field increment
+pub(super) fn hincrbyfloat(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hincrbyfloat {
+        key: parse.next_string()?,
+        field: parse.next_bytes()?,
+        increment: parse.next_float()?,
+    })
+}
+
+/// HEXISTS key field
+pub(super) fn hexists(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hexists {
+        key: parse.next_string()?,
+        field: parse.next_bytes()?,
+    })
+}
+
+/// HLEN key
+pub(super) fn hlen(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Hlen {
+        key: parse.next_string()?,
+    })
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+pub(super) fn hscan(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let mut novalues = false;
+    let scan = Scan::parse(parse, |flag, _| {
+        novalues |= flag == "NOVALUES";
+        Ok(flag == "NOVALUES")
+    })?;
+    Ok(Command::Hscan {
+        key,
+        scan,
+        novalues,
+    })
+}
+
+/// Reply with the number of fields that were added, the others were updated
+pub(super) fn apply_hset(
+    db: &impl Keyspace,
+    key: &str,
+    fields: Vec<(Bytes, Bytes)>,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let hash = shard.hash_or_insert(key)?;
+        let mut added = 0;
+        for (field, value) in fields {
+            if hash.insert(field, value).is_none() {
+                added += 1;
+            }
+        }
+        Ok(Frame::Integer(added as i64))
+    })
+}
+
+pub(super) fn apply_hget(db: &impl Keyspace, key: &str, field: &[u8]) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let value = shard.hash(key)?.and_then(|hash| hash.get(field).cloned());
+        Ok(value.into())
+    })
+}
+
+/// Reply with the number of fields that were removed, the key is deleted once the hash is empty
+pub(super) fn apply_hdel(
+    db: &impl Keyspace,
+    key: &str,
+    fields: Vec<Bytes>,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(hash) = shard.hash(key)? else {
+            return Ok(Frame::Integer(0));
+        };
+        let removed = fields
+            .iter()
+            .filter(|field| hash.remove(*field).is_some())
+            .count();
+        shard.remove_if_empty(key);
+        Ok(Frame::Integer(removed as i64))
+    })
+}
+
+pub(super) fn apply_hgetall(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let entries = shard.hash(key)?.map_or_else(Vec::new, |hash| {
+            hash.iter()
+                .map(|(field, value)| (Frame::Bulk(field.clone()), Frame::Bulk(value.clone())))
+                .collect()
+        });
+        Ok(Frame::Map(entries))
+    })
+}
+
+/// Reply with the value of the field after the increment, a missing field counts as 0
+pub(super) fn apply_hincrby(
+    db: &impl Keyspace,
+    key: &str,
+    field: Bytes,
+    increment: i64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        // 先算出结果再写入，出错时不会留下一个空的哈希
+        let current = match shard.hash(key)?.and_then(|hash| hash.get(&field)) {
+            Some(value) => parse_int(value).ok_or("ERR hash value is not an integer")?,
+            None => 0,
+        };
+        let value = current
+            .checked_add(increment)
+            .ok_or("ERR increment or decrement would overflow")?;
+        shard
+            .hash_or_insert(key)?
+            .insert(field, Bytes::from(value.to_string()));
+        Ok(Frame::Integer(value))
+    })
+}
+
+/// Reply with the value of the field after the increment, a missing field counts as 0
+pub(super) fn apply_hincrbyfloat(
+    db: &impl Keyspace,
+    key: &str,
+    field: Bytes,
+    increment: f64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let current = match shard.hash(key)?.and_then(|hash| hash.get(&field)) {
+            Some(value) => parse_float(value).ok_or("ERR hash value is not a float")?,
+            None => 0.0,
+        };
+        let value = current + increment;
+        if !value.is_finite() {
+            return Err("ERR increment would produce NaN or Infinity".into());
+        }
+        let value = Bytes::from(format_float(value));
+        shard.hash_or_insert(key)?.insert(field, value.clone());
+        Ok(Frame::Bulk(value))
+    })
+}
+
+pub(super) fn apply_hexists(db: &impl Keyspace, key: &str, field: &[u8]) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let exists = shard
+            .hash(key)?
+            .is_some_and(|hash| hash.contains_key(field));
+        Ok(Frame::Integer(exists as i64))
+    })
+}
+
+pub(super) fn apply_hlen(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let len = shard.hash(key)?.map_or(0, |hash| hash.len());
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+/// Reply with the next cursor and the visited fields and values
+pub(super) fn apply_hscan(
+    db: &impl Keyspace,
+    key: &str,
+    scan: &Scan,
+    novalues: bool,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(hash) = shard.hash(key)? else {
+            return Ok(scan::reply(0, Vec::new()));
+        };
+        let (cursor, entries) = scan.step(hash.iter().map(|entry| (entry.0, entry)));
+        let elements = entries
+            .into_iter()
+            .flat_map(|(field, value)| {
+                let value = (!novalues).then(|| value.clone());
+                [field.clone()].into_iter().chain(value)
+            })
+            .collect();
+        Ok(scan::reply(cursor, elements))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::{
+        cmd::tests::{parse, run, scan_all, wrong_arity},
+        db::Db,
+    };
+
+    const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+    #[test]
+    fn hset() {
+        assert_eq!(
+            parse(&["hset", "h", "a", "1", "b", "2"]),
+            Ok(Command::Hset {
+                key: "h".to_string(),
+                fields: vec![("a".into(), "1".into()), ("b".into(), "2".into())]
+            })
+        );
+        assert_eq!(parse(&["hset", "h", "a", "1", "b"]), wrong_arity("hset"));
+        assert_eq!(parse(&["hset", "h", "a"]), wrong_arity("hset"));
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["hget", "h"]), wrong_arity("hget"));
+        assert_eq!(parse(&["hdel", "h"]), wrong_arity("hdel"));
+        assert_eq!(parse(&["hgetall", "h", "a"]), wrong_arity("hgetall"));
+        assert_eq!(parse(&["hincrby", "h", "a"]), wrong_arity("hincrby"));
+        assert_eq!(parse(&["hexists", "h"]), wrong_arity("hexists"));
+        assert_eq!(parse(&["hlen"]), wrong_arity("hlen"));
+        assert_eq!(parse(&["hscan", "h"]), wrong_arity("hscan"));
+    }
+
+    #[test]
+    fn increments() {
+        assert_eq!(
+            parse(&["hincrby", "h", "a", "1.5"]),
+            Err("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(
+            parse(&["hincrbyfloat", "h", "a", "one"]),
+            Err("ERR value is not a valid float".to_string())
+        );
+        assert_eq!(
+            parse(&["HINCRBYFLOAT", "h", "a", "-inf"]),
+            Ok(Command::Hincrbyfloat {
+                key: "h".to_string(),
+                field: "a".into(),
+                increment: f64::NEG_INFINITY
+            })
+        );
+    }
+
+    #[test]
+    fn hscan_options() {
+        let scan = Scan {
+            cursor: 0,
+            pattern: Some("f*".into()),
+            count: 10,
+        };
+        assert_eq!(
+            parse(&["hscan", "h", "0", "match", "f*", "NoValues"]),
+            Ok(Command::Hscan {
+                key: "h".to_string(),
+                scan: scan.clone(),
+                novalues: true
+            })
+        );
+        assert_eq!(
+            parse(&["hscan", "h", "0", "MATCH", "f*"]),
+            Ok(Command::Hscan {
+                key: "h".to_string(),
+                scan,
+                novalues: false
+            })
+        );
+        assert_eq!(
+            parse(&["hscan", "h", "0", "WITHVALUES"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn set_get_and_delete_fields() {
+        let db = Db::new();
+        assert_eq!(
+            run(&db, &["HSET", "h", "a", "1", "b", "2"]).await,
+            Frame::Integer(2)
+        );
+        // 已有的字段只是被更新
+        assert_eq!(
+            run(&db, &["HSET", "h", "a", "10", "c", "3"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&db, &["HGET", "h", "a"]).await,
+            Frame::Bulk("10".into())
+        );
+        assert_eq!(run(&db, &["HGET", "h", "x"]).await, Frame::Null);
+        assert_eq!(run(&db, &["HGET", "missing", "a"]).await, Frame::Null);
+        assert_eq!(run(&db, &["HLEN", "h"]).await, Frame::Integer(3));
+        assert_eq!(run(&db, &["HEXISTS", "h", "b"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["HEXISTS", "h", "x"]).await, Frame::Integer(0));
+
+        let Frame::Map(mut entries) = run(&db, &["HGETALL", "h"]).await else {
+            panic!("HGETALL did not reply with a map");
+        };
+        entries.sort_by_key(|(field, _)| format!("{:?}", field));
+        assert_eq!(
+            entries,
+            [("a", "10"), ("b", "2"), ("c", "3")]
+                .map(|(field, value)| (Frame::Bulk(field.into()), Frame::Bulk(value.into())))
+        );
+
+        assert_eq!(
+            run(&db, &["HDEL", "h", "a", "b", "x"]).await,
+            Frame::Integer(2)
+        );
+        // 删除最后一个字段时键也被删除
+        assert_eq!(run(&db, &["HDEL", "h", "c"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["EXISTS", "h"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["HGETALL", "h"]).await, Frame::Map(Vec::new()));
+        assert_eq!(run(&db, &["HLEN", "h"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn increment_fields() {
+        let db = Db::new();
+        assert_eq!(
+            run(&db, &["HINCRBY", "h", "n", "5"]).await,
+            Frame::Integer(5)
+        );
+        assert_eq!(
+            run(&db, &["HINCRBY", "h", "n", "-7"]).await,
+            Frame::Integer(-2)
+        );
+        assert_eq!(
+            run(&db, &["HINCRBYFLOAT", "h", "n", "0.5"]).await,
+            Frame::Bulk("-1.5".into())
+        );
+        assert_eq!(
+            run(&db, &["HINCRBY", "h", "n", "1"]).await,
+            Frame::Error("ERR hash value is not an integer".to_string())
+        );
+
+        run(&db, &["HSET", "h", "big", &i64::MAX.to_string()]).await;
+        assert_eq!(
+            run(&db, &["HINCRBY", "h", "big", "1"]).await,
+            Frame::Error("ERR increment or decrement would overflow".to_string())
+        );
+        run(&db, &["HSET", "h", "word", "x"]).await;
+        assert_eq!(
+            run(&db, &["HINCRBYFLOAT", "h", "word", "1"]).await,
+            Frame::Error("ERR hash value is not a float".to_string())
+        );
+        assert_eq!(
+            run(&db, &["HINCRBYFLOAT", "h", "f", "inf"]).await,
+            Frame::Error("ERR increment would produce NaN or Infinity".to_string())
+        );
+        // 出错时不会创建字段，也不会留下空的哈希
+        assert_eq!(run(&db, &["HEXISTS", "h", "f"]).await, Frame::Integer(0));
+        assert_eq!(
+            run(&db, &["HINCRBYFLOAT", "other", "n", "inf"]).await,
+            Frame::Error("ERR increment would produce NaN or Infinity".to_string())
+        );
+        assert_eq!(run(&db, &["EXISTS", "other"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn wrong_type() {
+        let db = Db::new();
+        run(&db, &["SET", "s", "v"]).await;
+        for args in [
+            &["HSET", "s", "a", "1"][..],
+            &["HGET", "s", "a"],
+            &["HDEL", "s", "a"],
+            &["HGETALL", "s"],
+            &["HINCRBY", "s", "a", "1"],
+            &["HLEN", "s"],
+            &["HSCAN", "s", "0"],
+        ] {
+            assert_eq!(run(&db, args).await, Frame::Error(WRONGTYPE.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn hscan_visits_every_field() {
+        let db = Db::new();
+        let mut args = vec!["HSET", "h"];
+        let fields: Vec<String> = (0..50).map(|i| format!("f{}", i)).collect();
+        for field in &fields {
+            args.extend([field.as_str(), "v"]);
+        }
+        run(&db, &args).await;
+
+        // 每次最多返回 COUNT 对字段和值
+        let Frame::Array(reply) = run(&db, &["HSCAN", "h", "0", "COUNT", "7"]).await else {
+            panic!("HSCAN did not reply with an array");
+        };
+        assert!(matches!(&reply[1], Frame::Array(entries) if entries.len() == 14));
+
+        let mut visited = scan_all(&db, &["HSCAN", "h"], &["COUNT", "7", "NOVALUES"]).await;
+        visited.sort();
+        let mut expected: Vec<Bytes> = fields.iter().map(|field| field.clone().into()).collect();
+        expected.sort();
+        assert_eq!(visited, expected);
+
+        let entries = scan_all(&db, &["HSCAN", "h"], &["COUNT", "3"]).await;
+        assert_eq!(entries.len(), 100);
+        assert!(entries
+            .chunks(2)
+            .all(|entry| entry[0].starts_with(b"f") && entry[1] == "v"));
+
+        let mut matched = scan_all(&db, &["HSCAN", "h"], &["MATCH", "f1?", "NOVALUES"]).await;
+        matched.sort();
+        let expected: Vec<Bytes> = (10..20).map(|i| format!("f{}", i).into()).collect();
+        assert_eq!(matched, expected);
+
+        assert_eq!(
+            run(&db, &["HSCAN", "missing", "0"]).await,
+            Frame::Array(vec![Frame::Bulk("0".into()), Frame::Array(Vec::new())])
+        );
+    }
+}
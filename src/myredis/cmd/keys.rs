@@ -0,0 +1,280 @@
+//! Keyspace commands.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use super::{
+    scan::{self, Scan},
+    Command, Parse, ParseError,
+};
+use crate::myredis::{
+    db::{ExpireCondition, Expiry, Keyspace},
+    frame::Frame,
+    glob, Error,
+};
+
+/// DEL key [key ...]
+pub(super) fn del(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Del {
+        keys: parse.rest_strings()?,
+    })
+}
+
+/// EXISTS key [key ...]
+pub(super) fn exists(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Exists {
+        keys: parse.rest_strings()?,
+    })
+}
+
+/// TYPE key
+pub(super) fn type_(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Type {
+        key: parse.next_string()?,
+    })
+}
+
+/// RENAME key newkey
+pub(super) fn rename(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Rename {
+        key: parse.next_string()?,
+        new_key: parse.next_string()?,
+    })
+}
+
+/// KEYS pattern
+pub(super) fn keys(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Keys {
+        pattern: parse.next_bytes()?,
+    })
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count]
+pub(super) fn scan(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Scan(Scan::parse(parse, |_, _| Ok(false))?))
+}
+
+/// DBSIZE
+pub(super) fn dbsize(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Dbsize)
+}
+
+/// FLUSHDB [ASYNC | SYNC]
+pub(super) fn flushdb(parse: &mut Parse) -> Result<Command, ParseError> {
+    // 删除总是同步完成的，两个选项的效果一样
+    if parse.remaining() > 0 && !matches!(&parse.next_flag()?[..], "ASYNC" | "SYNC") {
+        return Err(ParseError::Syntax);
+    }
+    Ok(Command::Flushdb)
+}
+
+/// EXPIRE key seconds [NX | XX | GT | LT]
+pub(super) fn expire(parse: &mut Parse) -> Result<Command, ParseError> {
+    expire_generic(parse, "expire", 1000, false)
+}
+
+/// PEXPIRE key milliseconds [NX | XX | GT | LT]
+pub(super) fn pexpire(parse: &mut Parse) -> Result<Command, ParseError> {
+    expire_generic(parse, "pexpire", 1, false)
+}
+
+/// EXPIREAT key unix-time-seconds [NX | XX | GT | LT]
+pub(super) fn expireat(parse: &mut Parse) -> Result<Command, ParseError> {
+    expire_generic(parse, "expireat", 1000, true)
+}
+
+/// PEXPIREAT key unix-time-milliseconds [NX | XX | GT | LT]
+pub(super) fn pexpireat(parse: &mut Parse) -> Result<Command, ParseError> {
+    expire_generic(parse, "pexpireat", 1, true)
+}
+
+/// TTL key
+pub(super) fn ttl(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Ttl {
+        key: parse.next_string()?,
+    })
+}
+
+/// PTTL key
+pub(super) fn pttl(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Pttl {
+        key: parse.next_string()?,
+    })
+}
+
+/// PERSIST key
+pub(super) fn persist(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Persist {
+        key: parse.next_string()?,
+    })
+}
+
+/// Parse the `EXPIRE` family, `unit` is the number of milliseconds of the time argument
+fn expire_generic(
+    parse: &mut Parse,
+    name: &str,
+    unit: i64,
+    absolute: bool,
+) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let millis = parse.next_int()?.checked_mul(unit).ok_or_else(|| {
+        ParseError::Invalid(format!("ERR invalid expire time in '{}' command", name))
+    })?;
+
+    // 和 redis 一样，过去的时间(包括非正数的相对时间)会删除这个键
+    let millis = Duration::from_millis(millis.max(0) as u64);
+    let expiry = if absolute {
+        Expiry::At(UNIX_EPOCH + millis)
+    } else {
+        Expiry::After(millis)
+    };
+
+    let condition = match parse.remaining() {
+        0 => None,
+        _ => Some(match &parse.next_flag()?[..] {
+            "NX" => ExpireCondition::NotExists,
+            "XX" => ExpireCondition::Exists,
+            "GT" => ExpireCondition::Greater,
+            "LT" => ExpireCondition::Less,
+            flag => {
+                return Err(ParseError::Invalid(format!(
+                    "ERR Unsupported option {}",
+                    flag
+                )))
+            }
+        }),
+    };
+
+    Ok(Command::Expire {
+        key,
+        expiry,
+        condition,
+    })
+}
+
+pub(super) fn apply_type(db: &impl Keyspace, key: &str) -> Frame {
+    let name = db.with_shard(key, |shard| {
+        shard.value(key).map_or("none", |value| value.type_name())
+    });
+    Frame::Simple(name.to_string())
+}
+
+pub(super) fn apply_rename(db: &impl Keyspace, key: &str, new_key: &str) -> Result<Frame, Error> {
+    if !db.rename(key, new_key) {
+        return Err("ERR no such key".into());
+    }
+    Ok(Frame::ok())
+}
+
+pub(super) fn apply_keys(db: &impl Keyspace, pattern: &[u8]) -> Frame {
+    let keys = db.keys(|key| glob::matches(pattern, key.as_bytes()));
+    Frame::bulks(keys)
+}
+
+/// Reply with the next cursor and the visited keys that match the pattern
+pub(super) fn apply_scan(db: &impl Keyspace, scan: &Scan) -> Frame {
+    let (cursor, keys) = db.scan(scan.cursor, scan.count);
+    let keys = keys
+        .into_iter()
+        .filter(|key| scan.matches(key.as_bytes()))
+        .map(Bytes::from)
+        .collect();
+    scan::reply(cursor, keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::cmd::tests::{parse, wrong_arity};
+
+    fn expire(expiry: Expiry, condition: Option<ExpireCondition>) -> Result<Command, String> {
+        Ok(Command::Expire {
+            key: "k".to_string(),
+            expiry,
+            condition,
+        })
+    }
+
+    #[test]
+    fn expire_family() {
+        let after = |millis| Expiry::After(Duration::from_millis(millis));
+        let at = |millis| Expiry::At(UNIX_EPOCH + Duration::from_millis(millis));
+        assert_eq!(parse(&["expire", "k", "10"]), expire(after(10_000), None));
+        assert_eq!(parse(&["pexpire", "k", "10"]), expire(after(10), None));
+        assert_eq!(parse(&["expireat", "k", "10"]), expire(at(10_000), None));
+        assert_eq!(parse(&["PEXPIREAT", "k", "10"]), expire(at(10), None));
+        // 非正数的时间会删除这个键
+        assert_eq!(parse(&["expire", "k", "-5"]), expire(after(0), None));
+    }
+
+    #[test]
+    fn expire_conditions() {
+        let after = Expiry::After(Duration::from_secs(1));
+        for (flag, condition) in [
+            ("nx", ExpireCondition::NotExists),
+            ("XX", ExpireCondition::Exists),
+            ("Gt", ExpireCondition::Greater),
+            ("lt", ExpireCondition::Less),
+        ] {
+            assert_eq!(
+                parse(&["expire", "k", "1", flag]),
+                expire(after, Some(condition))
+            );
+        }
+        assert_eq!(
+            parse(&["expire", "k", "1", "sooner"]),
+            Err("ERR Unsupported option SOONER".to_string())
+        );
+        // 只能给出一个条件
+        assert_eq!(
+            parse(&["expire", "k", "1", "NX", "XX"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn expire_time() {
+        assert_eq!(
+            parse(&["expire", "k", "soon"]),
+            Err("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(
+            parse(&["expire", "k", &i64::MAX.to_string()]),
+            Err("ERR invalid expire time in 'expire' command".to_string())
+        );
+        assert_eq!(
+            parse(&["expireat", "k", &(i64::MAX / 10).to_string()]),
+            Err("ERR invalid expire time in 'expireat' command".to_string())
+        );
+    }
+
+    #[test]
+    fn flushdb() {
+        assert_eq!(parse(&["flushdb"]), Ok(Command::Flushdb));
+        assert_eq!(parse(&["flushdb", "async"]), Ok(Command::Flushdb));
+        assert_eq!(parse(&["FLUSHDB", "Sync"]), Ok(Command::Flushdb));
+        assert_eq!(
+            parse(&["flushdb", "later"]),
+            Err("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            parse(&["flushdb", "sync", "async"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["del"]), wrong_arity("del"));
+        assert_eq!(parse(&["exists"]), wrong_arity("exists"));
+        assert_eq!(parse(&["type", "a", "b"]), wrong_arity("type"));
+        assert_eq!(parse(&["rename", "a"]), wrong_arity("rename"));
+        assert_eq!(parse(&["keys"]), wrong_arity("keys"));
+        assert_eq!(parse(&["scan"]), wrong_arity("scan"));
+        assert_eq!(parse(&["dbsize", "x"]), wrong_arity("dbsize"));
+        assert_eq!(parse(&["expire", "k"]), wrong_arity("expire"));
+        assert_eq!(parse(&["ttl"]), wrong_arity("ttl"));
+        assert_eq!(parse(&["persist", "a", "b"]), wrong_arity("persist"));
+    }
+}
@@ -0,0 +1,758 @@
+//! List commands.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::{Command, Parse, ParseError};
+use crate::myredis::{
+    db::{BlockedPop, Db, Keyspace, ListEnd, ListExt, WrongType},
+    frame::Frame,
+    Error,
+};
+
+/// LPUSH key element [element ...]
+pub(super) fn lpush(parse: &mut Parse) -> Result<Command, ParseError> {
+    push(parse, ListEnd::Left)
+}
+
+/// RPUSH key element [element ...]
+pub(super) fn rpush(parse: &mut Parse) -> Result<Command, ParseError> {
+    push(parse, ListEnd::Right)
+}
+
+/// LPOP key [count]
+pub(super) fn lpop(parse: &mut Parse) -> Result<Command, ParseError> {
+    pop(parse, ListEnd::Left)
+}
+
+/// RPOP key [count]
+pub(super) fn rpop(parse: &mut Parse) -> Result<Command, ParseError> {
+    pop(parse, ListEnd::Right)
+}
+
+/// LRANGE key start stop
+pub(super) fn lrange(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Lrange {
+        key: parse.next_string()?,
+        start: parse.next_int()?,
+        stop: parse.next_int()?,
+    })
+}
+
+/// LLEN key
+pub(super) fn llen(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Llen {
+        key: parse.next_string()?,
+    })
+}
+
+/// LINDEX key index
+pub(super) fn lindex(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Lindex {
+        key: parse.next_string()?,
+        index: parse.next_int()?,
+    })
+}
+
+/// LTRIM key start stop
+pub(super) fn ltrim(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Ltrim {
+        key: parse.next_string()?,
+        start: parse.next_int()?,
+        stop: parse.next_int()?,
+    })
+}
+
+/// BLPOP key [key ...] timeout
+pub(super) fn blpop(parse: &mut Parse) -> Result<Command, ParseError> {
+    blocking_pop(parse, ListEnd::Left)
+}
+
+/// BRPOP key [key ...] timeout
+pub(super) fn brpop(parse: &mut Parse) -> Result<Command, ParseError> {
+    blocking_pop(parse, ListEnd::Right)
+}
+
+/// BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout
+pub(super) fn blmove(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Blmove {
+        source: parse.next_string()?,
+        destination: parse.next_string()?,
+        from: list_end(parse)?,
+        to: list_end(parse)?,
+        timeout: timeout(parse)?,
+    })
+}
+
+fn push(parse: &mut Parse, end: ListEnd) -> Result<Command, ParseError> {
+    Ok(Command::Push {
+        key: parse.next_string()?,
+        elements: parse.rest_bytes()?,
+        end,
+    })
+}
+
+fn pop(parse: &mut Parse, end: ListEnd) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let count = match parse.remaining() {
+        0 => None,
+        _ => {
+            let count = usize::try_from(parse.next_int()?).map_err(|_| {
+                ParseError::Invalid("ERR value is out of range, must be positive".to_string())
+            })?;
+            Some(count)
+        }
+    };
+    Ok(Command::Pop { key, count, end })
+}
+
+fn blocking_pop(parse: &mut Parse, end: ListEnd) -> Result<Command, ParseError> {
+    let mut keys = Vec::new();
+    while parse.remaining() > 1 {
+        keys.push(parse.next_string()?);
+    }
+    Ok(Command::BlockingPop {
+        keys,
+        end,
+        timeout: timeout(parse)?,
+    })
+}
+
+fn list_end(parse: &mut Parse) -> Result<ListEnd, ParseError> {
+    match &parse.next_flag()?[..] {
+        "LEFT" => Ok(ListEnd::Left),
+        "RIGHT" => Ok(ListEnd::Right),
+        _ => Err(ParseError::Syntax),
+    }
+}
+
+/// The timeout of blocking commands in seconds, `None` for 0 which blocks forever
+fn timeout(parse: &mut Parse) -> Result<Option<Duration>, ParseError> {
+    let timeout = parse.next_float().map_err(|_| {
+        ParseError::Invalid("ERR timeout is not a float or out of range".to_string())
+    })?;
+    if timeout < 0.0 {
+        return Err(ParseError::Invalid("ERR timeout is negative".to_string()));
+    }
+    let timeout = Duration::try_from_secs_f64(timeout)
+        .map_err(|_| ParseError::Invalid("ERR timeout is out of range".to_string()))?;
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// Push `elements` one by one, replying with the length of the list
+pub(super) fn apply_push(
+    db: &impl Keyspace,
+    key: &str,
+    elements: Vec<Bytes>,
+    end: ListEnd,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let list = shard.list_or_insert(key)?;
+        for element in elements {
+            list.push(end, element);
+        }
+        let len = list.len();
+        // 回复的是推入之后、交给阻塞的客户端之前的长度，和 redis 一样
+        shard.serve_blocked(key);
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+/// Without `count` reply with the popped element, otherwise with an array of up to `count` elements
+pub(super) fn apply_pop(
+    db: &impl Keyspace,
+    key: &str,
+    count: Option<usize>,
+    end: ListEnd,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(list) = shard.list(key)? else {
+            // 和 redis 一样，带 `count` 时回复空数组而不是空字符串
+            return Ok(count.map_or(Frame::Null, |_| Frame::NullArray));
+        };
+
+        let mut popped = Vec::new();
+        for _ in 0..count.unwrap_or(1) {
+            match list.pop(end) {
+                Some(element) => popped.push(element),
+                None => break,
+            }
+        }
+        shard.remove_if_empty(key);
+
+        Ok(match count {
+            Some(_) => Frame::bulks(popped),
+            None => popped.pop().into(),
+        })
+    })
+}
+
+/// Reply with the key and the popped element, or a null array once the timeout elapses
+pub(super) async fn apply_blocking_pop(
+    db: &Db,
+    keys: Vec<String>,
+    end: ListEnd,
+    timeout: Option<Duration>,
+) -> Result<Frame, Error> {
+    let blocked = db.blocking_pop(keys, end)?;
+    Ok(match wait(db, blocked, timeout).await? {
+        Some((key, element)) => Frame::bulks([Bytes::from(key), element]),
+        None => Frame::NullArray,
+    })
+}
+
+/// Move an element from `source` to `destination`, waiting for one if `source` is empty
+pub(super) async fn apply_blmove(
+    db: &Db,
+    source: String,
+    destination: String,
+    ends: (ListEnd, ListEnd),
+    timeout: Option<Duration>,
+) -> Result<Frame, Error> {
+    // 和 redis 一样，目标键的类型不对时不会阻塞
+    let blocked = db.blocking_move(source, destination, ends)?;
+    Ok(match wait(db, blocked, timeout).await? {
+        Some((_, element)) => Frame::Bulk(element),
+        None => Frame::Null,
+    })
+}
+
+/// Wait until the blocked client is served, `None` once the timeout elapses
+async fn wait(
+    db: &Db,
+    blocked: BlockedPop,
+    timeout: Option<Duration>,
+) -> Result<Option<(String, Bytes)>, WrongType> {
+    let Some(timeout) = timeout else {
+        return blocked.await.map(Some);
+    };
+    let mut timeout = db.clock().timeout(timeout, blocked);
+    match (&mut timeout).await {
+        Ok(served) => served.map(Some),
+        // 超时的同时可能已经拿到了元素，这时回复这个元素，不再放回列表
+        Err(_) => timeout.into_inner().cancel().transpose(),
+    }
+}
+
+/// BLPOP and BRPOP in a transaction: pop from the first non-empty list of `keys` without waiting
+pub(super) fn apply_pop_first(
+    db: &impl Keyspace,
+    keys: &[String],
+    end: ListEnd,
+) -> Result<Frame, Error> {
+    for key in keys {
+        let popped = db.with_shard(key, |shard| {
+            let Some(list) = shard.list(key)? else {
+                return Ok::<_, Error>(None);
+            };
+            let element = list.pop(end);
+            shard.remove_if_empty(key);
+            Ok(element)
+        })?;
+        if let Some(element) = popped {
+            return Ok(Frame::bulks([Bytes::from(key.clone()), element]));
+        }
+    }
+    Ok(Frame::NullArray)
+}
+
+/// BLMOVE in a transaction: move an element from `source` to `destination` without waiting
+pub(super) fn apply_lmove(
+    db: &impl Keyspace,
+    source: &str,
+    destination: &str,
+    (from, to): (ListEnd, ListEnd),
+) -> Result<Frame, Error> {
+    db.with_keys(&[source, destination], |shards| {
+        shards.shard(destination).list(destination)?;
+        let shard = shards.shard(source);
+        let Some(element) = shard.list(source)?.and_then(|list| list.pop(from)) else {
+            return Ok(Frame::Null);
+        };
+        shard.remove_if_empty(source);
+
+        let shard = shards.shard(destination);
+        shard.list_or_insert(destination)?.push(to, element.clone());
+        shard.serve_blocked(destination);
+        Ok(Frame::Bulk(element))
+    })
+}
+
+pub(super) fn apply_lrange(
+    db: &impl Keyspace,
+    key: &str,
+    start: i64,
+    stop: i64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let elements = match shard.list(key)? {
+            Some(list) => match range(list.len(), start, stop) {
+                Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+        Ok(Frame::bulks(elements))
+    })
+}
+
+pub(super) fn apply_llen(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let len = shard.list(key)?.map_or(0, |list| list.len());
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+pub(super) fn apply_lindex(db: &impl Keyspace, key: &str, index: i64) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let element = shard.list(key)?.and_then(|list| {
+            // 负数下标从列表尾部开始计数
+            let index = if index < 0 {
+                index + list.len() as i64
+            } else {
+                index
+            };
+            usize::try_from(index)
+                .ok()
+                .and_then(|index| list.get(index).cloned())
+        });
+        Ok(element.into())
+    })
+}
+
+/// Keep only the elements in the range, deleting the key if none is left
+pub(super) fn apply_ltrim(
+    db: &impl Keyspace,
+    key: &str,
+    start: i64,
+    stop: i64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        if let Some(list) = shard.list(key)? {
+            match range(list.len(), start, stop) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            shard.remove_if_empty(key);
+        }
+        Ok(Frame::ok())
+    })
+}
+
+/// Turn the inclusive range of LRANGE, LTRIM and ZRANGE into indices of a list of length `len`, negative
+/// indices count from the end. `None` if the range is empty.
+pub(super) fn range(len: usize, start: i64, stop: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tokio::task::{self, JoinHandle};
+
+    use super::*;
+    use crate::{
+        myredis::cmd::tests::{parse, run, wrong_arity},
+        timer_future::Clock,
+    };
+
+    const WRONGTYPE: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+
+    #[tokio::test]
+    async fn push_and_pop() {
+        let db = Db::new();
+        assert_eq!(run(&db, &["RPUSH", "l", "b", "c"]).await, Frame::Integer(2));
+        // LPUSH 逐个推入，最后一个元素在最前面
+        assert_eq!(run(&db, &["LPUSH", "l", "a", "z"]).await, Frame::Integer(4));
+        assert_eq!(
+            run(&db, &["LRANGE", "l", "0", "-1"]).await,
+            Frame::bulks(["z", "a", "b", "c"])
+        );
+        assert_eq!(run(&db, &["LPOP", "l"]).await, Frame::Bulk("z".into()));
+        assert_eq!(run(&db, &["RPOP", "l"]).await, Frame::Bulk("c".into()));
+        assert_eq!(run(&db, &["LLEN", "l"]).await, Frame::Integer(2));
+
+        assert_eq!(
+            run(&db, &["RPOP", "l", "0"]).await,
+            Frame::bulks(Vec::<Bytes>::new())
+        );
+        assert_eq!(
+            run(&db, &["RPOP", "l", "5"]).await,
+            Frame::bulks(["b", "a"])
+        );
+        // 最后一个元素被弹出后键被删除
+        assert_eq!(run(&db, &["EXISTS", "l"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["LPOP", "l"]).await, Frame::Null);
+        assert_eq!(run(&db, &["LPOP", "l", "2"]).await, Frame::NullArray);
+        assert_eq!(run(&db, &["LLEN", "l"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn lrange_and_lindex() {
+        let db = Db::new();
+        run(&db, &["RPUSH", "l", "a", "b", "c", "d", "e"]).await;
+        let lrange = |start: &'static str, stop: &'static str| {
+            let db = db.clone();
+            async move { run(&db, &["LRANGE", "l", start, stop]).await }
+        };
+        assert_eq!(lrange("1", "2").await, Frame::bulks(["b", "c"]));
+        assert_eq!(lrange("-2", "-1").await, Frame::bulks(["d", "e"]));
+        assert_eq!(lrange("-100", "1").await, Frame::bulks(["a", "b"]));
+        assert_eq!(lrange("3", "100").await, Frame::bulks(["d", "e"]));
+        assert_eq!(lrange("3", "1").await, Frame::bulks(Vec::<Bytes>::new()));
+        assert_eq!(lrange("5", "10").await, Frame::bulks(Vec::<Bytes>::new()));
+        assert_eq!(lrange("0", "-10").await, Frame::bulks(Vec::<Bytes>::new()));
+        assert_eq!(
+            run(&db, &["LRANGE", "missing", "0", "-1"]).await,
+            Frame::bulks(Vec::<Bytes>::new())
+        );
+
+        assert_eq!(
+            run(&db, &["LINDEX", "l", "0"]).await,
+            Frame::Bulk("a".into())
+        );
+        assert_eq!(
+            run(&db, &["LINDEX", "l", "-1"]).await,
+            Frame::Bulk("e".into())
+        );
+        assert_eq!(run(&db, &["LINDEX", "l", "5"]).await, Frame::Null);
+        assert_eq!(run(&db, &["LINDEX", "l", "-6"]).await, Frame::Null);
+    }
+
+    #[tokio::test]
+    async fn ltrim() {
+        let db = Db::new();
+        run(&db, &["RPUSH", "l", "a", "b", "c", "d", "e"]).await;
+        assert_eq!(run(&db, &["LTRIM", "l", "1", "-2"]).await, Frame::ok());
+        assert_eq!(
+            run(&db, &["LRANGE", "l", "0", "-1"]).await,
+            Frame::bulks(["b", "c", "d"])
+        );
+        assert_eq!(run(&db, &["LTRIM", "l", "-100", "100"]).await, Frame::ok());
+        assert_eq!(run(&db, &["LLEN", "l"]).await, Frame::Integer(3));
+        // 空的范围删除这个键
+        assert_eq!(run(&db, &["LTRIM", "l", "2", "1"]).await, Frame::ok());
+        assert_eq!(run(&db, &["EXISTS", "l"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["LTRIM", "missing", "0", "1"]).await, Frame::ok());
+    }
+
+    #[tokio::test]
+    async fn wrong_type() {
+        let db = Db::new();
+        run(&db, &["SET", "s", "v"]).await;
+        for args in [
+            &["LPUSH", "s", "a"][..],
+            &["RPOP", "s"],
+            &["LRANGE", "s", "0", "-1"],
+            &["LLEN", "s"],
+            &["LINDEX", "s", "0"],
+            &["LTRIM", "s", "0", "1"],
+        ] {
+            assert_eq!(run(&db, args).await, Frame::Error(WRONGTYPE.to_string()));
+        }
+        assert_eq!(run(&db, &["GET", "s"]).await, Frame::Bulk("v".into()));
+    }
+
+    /// Run a command in another task, once it had the time to block
+    async fn spawn(db: &Db, args: &'static [&'static str]) -> JoinHandle<Frame> {
+        let db = db.clone();
+        let handle = tokio::spawn(async move { run(&db, args).await });
+        settle().await;
+        handle
+    }
+
+    /// Let the other tasks run until they block
+    async fn settle() {
+        for _ in 0..10 {
+            task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn blocked_clients_are_served_in_order() {
+        let db = Db::new();
+        let first = spawn(&db, &["BLPOP", "l", "0"]).await;
+        let second = spawn(&db, &["BRPOP", "other", "l", "0"]).await;
+        let third = spawn(&db, &["BLPOP", "l", "0"]).await;
+
+        run(&db, &["RPUSH", "l", "a"]).await;
+        settle().await;
+        assert!(first.is_finished());
+        assert!(!second.is_finished() && !third.is_finished());
+        assert_eq!(first.await.unwrap(), Frame::bulks(["l", "a"]));
+
+        run(&db, &["RPUSH", "l", "b"]).await;
+        assert_eq!(second.await.unwrap(), Frame::bulks(["l", "b"]));
+        run(&db, &["RPUSH", "l", "c"]).await;
+        assert_eq!(third.await.unwrap(), Frame::bulks(["l", "c"]));
+        assert_eq!(run(&db, &["EXISTS", "l"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn blmove_keeps_its_place_in_the_queue() {
+        let db = Db::new();
+        let first = spawn(&db, &["BLPOP", "l", "0"]).await;
+        let mover = spawn(&db, &["BLMOVE", "l", "d", "LEFT", "RIGHT", "0"]).await;
+        let last = spawn(&db, &["BLPOP", "l", "0"]).await;
+
+        // 一次推入三个元素，依次交给三个客户端
+        assert_eq!(
+            run(&db, &["RPUSH", "l", "a", "b", "c", "d"]).await,
+            Frame::Integer(4)
+        );
+        assert_eq!(first.await.unwrap(), Frame::bulks(["l", "a"]));
+        assert_eq!(mover.await.unwrap(), Frame::Bulk("b".into()));
+        assert_eq!(last.await.unwrap(), Frame::bulks(["l", "c"]));
+        assert_eq!(
+            run(&db, &["LRANGE", "l", "0", "-1"]).await,
+            Frame::bulks(["d"])
+        );
+        assert_eq!(
+            run(&db, &["LRANGE", "d", "0", "-1"]).await,
+            Frame::bulks(["b"])
+        );
+    }
+
+    #[tokio::test]
+    async fn blmove_serves_the_clients_blocked_on_the_destination() {
+        let db = Db::new();
+        let mover = spawn(&db, &["BLMOVE", "a", "b", "RIGHT", "LEFT", "0"]).await;
+        let popper = spawn(&db, &["BRPOP", "b", "0"]).await;
+
+        run(&db, &["LPUSH", "a", "x"]).await;
+        assert_eq!(mover.await.unwrap(), Frame::Bulk("x".into()));
+        assert_eq!(popper.await.unwrap(), Frame::bulks(["b", "x"]));
+        assert_eq!(run(&db, &["DBSIZE"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn blmove_to_a_key_of_another_type() {
+        let db = Db::new();
+        run(&db, &["SET", "s", "v"]).await;
+        // 目标键的类型不对时不会阻塞
+        assert_eq!(
+            run(&db, &["BLMOVE", "l", "s", "LEFT", "LEFT", "0"]).await,
+            Frame::Error(WRONGTYPE.to_string())
+        );
+
+        let mover = spawn(&db, &["BLMOVE", "l", "d", "LEFT", "LEFT", "0"]).await;
+        run(&db, &["SET", "d", "v"]).await;
+        run(&db, &["RPUSH", "l", "x"]).await;
+        assert_eq!(mover.await.unwrap(), Frame::Error(WRONGTYPE.to_string()));
+        // 元素留在原来的列表里
+        assert_eq!(run(&db, &["LLEN", "l"]).await, Frame::Integer(1));
+    }
+
+    #[tokio::test]
+    async fn blocking_commands_time_out() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        let pop = spawn(&db, &["BLPOP", "l", "1"]).await;
+        let mover = spawn(&db, &["BLMOVE", "l", "d", "LEFT", "LEFT", "0.5"]).await;
+
+        clock.advance(Duration::from_millis(499));
+        settle().await;
+        assert!(!mover.is_finished());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(mover.await.unwrap(), Frame::Null);
+
+        clock.advance(Duration::from_millis(499));
+        settle().await;
+        assert!(!pop.is_finished());
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(pop.await.unwrap(), Frame::NullArray);
+
+        // 超时的客户端不再等待，推入的元素留在列表里
+        assert_eq!(run(&db, &["RPUSH", "l", "x"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["LLEN", "l"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["EXISTS", "d"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn abandoned_clients_are_skipped() {
+        let db = Db::new();
+        let gone = spawn(&db, &["BLPOP", "l", "0"]).await;
+        let mover_gone = spawn(&db, &["BLMOVE", "l", "d", "LEFT", "LEFT", "0"]).await;
+        let waiting = spawn(&db, &["BLPOP", "l", "0"]).await;
+        gone.abort();
+        mover_gone.abort();
+        settle().await;
+
+        run(&db, &["RPUSH", "l", "x"]).await;
+        assert_eq!(waiting.await.unwrap(), Frame::bulks(["l", "x"]));
+        assert_eq!(run(&db, &["DBSIZE"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_moves_lose_no_element() {
+        const CLIENTS: usize = 200;
+        let db = Db::with_shards(4, Clock::system());
+        let mut clients = Vec::new();
+        for i in 0..CLIENTS {
+            let db = db.clone();
+            clients.push(tokio::spawn(async move {
+                // 一半的客户端从 a 移到 b，另一半从 b 弹出
+                let args: &[&str] = match i % 2 {
+                    0 => &["BLMOVE", "a", "b", "LEFT", "RIGHT", "0"],
+                    _ => &["BLPOP", "b", "0"],
+                };
+                run(&db, args).await
+            }));
+        }
+        let pushers: Vec<_> = (0..CLIENTS / 2)
+            .map(|i| {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    let element = i.to_string();
+                    run(&db, &["RPUSH", "a", &element]).await
+                })
+            })
+            .collect();
+        for pusher in pushers {
+            pusher.await.unwrap();
+        }
+
+        let mut moved = HashSet::new();
+        let mut popped = HashSet::new();
+        for client in clients {
+            match client.await.unwrap() {
+                Frame::Bulk(element) => assert!(moved.insert(element)),
+                Frame::Array(reply) => match &reply[..] {
+                    [key, Frame::Bulk(element)] if *key == Frame::Bulk("b".into()) => {
+                        assert!(popped.insert(element.clone()))
+                    }
+                    _ => panic!("unexpected reply {:?}", reply),
+                },
+                frame => panic!("unexpected reply {:?}", frame),
+            }
+        }
+        // 每个推入的元素都被移动了一次，然后被弹出了一次
+        assert_eq!(moved.len(), CLIENTS / 2);
+        assert_eq!(popped.len(), CLIENTS / 2);
+        assert_eq!(run(&db, &["DBSIZE"]).await, Frame::Integer(0));
+    }
+
+    #[test]
+    fn pop_count() {
+        assert_eq!(
+            parse(&["lpop", "l", "2"]),
+            Ok(Command::Pop {
+                key: "l".to_string(),
+                count: Some(2),
+                end: ListEnd::Left
+            })
+        );
+        assert_eq!(
+            parse(&["rpop", "l", "-1"]),
+            Err("ERR value is out of range, must be positive".to_string())
+        );
+        assert_eq!(
+            parse(&["rpop", "l", "two"]),
+            Err("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(
+            parse(&["rpop", "l", "1", "2"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn blocking_pop() {
+        assert_eq!(
+            parse(&["blpop", "a", "b", "0.5"]),
+            Ok(Command::BlockingPop {
+                keys: vec!["a".to_string(), "b".to_string()],
+                end: ListEnd::Left,
+                timeout: Some(Duration::from_millis(500))
+            })
+        );
+        // 超时为 0 表示一直阻塞
+        assert_eq!(
+            parse(&["BRPOP", "a", "0"]),
+            Ok(Command::BlockingPop {
+                keys: vec!["a".to_string()],
+                end: ListEnd::Right,
+                timeout: None
+            })
+        );
+        assert_eq!(
+            parse(&["blpop", "a", "soon"]),
+            Err("ERR timeout is not a float or out of range".to_string())
+        );
+        assert_eq!(
+            parse(&["blpop", "a", "-1"]),
+            Err("ERR timeout is negative".to_string())
+        );
+        assert_eq!(
+            parse(&["blpop", "a", "inf"]),
+            Err("ERR timeout is out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn blmove() {
+        assert_eq!(
+            parse(&["blmove", "a", "b", "left", "Right", "1"]),
+            Ok(Command::Blmove {
+                source: "a".to_string(),
+                destination: "b".to_string(),
+                from: ListEnd::Left,
+                to: ListEnd::Right,
+                timeout: Some(Duration::from_secs(1))
+            })
+        );
+        assert_eq!(
+            parse(&["blmove", "a", "b", "up", "RIGHT", "1"]),
+            Err("ERR syntax error".to_string())
+        );
+        assert_eq!(
+            parse(&["blmove", "a", "b", "LEFT", "RIGHT"]),
+            wrong_arity("blmove")
+        );
+    }
+
+    #[test]
+    fn indexes() {
+        let not_integer = Err("ERR value is not an integer or out of range".to_string());
+        assert_eq!(parse(&["lrange", "l", "0", "end"]), not_integer);
+        assert_eq!(parse(&["ltrim", "l", "first", "1"]), not_integer);
+        assert_eq!(parse(&["lindex", "l", "1.0"]), not_integer);
+        assert_eq!(
+            parse(&["lrange", "l", "-3", "-1"]),
+            Ok(Command::Lrange {
+                key: "l".to_string(),
+                start: -3,
+                stop: -1
+            })
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["lpush", "l"]), wrong_arity("lpush"));
+        assert_eq!(parse(&["rpush", "l"]), wrong_arity("rpush"));
+        assert_eq!(parse(&["lpop"]), wrong_arity("lpop"));
+        assert_eq!(parse(&["blpop", "l"]), wrong_arity("blpop"));
+        assert_eq!(parse(&["brpop", "l"]), wrong_arity("brpop"));
+        assert_eq!(parse(&["lrange", "l", "0"]), wrong_arity("lrange"));
+        assert_eq!(parse(&["llen"]), wrong_arity("llen"));
+        assert_eq!(parse(&["lindex", "l"]), wrong_arity("lindex"));
+        assert_eq!(parse(&["ltrim", "l", "0"]), wrong_arity("ltrim"));
+    }
+}
@@ -0,0 +1,778 @@
+//! Commands.
+//! 把客户端发来的数组帧解析成 `Command`，解析时会检查参数个数、整数参数和选项，
+//! 命令名和选项都不区分大小写。
+
+mod connection;
+mod hash;
+mod keys;
+mod list;
+mod pubsub;
+mod scan;
+mod server;
+mod set;
+mod string;
+mod transaction;
+mod zset;
+
+pub use super::db::ListEnd;
+pub use scan::Scan;
+pub use set::SetOp;
+pub use string::{Set, SetCondition};
+pub use zset::{Zadd, Zrange, ZrangeBy};
+
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use super::{
+    db::{Db, ExpireCondition, Expiry, KeyTtl, Keyspace, Locked, Touching},
+    frame::{Frame, Protocol},
+    parse::{Parse, ParseError},
+    Error,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Ping {
+        message: Option<Bytes>,
+    },
+    /// `None` keeps the protocol of the connection
+    Hello {
+        protocol: Option<Protocol>,
+    },
+    Get {
+        key: String,
+    },
+    /// SET and GETSET
+    Set(Set),
+    Mget {
+        keys: Vec<String>,
+    },
+    Mset {
+        pairs: Vec<(String, Bytes)>,
+    },
+    /// INCR, DECR, INCRBY and DECRBY
+    Incrby {
+        key: String,
+        increment: i64,
+    },
+    Incrbyfloat {
+        key: String,
+        increment: f64,
+    },
+    Append {
+        key: String,
+        value: Bytes,
+    },
+    Getrange {
+        key: String,
+        start: i64,
+        end: i64,
+    },
+    Setrange {
+        key: String,
+        offset: usize,
+        value: Bytes,
+    },
+    Strlen {
+        key: String,
+    },
+    Del {
+        keys: Vec<String>,
+    },
+    Exists {
+        keys: Vec<String>,
+    },
+    Type {
+        key: String,
+    },
+    Rename {
+        key: String,
+        new_key: String,
+    },
+    Keys {
+        pattern: Bytes,
+    },
+    Scan(Scan),
+    Dbsize,
+    Flushdb,
+    Expire {
+        key: String,
+        expiry: Expiry,
+        condition: Option<ExpireCondition>,
+    },
+    Ttl {
+        key: String,
+    },
+    Pttl {
+        key: String,
+    },
+    Persist {
+        key: String,
+    },
+    Push {
+        key: String,
+        elements: Vec<Bytes>,
+        end: ListEnd,
+    },
+    Pop {
+        key: String,
+        /// Reply with an array of up to `count` elements instead of a single element
+        count: Option<usize>,
+        end: ListEnd,
+    },
+    BlockingPop {
+        keys: Vec<String>,
+        end: ListEnd,
+        /// `None` blocks forever
+        timeout: Option<Duration>,
+    },
+    Blmove {
+        source: String,
+        destination: String,
+        from: ListEnd,
+        to: ListEnd,
+        timeout: Option<Duration>,
+    },
+    Lrange {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Llen {
+        key: String,
+    },
+    Lindex {
+        key: String,
+        index: i64,
+    },
+    Ltrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    Hset {
+        key: String,
+        fields: Vec<(Bytes, Bytes)>,
+    },
+    Hget {
+        key: String,
+        field: Bytes,
+    },
+    Hdel {
+        key: String,
+        fields: Vec<Bytes>,
+    },
+    Hgetall {
+        key: String,
+    },
+    Hincrby {
+        key: String,
+        field: Bytes,
+        increment: i64,
+    },
+    Hincrbyfloat {
+        key: String,
+        field: Bytes,
+        increment: f64,
+    },
+    Hexists {
+        key: String,
+        field: Bytes,
+    },
+    Hlen {
+        key: String,
+    },
+    Hscan {
+        key: String,
+        scan: Scan,
+        /// Only reply with the fields
+        novalues: bool,
+    },
+    Sadd {
+        key: String,
+        members: Vec<Bytes>,
+    },
+    Srem {
+        key: String,
+        members: Vec<Bytes>,
+    },
+    Smembers {
+        key: String,
+    },
+    Sismember {
+        key: String,
+        member: Bytes,
+    },
+    Scard {
+        key: String,
+    },
+    /// SINTER, SUNION and SDIFF
+    Combine {
+        keys: Vec<String>,
+        op: SetOp,
+    },
+    /// ZADD and ZINCRBY
+    Zadd(Zadd),
+    Zrange(Zrange),
+    Zrank {
+        key: String,
+        member: Bytes,
+        /// Reply with the score too
+        with_score: bool,
+    },
+    Zrem {
+        key: String,
+        members: Vec<Bytes>,
+    },
+    Zcard {
+        key: String,
+    },
+    Publish {
+        channel: String,
+        message: Bytes,
+    },
+    Subscribe {
+        channels: Vec<String>,
+    },
+    Unsubscribe {
+        channels: Vec<String>,
+    },
+    Psubscribe {
+        patterns: Vec<String>,
+    },
+    Punsubscribe {
+        patterns: Vec<String>,
+    },
+    Save,
+    Bgsave,
+    Bgrewriteaof,
+    Multi,
+    Exec,
+    Discard,
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
+}
+
+/// Parse the arguments that follow the command name
+type Parser = fn(&mut Parse) -> Result<Command, ParseError>;
+
+/// The command table: name, arity and parser of each command.
+///
+/// The arity follows the redis convention and counts the command name: a positive arity is
+/// the exact number of arguments, a negative arity is the minimum number of arguments.
+const COMMANDS: &[(&str, i32, Parser)] = &[
+    ("ping", -1, ping),
+    ("hello", -1, connection::hello),
+    ("get", 2, string::get),
+    ("set", -3, string::set),
+    ("getset", 3, string::getset),
+    ("mget", -2, string::mget),
+    ("mset", -3, string::mset),
+    ("incr", 2, string::incr),
+    ("decr", 2, string::decr),
+    ("incrby", 3, string::incrby),
+    ("decrby", 3, string::decrby),
+    ("incrbyfloat", 3, string::incrbyfloat),
+    ("append", 3, string::append),
+    ("getrange", 4, string::getrange),
+    ("setrange", 4, string::setrange),
+    ("strlen", 2, string::strlen),
+    ("del", -2, keys::del),
+    ("exists", -2, keys::exists),
+    ("type", 2, keys::type_),
+    ("rename", 3, keys::rename),
+    ("keys", 2, keys::keys),
+    ("scan", -2, keys::scan),
+    ("dbsize", 1, keys::dbsize),
+    ("flushdb", -1, keys::flushdb),
+    ("expire", -3, keys::expire),
+    ("pexpire", -3, keys::pexpire),
+    ("expireat", -3, keys::expireat),
+    ("pexpireat", -3, keys::pexpireat),
+    ("ttl", 2, keys::ttl),
+    ("pttl", 2, keys::pttl),
+    ("persist", 2, keys::persist),
+    ("lpush", -3, list::lpush),
+    ("rpush", -3, list::rpush),
+    ("lpop", -2, list::lpop),
+    ("rpop", -2, list::rpop),
+    ("blpop", -3, list::blpop),
+    ("brpop", -3, list::brpop),
+    ("blmove", 6, list::blmove),
+    ("lrange", 4, list::lrange),
+    ("llen", 2, list::llen),
+    ("lindex", 3, list::lindex),
+    ("ltrim", 4, list::ltrim),
+    ("hset", -4, hash::hset),
+    ("hget", 3, hash::hget),
+    ("hdel", -3, hash::hdel),
+    ("hgetall", 2, hash::hgetall),
+    ("hincrby", 4, hash::hincrby),
+    ("hincrbyfloat", 4, hash::hincrbyfloat),
+    ("hexists", 3, hash::hexists),
+    ("hlen", 2, hash::hlen),
+    ("hscan", -3, hash::hscan),
+    ("sadd", -3, set::sadd),
+    ("srem", -3, set::srem),
+    ("smembers", 2, set::smembers),
+    ("sismember", 3, set::sismember),
+    ("scard", 2, set::scard),
+    ("sinter", -2, set::sinter),
+    ("sunion", -2, set::sunion),
+    ("sdiff", -2, set::sdiff),
+    ("zadd", -4, zset::zadd),
+    ("zincrby", 4, zset::zincrby),
+    ("zrange", -4, zset::zrange),
+    ("zrank", -3, zset::zrank),
+    ("zrem", -3, zset::zrem),
+    ("zcard", 2, zset::zcard),
+    ("publish", 3, pubsub::publish),
+    ("subscribe", -2, pubsub::subscribe),
+    ("unsubscribe", -1, pubsub::unsubscribe),
+    ("psubscribe", -2, pubsub::psubscribe),
+    ("punsubscribe", -1, pubsub::punsubscribe),
+    ("save", 1, server::save),
+    ("bgsave", 1, server::bgsave),
+    ("bgrewriteaof", 1, server::bgrewriteaof),
+    ("multi", 1, transaction::multi),
+    ("exec", 1, transaction::exec),
+    ("discard", 1, transaction::discard),
+    ("watch", -2, transaction::watch),
+    ("unwatch", 1, transaction::unwatch),
+];
+
+impl Command {
+    /// Parse a command from a received frame, which must be an array of strings
+    pub fn from_frame(frame: Frame) -> Result<Command, ParseError> {
+        let mut parse = Parse::new(frame)?;
+        if parse.remaining() == 0 {
+            return Err(ParseError::Protocol("empty command".to_string()));
+        }
+        let name = parse.next_string()?.to_lowercase();
+
+        let Some(&(_, arity, parser)) = COMMANDS.iter().find(|(n, _, _)| *n == name) else {
+            // 和 redis 一样，错误信息里带上前几个参数，方便排查
+            let args = parse.rest_strings().unwrap_or_default();
+            return Err(ParseError::UnknownCommand(
+                name,
+                args.into_iter().take(3).collect(),
+            ));
+        };
+
+        let argc = parse.remaining() as i32 + 1;
+        if (arity > 0 && argc != arity) || (arity < 0 && argc < -arity) {
+            return Err(ParseError::WrongArity(name));
+        }
+
+        let command = parser(&mut parse)?;
+        // 所有参数都应该被解析器消费掉，多出来的参数是无法识别的选项
+        parse.finish()?;
+        Ok(command)
+    }
+
+    /// Execute a command against `db` and return the response, errors are returned as error frames.
+    ///
+    /// Pub/sub, transaction commands and HELLO depend on the state of the connection and
+    /// persistence commands on the configuration of the server, they are handled by the server.
+    /// Blocking commands only complete once they are served or time out.
+    pub async fn apply(self, db: &Db) -> Frame {
+        self.execute(db)
+            .await
+            .unwrap_or_else(|err| Frame::Error(err.to_string()))
+    }
+
+    /// Execute a command of a transaction against the shards it locked. Like in redis, blocking
+    /// commands do not wait and reply as if they timed out when there is nothing to pop.
+    pub fn apply_locked(self, locked: &Locked<'_>) -> Frame {
+        let res = match self {
+            Command::BlockingPop { keys, end, .. } => {
+                list::apply_pop_first(&Touching(locked), &keys, end)
+            }
+            Command::Blmove {
+                source,
+                destination,
+                from,
+                to,
+                ..
+            } => list::apply_lmove(&Touching(locked), &source, &destination, (from, to)),
+            cmd => cmd.execute_now(locked),
+        };
+        res.unwrap_or_else(|err| Frame::Error(err.to_string()))
+    }
+
+    /// Whether the command may wait for other clients before replying
+    pub fn is_blocking(&self) -> bool {
+        matches!(self, Command::BlockingPop { .. } | Command::Blmove { .. })
+    }
+
+    /// Whether the command may modify the database, such commands are logged by the append-only file
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            Command::Set(_)
+                | Command::Mset { .. }
+                | Command::Incrby { .. }
+                | Command::Incrbyfloat { .. }
+                | Command::Append { .. }
+                | Command::Setrange { .. }
+                | Command::Del { .. }
+                | Command::Rename { .. }
+                | Command::Flushdb
+                | Command::Expire { .. }
+                | Command::Persist { .. }
+                | Command::Push { .. }
+                | Command::Pop { .. }
+                | Command::BlockingPop { .. }
+                | Command::Blmove { .. }
+                | Command::Ltrim { .. }
+                | Command::Hset { .. }
+                | Command::Hdel { .. }
+                | Command::Hincrby { .. }
+                | Command::Hincrbyfloat { .. }
+                | Command::Sadd { .. }
+                | Command::Srem { .. }
+                | Command::Zadd(_)
+                | Command::Zrem { .. }
+        )
+    }
+
+    /// The keys the command reads or writes, `None` for the commands that visit every key
+    pub fn keys(&self) -> Option<Vec<&str>> {
+        let keys = match self {
+            Command::Keys { .. } | Command::Scan(_) | Command::Dbsize | Command::Flushdb => {
+                return None
+            }
+            Command::Get { key }
+            | Command::Incrby { key, .. }
+            | Command::Incrbyfloat { key, .. }
+            | Command::Append { key, .. }
+            | Command::Getrange { key, .. }
+            | Command::Setrange { key, .. }
+            | Command::Strlen { key }
+            | Command::Type { key }
+            | Command::Expire { key, .. }
+            | Command::Ttl { key }
+            | Command::Pttl { key }
+            | Command::Persist { key }
+            | Command::Push { key, .. }
+            | Command::Pop { key, .. }
+            | Command::Lrange { key, .. }
+            | Command::Llen { key }
+            | Command::Lindex { key, .. }
+            | Command::Ltrim { key, .. }
+            | Command::Hset { key, .. }
+            | Command::Hget { key, .. }
+            | Command::Hdel { key, .. }
+            | Command::Hgetall { key }
+            | Command::Hincrby { key, .. }
+            | Command::Hincrbyfloat { key, .. }
+            | Command::Hexists { key, .. }
+            | Command::Hlen { key }
+            | Command::Hscan { key, .. }
+            | Command::Sadd { key, .. }
+            | Command::Srem { key, .. }
+            | Command::Smembers { key }
+            | Command::Sismember { key, .. }
+            | Command::Scard { key }
+            | Command::Zrank { key, .. }
+            | Command::Zrem { key, .. }
+            | Command::Zcard { key } => vec![&key[..]],
+            Command::Set(set) => vec![&set.key[..]],
+            Command::Zadd(zadd) => vec![&zadd.key[..]],
+            Command::Zrange(zrange) => vec![&zrange.key[..]],
+            Command::Mget { keys }
+            | Command::Del { keys }
+            | Command::Exists { keys }
+            | Command::BlockingPop { keys, .. }
+            | Command::Combine { keys, .. }
+            | Command::Watch { keys } => keys.iter().map(|key| &key[..]).collect(),
+            Command::Mset { pairs } => pairs.iter().map(|(key, _)| &key[..]).collect(),
+            Command::Rename { key, new_key } => vec![&key[..], &new_key[..]],
+            Command::Blmove {
+                source,
+                destination,
+                ..
+            } => vec![&source[..], &destination[..]],
+            Command::Ping { .. }
+            | Command::Hello { .. }
+            | Command::Publish { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Psubscribe { .. }
+            | Command::Punsubscribe { .. }
+            | Command::Save
+            | Command::Bgsave
+            | Command::Bgrewriteaof
+            | Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Unwatch => vec![],
+        };
+        Some(keys)
+    }
+
+    async fn execute(self, db: &Db) -> Result<Frame, Error> {
+        match self {
+            Command::BlockingPop { keys, end, timeout } => {
+                list::apply_blocking_pop(db, keys, end, timeout).await
+            }
+            Command::Blmove {
+                source,
+                destination,
+                from,
+                to,
+                timeout,
+            } => list::apply_blmove(db, source, destination, (from, to), timeout).await,
+            cmd if cmd.is_write() => {
+                let res = cmd.execute_now(db);
+                // 推入的元素可能让等待的 BLMOVE 可以移动了
+                db.serve_ready();
+                res
+            }
+            cmd => cmd.execute_now(db),
+        }
+    }
+
+    /// Execute a command that does not block, the keys written by the command are marked as
+    /// changed for the clients watching them
+    fn execute_now(self, db: &impl Keyspace) -> Result<Frame, Error> {
+        match self.is_write() {
+            true => self.run(&Touching(db)),
+            false => self.run(db),
+        }
+    }
+
+    fn run(self, db: &impl Keyspace) -> Result<Frame, Error> {
+        let frame = match self {
+            Command::Ping { message } => match message {
+                Some(message) => Frame::Bulk(message),
+                None => Frame::Simple("PONG".to_string()),
+            },
+            Command::Get { key } => db.get(&key)?.into(),
+            Command::Set(set) => string::apply_set(db, set)?,
+            Command::Mget { keys } => string::apply_mget(db, &keys),
+            Command::Mset { pairs } => string::apply_mset(db, pairs),
+            Command::Incrby { key, increment } => string::apply_incrby(db, &key, increment)?,
+            Command::Incrbyfloat { key, increment } => {
+                string::apply_incrbyfloat(db, &key, increment)?
+            }
+            Command::Append { key, value } => string::apply_append(db, &key, &value)?,
+            Command::Getrange { key, start, end } => string::apply_getrange(db, &key, start, end)?,
+            Command::Setrange { key, offset, value } => {
+                string::apply_setrange(db, &key, offset, &value)?
+            }
+            Command::Strlen { key } => string::apply_strlen(db, &key)?,
+            Command::Del { keys } => Frame::Integer(db.del(&keys) as i64),
+            Command::Exists { keys } => Frame::Integer(db.exists(&keys) as i64),
+            Command::Type { key } => keys::apply_type(db, &key),
+            Command::Rename { key, new_key } => keys::apply_rename(db, &key, &new_key)?,
+            Command::Keys { pattern } => keys::apply_keys(db, &pattern),
+            Command::Scan(scan) => keys::apply_scan(db, &scan),
+            Command::Dbsize => Frame::Integer(db.len() as i64),
+            Command::Flushdb => {
+                db.flush();
+                Frame::ok()
+            }
+            Command::Expire {
+                key,
+                expiry,
+                condition,
+            } => Frame::Integer(db.expire(&key, expiry, condition) as i64),
+            // 和 redis 一样，TTL 按四舍五入换算成秒
+            Command::Ttl { key } => {
+                ttl_frame(db.ttl(&key), |ttl| (ttl.as_millis() as i64 + 500) / 1000)
+            }
+            Command::Pttl { key } => ttl_frame(db.ttl(&key), |ttl| ttl.as_millis() as i64),
+            Command::Persist { key } => Frame::Integer(db.persist(&key) as i64),
+            Command::Push { key, elements, end } => list::apply_push(db, &key, elements, end)?,
+            Command::Pop { key, count, end } => list::apply_pop(db, &key, count, end)?,
+            Command::BlockingPop { .. } | Command::Blmove { .. } => {
+                unreachable!("blocking commands are executed by `Command::execute`")
+            }
+            Command::Lrange { key, start, stop } => list::apply_lrange(db, &key, start, stop)?,
+            Command::Llen { key } => list::apply_llen(db, &key)?,
+            Command::Lindex { key, index } => list::apply_lindex(db, &key, index)?,
+            Command::Ltrim { key, start, stop } => list::apply_ltrim(db, &key, start, stop)?,
+            Command::Hset { key, fields } => hash::apply_hset(db, &key, fields)?,
+            Command::Hget { key, field } => hash::apply_hget(db, &key, &field)?,
+            Command::Hdel { key, fields } => hash::apply_hdel(db, &key, fields)?,
+            Command::Hgetall { key } => hash::apply_hgetall(db, &key)?,
+            Command::Hincrby {
+                key,
+                field,
+                increment,
+            } => hash::apply_hincrby(db, &key, field, increment)?,
+            Command::Hincrbyfloat {
+                key,
+                field,
+                increment,
+            } => hash::apply_hincrbyfloat(db, &key, field, increment)?,
+            Command::Hexists { key, field } => hash::apply_hexists(db, &key, &field)?,
+            Command::Hlen { key } => hash::apply_hlen(db, &key)?,
+            Command::Hscan {
+                key,
+                scan,
+                novalues,
+            } => hash::apply_hscan(db, &key, &scan, novalues)?,
+            Command::Sadd { key, members } => set::apply_sadd(db, &key, members)?,
+            Command::Srem { key, members } => set::apply_srem(db, &key, members)?,
+            Command::Smembers { key } => set::apply_smembers(db, &key)?,
+            Command::Sismember { key, member } => set::apply_sismember(db, &key, &member)?,
+            Command::Scard { key } => set::apply_scard(db, &key)?,
+            Command::Combine { keys, op } => set::apply_combine(db, &keys, op)?,
+            Command::Zadd(zadd) => zset::apply_zadd(db, zadd)?,
+            Command::Zrange(zrange) => zset::apply_zrange(db, &zrange)?,
+            Command::Zrank {
+                key,
+                member,
+                with_score,
+            } => zset::apply_zrank(db, &key, &member, with_score)?,
+            Command::Zrem { key, members } => zset::apply_zrem(db, &key, members)?,
+            Command::Zcard { key } => zset::apply_zcard(db, &key)?,
+            Command::Publish { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Psubscribe { .. }
+            | Command::Punsubscribe { .. } => {
+                return Err("ERR pub/sub commands are handled by the connection".into())
+            }
+            Command::Save | Command::Bgsave | Command::Bgrewriteaof => {
+                return Err("ERR persistence commands are handled by the server".into())
+            }
+            Command::Hello { .. } => return Err("ERR HELLO is handled by the connection".into()),
+            Command::Multi
+            | Command::Exec
+            | Command::Discard
+            | Command::Watch { .. }
+            | Command::Unwatch => {
+                return Err("ERR transaction commands are handled by the connection".into())
+            }
+        };
+        Ok(frame)
+    }
+}
+
+/// The reply of TTL and PTTL: -2 if the key does not exist, -1 if it has no expiry
+fn ttl_frame(ttl: KeyTtl, convert: impl FnOnce(std::time::Duration) -> i64) -> Frame {
+    match ttl {
+        KeyTtl::Missing => Frame::Integer(-2),
+        KeyTtl::Persistent => Frame::Integer(-1),
+        KeyTtl::Expires(ttl) => Frame::Integer(convert(ttl)),
+    }
+}
+
+fn ping(parse: &mut Parse) -> Result<Command, ParseError> {
+    if parse.remaining() > 1 {
+        return Err(ParseError::WrongArity("ping".to_string()));
+    }
+    let message = match parse.remaining() {
+        0 => None,
+        _ => Some(parse.next_bytes()?),
+    };
+    Ok(Command::Ping { message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse the command made of `args`, an error is the message sent back to the client
+    pub(super) fn parse(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<Bytes> = args
+            .iter()
+            .map(|arg| Bytes::from(arg.to_string()))
+            .collect();
+        Command::from_frame(Frame::bulks(args)).map_err(|err| err.to_string())
+    }
+
+    /// Run the command made of `args` against `db`
+    pub(super) async fn run(db: &Db, args: &[&str]) -> Frame {
+        let command = parse(args).unwrap_or_else(|err| panic!("{}", err));
+        command.apply(db).await
+    }
+
+    /// Run a command of the SCAN family until the cursor comes back to 0, `args` are the arguments
+    /// before the cursor and `options` the ones after it. Returns the elements of every reply.
+    pub(super) async fn scan_all(db: &Db, args: &[&str], options: &[&str]) -> Vec<Bytes> {
+        let mut cursor = "0".to_string();
+        let mut elements = Vec::new();
+        loop {
+            let command: Vec<&str> = args
+                .iter()
+                .copied()
+                .chain([cursor.as_str()])
+                .chain(options.iter().copied())
+                .collect();
+            let Frame::Array(reply) = run(db, &command).await else {
+                panic!("{:?} did not reply with an array", command);
+            };
+            let [Frame::Bulk(next), Frame::Array(batch)] = &reply[..] else {
+                panic!("unexpected reply {:?}", reply);
+            };
+            elements.extend(batch.iter().map(|element| match element {
+                Frame::Bulk(element) => element.clone(),
+                element => panic!("unexpected element {:?}", element),
+            }));
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                return elements;
+            }
+        }
+    }
+
+    /// The error replied to a command `name` with a wrong number of arguments
+    pub(super) fn wrong_arity(name: &str) -> Result<Command, String> {
+        Err(ParseError::WrongArity(name.to_string()).to_string())
+    }
+
+    #[test]
+    fn command_names_are_case_insensitive() {
+        let get = Command::Get {
+            key: "k".to_string(),
+        };
+        assert_eq!(parse(&["GET", "k"]), Ok(get.clone()));
+        assert_eq!(parse(&["gEt", "k"]), Ok(get));
+    }
+
+    #[test]
+    fn unknown_command() {
+        assert_eq!(
+            parse(&["nope", "a", "b", "c", "d"]),
+            Err("ERR unknown command 'nope', with args beginning with: 'a' 'b' 'c'".to_string())
+        );
+    }
+
+    #[test]
+    fn empty_command() {
+        assert_eq!(
+            Command::from_frame(Frame::Array(vec![])),
+            Err(ParseError::Protocol("empty command".to_string()))
+        );
+    }
+
+    #[test]
+    fn arity() {
+        // 命令名按小写报告
+        assert_eq!(
+            parse(&["GET"]),
+            Err("ERR wrong number of arguments for 'get' command".to_string())
+        );
+        assert_eq!(parse(&["get", "a", "b"]), wrong_arity("get"));
+        assert_eq!(parse(&["set", "k"]), wrong_arity("set"));
+        assert_eq!(parse(&["ping", "a", "b"]), wrong_arity("ping"));
+    }
+
+    #[test]
+    fn ping() {
+        assert_eq!(parse(&["ping"]), Ok(Command::Ping { message: None }));
+        assert_eq!(
+            parse(&["ping", "hi"]),
+            Ok(Command::Ping {
+                message: Some(Bytes::from("hi"))
+            })
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Pub/sub commands.
+
+use super::{Command, Parse, ParseError};
+
+/// PUBLISH channel message
+pub(super) fn publish(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Publish {
+        channel: parse.next_string()?,
+        message: parse.next_bytes()?,
+    })
+}
+
+/// SUBSCRIBE channel [channel ...]
+pub(super) fn subscribe(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Subscribe {
+        channels: parse.rest_strings()?,
+    })
+}
+
+/// UNSUBSCRIBE [channel ...], unsubscribe from all channels when none is given
+pub(super) fn unsubscribe(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Unsubscribe {
+        channels: parse.rest_strings()?,
+    })
+}
+
+/// PSUBSCRIBE pattern [pattern ...]
+pub(super) fn psubscribe(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Psubscribe {
+        patterns: parse.rest_strings()?,
+    })
+}
+
+/// PUNSUBSCRIBE [pattern ...], unsubscribe from all patterns when none is given
+pub(super) fn punsubscribe(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Punsubscribe {
+        patterns: parse.rest_strings()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::myredis::cmd::tests::{parse, wrong_arity};
+
+    #[test]
+    fn pubsub_commands() {
+        assert_eq!(
+            parse(&["PUBLISH", "news", "hi"]),
+            Ok(Command::Publish {
+                channel: "news".to_string(),
+                message: Bytes::from("hi")
+            })
+        );
+        assert_eq!(
+            parse(&["subscribe", "a", "b"]),
+            Ok(Command::Subscribe {
+                channels: vec!["a".to_string(), "b".to_string()]
+            })
+        );
+        assert_eq!(
+            parse(&["unsubscribe"]),
+            Ok(Command::Unsubscribe { channels: vec![] })
+        );
+        assert_eq!(
+            parse(&["PSubscribe", "n*"]),
+            Ok(Command::Psubscribe {
+                patterns: vec!["n*".to_string()]
+            })
+        );
+        assert_eq!(
+            parse(&["punsubscribe"]),
+            Ok(Command::Punsubscribe { patterns: vec![] })
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["publish", "news"]), wrong_arity("publish"));
+        assert_eq!(
+            parse(&["publish", "news", "a", "b"]),
+            wrong_arity("publish")
+        );
+        assert_eq!(parse(&["subscribe"]), wrong_arity("subscribe"));
+        assert_eq!(parse(&["psubscribe"]), wrong_arity("psubscribe"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Cursor based iteration of the SCAN family of commands.
+//! 元素按照一个固定的哈希值排序，游标就是下一次开始的哈希值。这样在整个迭代过程中一直存在的元素一定会被返回，
+//! 不受期间插入、删除其他元素的影响；哈希值相同的元素可能被返回两次，和 redis 一样调用方需要能处理重复的元素。
+
+use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+use bytes::Bytes;
+
+use super::{Parse, ParseError};
+use crate::myredis::{frame::Frame, glob};
+
+/// The number of elements visited by one call when COUNT is not given
+const DEFAULT_COUNT: usize = 10;
+
+/// cursor [MATCH pattern] [COUNT count]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scan {
+    pub cursor: u64,
+    /// Only return the elements matching the glob-style pattern
+    pub pattern: Option<Bytes>,
+    /// How many elements to visit, the reply may contain fewer once filtered by `pattern`
+    pub count: usize,
+}
+
+impl Scan {
+    /// Parse the cursor and the options, `option` is called with the options specific to the command
+    /// and returns whether it recognised the option
+    pub(super) fn parse(
+        parse: &mut Parse,
+        mut option: impl FnMut(&str, &mut Parse) -> Result<bool, ParseError>,
+    ) -> Result<Scan, ParseError> {
+        let cursor = parse.next_string()?;
+        let mut scan = Scan {
+            cursor: cursor
+                .parse()
+                .map_err(|_| ParseError::Invalid("ERR invalid cursor".to_string()))?,
+            pattern: None,
+            count: DEFAULT_COUNT,
+        };
+
+        while parse.remaining() > 0 {
+            let flag = parse.next_flag()?;
+            match &flag[..] {
+                "MATCH" => scan.pattern = Some(parse.next_bytes()?),
+                "COUNT" => {
+                    scan.count = match parse.next_int()? {
+                        count if count < 1 => return Err(ParseError::Syntax),
+                        count => count as usize,
+                    }
+                }
+                flag if option(flag, parse)? => {}
+                _ => return Err(ParseError::Syntax),
+            }
+        }
+        Ok(scan)
+    }
+
+    /// Visit the next `count` elements from the cursor, returning the next cursor and the visited
+    /// elements that match the pattern. The next cursor is 0 once all the elements have been visited.
+    /// A call takes time linear in the number of elements.
+    pub(super) fn step<K, T>(&self, elements: impl IntoIterator<Item = (K, T)>) -> (u64, Vec<T>)
+    where
+        K: AsRef<[u8]>,
+    {
+        let mut remaining: Vec<(u64, K, T)> = elements
+            .into_iter()
+            .map(|(name, element)| (position(name.as_ref()), name, element))
+            .filter(|(position, _, _)| *position >= self.cursor)
+            .collect();
+
+        // 只需要找出最前面的 `count` 个元素，不用排序所有的元素
+        let mut next = 0;
+        if remaining.len() > self.count {
+            remaining.select_nth_unstable_by_key(self.count, |(position, _, _)| *position);
+            remaining.truncate(self.count + 1);
+            next = remaining.pop().unwrap().0;
+        }
+
+        let matched = remaining
+            .into_iter()
+            .filter(|(_, name, _)| self.matches(name.as_ref()))
+            .map(|(_, _, element)| element)
+            .collect();
+        (next, matched)
+    }
+
+    /// Whether `name` matches the pattern, if any
+    pub(super) fn matches(&self, name: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob::matches(pattern, name))
+    }
+}
+
+/// The position of an element in the iteration order, never 0 which is the cursor of a new iteration
+fn position(name: &[u8]) -> u64 {
+    // `DefaultHasher::new` 使用固定的密钥，同一个元素每次的哈希值都相同
+    let mut hasher = DefaultHasher::new();
+    hasher.write(name);
+    hasher.finish().max(1)
+}
+
+/// The reply of the SCAN family: the next cursor and the elements
+pub(super) fn reply(cursor: u64, elements: Vec<Bytes>) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from(cursor.to_string())),
+        Frame::bulks(elements),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use super::*;
+    use crate::{
+        myredis::{
+            cmd::{
+                tests::{parse, run, scan_all},
+                Command,
+            },
+            db::Db,
+        },
+        timer_future::Clock,
+    };
+
+    /// One SCAN call from `cursor`, returning the next cursor and the keys
+    async fn scan(db: &Db, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let args = ["SCAN", &cursor.to_string(), "COUNT", &count.to_string()];
+        let Frame::Array(reply) = run(db, &args).await else {
+            panic!("SCAN did not reply with an array");
+        };
+        let [Frame::Bulk(next), Frame::Array(keys)] = &reply[..] else {
+            panic!("unexpected reply {:?}", reply);
+        };
+        let keys = keys
+            .iter()
+            .map(|key| match key {
+                Frame::Bulk(key) => String::from_utf8(key.to_vec()).unwrap(),
+                key => panic!("unexpected key {:?}", key),
+            })
+            .collect();
+        (std::str::from_utf8(next).unwrap().parse().unwrap(), keys)
+    }
+
+    /// A database with several shards holding the keys `key:0` to `key:{n-1}`
+    async fn db(clock: Clock, n: usize) -> Db {
+        let db = Db::with_shards(16, clock);
+        for i in 0..n {
+            run(&db, &["SET", &format!("key:{}", i), "v"]).await;
+        }
+        db
+    }
+
+    #[test]
+    fn scan_options() {
+        assert_eq!(
+            parse(&["scan", "0"]),
+            Ok(Command::Scan(Scan {
+                cursor: 0,
+                pattern: None,
+                count: 10
+            }))
+        );
+        assert_eq!(
+            parse(&["SCAN", "42", "match", "user:*", "Count", "100"]),
+            Ok(Command::Scan(Scan {
+                cursor: 42,
+                pattern: Some("user:*".into()),
+                count: 100
+            }))
+        );
+        // 重复的选项以最后一个为准
+        assert_eq!(
+            parse(&["scan", "0", "COUNT", "1", "COUNT", "2"]),
+            Ok(Command::Scan(Scan {
+                cursor: 0,
+                pattern: None,
+                count: 2
+            }))
+        );
+    }
+
+    #[test]
+    fn scan_errors() {
+        let syntax = Err("ERR syntax error".to_string());
+        assert_eq!(
+            parse(&["scan", "-1"]),
+            Err("ERR invalid cursor".to_string())
+        );
+        assert_eq!(parse(&["scan", "x"]), Err("ERR invalid cursor".to_string()));
+        assert_eq!(
+            parse(&["scan", "0", "COUNT", "many"]),
+            Err("ERR value is not an integer or out of range".to_string())
+        );
+        assert_eq!(parse(&["scan", "0", "COUNT", "0"]), syntax);
+        assert_eq!(parse(&["scan", "0", "MATCH"]), syntax);
+        assert_eq!(parse(&["scan", "0", "TYPE", "string"]), syntax);
+        // NOVALUES 只是 HSCAN 的选项
+        assert_eq!(parse(&["scan", "0", "NOVALUES"]), syntax);
+    }
+
+    #[test]
+    fn step_in_batches() {
+        let scan = Scan {
+            cursor: 0,
+            pattern: None,
+            count: 3,
+        };
+        let names: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let (next, batch) = Scan {
+                cursor,
+                ..scan.clone()
+            }
+            .step(names.iter().map(|name| (name, name)));
+            assert!(batch.len() <= 3);
+            seen.extend(batch.into_iter().cloned());
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        seen.sort();
+        assert_eq!(seen, names);
+
+        let (next, matched) = Scan {
+            pattern: Some("[0-4]".into()),
+            count: 100,
+            ..scan
+        }
+        .step(names.iter().map(|name| (name, name)));
+        assert_eq!(next, 0);
+        assert_eq!(matched.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn scan_visits_every_key_once() {
+        let db = db(Clock::system(), 500).await;
+        let mut cursor = 0;
+        let mut seen = HashSet::new();
+        loop {
+            let (next, keys) = scan(&db, cursor, 7).await;
+            assert!(keys.len() <= 7, "{} keys for COUNT 7", keys.len());
+            for key in keys {
+                assert!(seen.insert(key.clone()), "{} returned twice", key);
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 500);
+
+        let mut matched = scan_all(&db, &["SCAN"], &["MATCH", "key:4?", "COUNT", "20"]).await;
+        matched.sort();
+        let expected: Vec<Bytes> = (40..50).map(|i| format!("key:{}", i).into()).collect();
+        assert_eq!(matched, expected);
+
+        let empty = Db::new();
+        assert_eq!(scan(&empty, 0, 10).await, (0, Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn scan_while_keys_change() {
+        let db = db(Clock::system(), 200).await;
+        let mut cursor = 0;
+        let mut seen = HashSet::new();
+        let mut round = 0;
+        loop {
+            let (next, keys) = scan(&db, cursor, 5).await;
+            seen.extend(keys);
+            // 迭代期间删除一部分键，再加入新的键
+            run(&db, &["DEL", &format!("key:{}", 100 + round)]).await;
+            run(&db, &["SET", &format!("new:{}", round), "v"]).await;
+            round += 1;
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        // 整个迭代期间一直存在的键都被返回了
+        for i in 0..100 {
+            assert!(seen.contains(&format!("key:{}", i)), "key:{} was missed", i);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_skips_expired_keys() {
+        let clock = Clock::paused();
+        let db = db(clock.clone(), 20).await;
+        for i in 0..10 {
+            run(&db, &["PEXPIRE", &format!("key:{}", i), "100"]).await;
+        }
+        clock.advance(Duration::from_millis(100));
+
+        let mut keys = scan_all(&db, &["SCAN"], &["COUNT", "3"]).await;
+        keys.sort();
+        let mut expected: Vec<Bytes> = (10..20).map(|i| format!("key:{}", i).into()).collect();
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+}
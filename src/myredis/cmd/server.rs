@@ -0,0 +1,36 @@
+//! Server commands.
+
+use super::{Command, Parse, ParseError};
+
+/// SAVE
+pub(super) fn save(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Save)
+}
+
+/// BGSAVE
+pub(super) fn bgsave(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Bgsave)
+}
+
+/// BGREWRITEAOF
+pub(super) fn bgrewriteaof(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Bgrewriteaof)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::cmd::tests::parse;
+
+    #[test]
+    fn server_commands() {
+        assert_eq!(parse(&["save"]), Ok(Command::Save));
+        assert_eq!(parse(&["BGSAVE"]), Ok(Command::Bgsave));
+        assert_eq!(parse(&["BgRewriteAof"]), Ok(Command::Bgrewriteaof));
+        // 不支持 BGSAVE SCHEDULE
+        assert_eq!(
+            parse(&["bgsave", "schedule"]),
+            Err("ERR wrong number of arguments for 'bgsave' command".to_string())
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Set commands.
+
+use std::collections::HashSet;
+
+use bytes::Bytes;
+
+use super::{Command, Parse, ParseError};
+use crate::myredis::{db::Keyspace, frame::Frame, Error};
+
+/// How SINTER, SUNION and SDIFF combine their sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// SADD key member [member ...]
+pub(super) fn sadd(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Sadd {
+        key: parse.next_string()?,
+        members: parse.rest_bytes()?,
+    })
+}
+
+/// SREM key member [member ...]
+pub(super) fn srem(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Srem {
+        key: parse.next_string()?,
+        members: parse.rest_bytes()?,
+    })
+}
+
+/// SMEMBERS key
+pub(super) fn smembers(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Smembers {
+        key: parse.next_string()?,
+    })
+}
+
+/// SISMEMBER key member
+pub(super) fn sismember(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Sismember {
+        key: parse.next_string()?,
+        member: parse.next_bytes()?,
+    })
+}
+
+/// SCARD key
+pub(super) fn scard(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Scard {
+        key: parse.next_string()?,
+    })
+}
+
+/// SINTER key [key ...]
+pub(super) fn sinter(parse: &mut Parse) -> Result<Command, ParseError> {
+    combine(parse, SetOp::Inter)
+}
+
+/// SUNION key [key ...]
+pub(super) fn sunion(parse: &mut Parse) -> Result<Command, ParseError> {
+    combine(parse, SetOp::Union)
+}
+
+/// SDIFF key [key ...]
+pub(super) fn sdiff(parse: &mut Parse) -> Result<Command, ParseError> {
+    combine(parse, SetOp::Diff)
+}
+
+fn combine(parse: &mut Parse, op: SetOp) -> Result<Command, ParseError> {
+    Ok(Command::Combine {
+        keys: parse.rest_strings()?,
+        op,
+    })
+}
+
+/// Reply with the number of members that were added, not counting those already in the set
+pub(super) fn apply_sadd(
+    db: &impl Keyspace,
+    key: &str,
+    members: Vec<Bytes>,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let set = shard.members_or_insert(key)?;
+        let added = members
+            .into_iter()
+            .filter(|member| set.insert(member.clone()))
+            .count();
+        Ok(Frame::Integer(added as i64))
+    })
+}
+
+/// Reply with the number of members that were removed, deleting the key if none is left
+pub(super) fn apply_srem(
+    db: &impl Keyspace,
+    key: &str,
+    members: Vec<Bytes>,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(set) = shard.members(key)? else {
+            return Ok(Frame::Integer(0));
+        };
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        shard.remove_if_empty(key);
+        Ok(Frame::Integer(removed as i64))
+    })
+}
+
+pub(super) fn apply_smembers(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let members = shard.members(key)?.map_or_else(Vec::new, |set| {
+            set.iter().cloned().map(Frame::Bulk).collect()
+        });
+        Ok(Frame::Set(members))
+    })
+}
+
+pub(super) fn apply_sismember(
+    db: &impl Keyspace,
+    key: &str,
+    member: &[u8],
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let found = shard.members(key)?.is_some_and(|set| set.contains(member));
+        Ok(Frame::Integer(found as i64))
+    })
+}
+
+pub(super) fn apply_scard(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let len = shard.members(key)?.map_or(0, |set| set.len());
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+/// Combine the sets at `keys`, a missing key counts as an empty set
+pub(super) fn apply_combine(
+    db: &impl Keyspace,
+    keys: &[String],
+    op: SetOp,
+) -> Result<Frame, Error> {
+    db.with_keys(keys, |shards| {
+        // 只复制结果，其余的集合逐个借用，每个键的类型都要检查
+        let mut result: Option<HashSet<Bytes>> = None;
+        for key in keys {
+            let set = shards.shard(key).members(key)?;
+            result = Some(match (result, set) {
+                (None, set) => set.cloned().unwrap_or_default(),
+                (Some(mut result), None) => {
+                    if op == SetOp::Inter {
+                        result.clear();
+                    }
+                    result
+                }
+                (Some(mut result), Some(set)) => {
+                    match op {
+                        SetOp::Inter => result.retain(|member| set.contains(member)),
+                        SetOp::Union => result.extend(set.iter().cloned()),
+                        SetOp::Diff => result.retain(|member| !set.contains(member)),
+                    }
+                    result
+                }
+            });
+        }
+        let members = result.unwrap_or_default().into_iter().map(Frame::Bulk);
+        Ok(Frame::Set(members.collect()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        myredis::{
+            cmd::tests::{parse, run, wrong_arity},
+            db::Db,
+        },
+        timer_future::Clock,
+    };
+
+    /// The members of a set reply, sorted
+    fn sorted(frame: Frame) -> Vec<Frame> {
+        let Frame::Set(mut members) = frame else {
+            panic!("{:?} is not a set", frame);
+        };
+        members.sort_by_key(|member| format!("{:?}", member));
+        members
+    }
+
+    fn bulks(members: &[&str]) -> Vec<Frame> {
+        members
+            .iter()
+            .map(|member| Frame::Bulk(Bytes::from(member.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn set_commands() {
+        assert_eq!(
+            parse(&["SADD", "s", "a", "b"]),
+            Ok(Command::Sadd {
+                key: "s".to_string(),
+                members: vec!["a".into(), "b".into()]
+            })
+        );
+        for (name, op) in [
+            ("sinter", SetOp::Inter),
+            ("SUNION", SetOp::Union),
+            ("sDiff", SetOp::Diff),
+        ] {
+            assert_eq!(
+                parse(&[name, "a", "b"]),
+                Ok(Command::Combine {
+                    keys: vec!["a".to_string(), "b".to_string()],
+                    op
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["sadd", "s"]), wrong_arity("sadd"));
+        assert_eq!(parse(&["srem", "s"]), wrong_arity("srem"));
+        assert_eq!(parse(&["smembers", "s", "t"]), wrong_arity("smembers"));
+        assert_eq!(parse(&["sismember", "s"]), wrong_arity("sismember"));
+        assert_eq!(parse(&["scard"]), wrong_arity("scard"));
+        assert_eq!(parse(&["sinter"]), wrong_arity("sinter"));
+        assert_eq!(parse(&["sunion"]), wrong_arity("sunion"));
+        assert_eq!(parse(&["sdiff"]), wrong_arity("sdiff"));
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_members() {
+        let db = Db::new();
+        assert_eq!(
+            run(&db, &["SADD", "s", "a", "b", "a"]).await,
+            Frame::Integer(2)
+        );
+        assert_eq!(run(&db, &["SADD", "s", "b", "c"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["SCARD", "s"]).await, Frame::Integer(3));
+        assert_eq!(run(&db, &["SISMEMBER", "s", "a"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["SISMEMBER", "s", "x"]).await, Frame::Integer(0));
+        assert_eq!(
+            sorted(run(&db, &["SMEMBERS", "s"]).await),
+            bulks(&["a", "b", "c"])
+        );
+
+        assert_eq!(run(&db, &["SREM", "s", "a", "x"]).await, Frame::Integer(1));
+        // 移除最后一个成员时键也被删除
+        assert_eq!(run(&db, &["SREM", "s", "b", "c"]).await, Frame::Integer(2));
+        assert_eq!(run(&db, &["EXISTS", "s"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["SMEMBERS", "s"]).await, Frame::Set(Vec::new()));
+        assert_eq!(run(&db, &["SCARD", "s"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["SREM", "s", "a"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn combine_sets() {
+        let db = Db::with_shards(4, Clock::system());
+        run(&db, &["SADD", "a", "1", "2", "3", "4"]).await;
+        run(&db, &["SADD", "b", "3", "4", "5"]).await;
+        run(&db, &["SADD", "c", "4", "6"]).await;
+
+        assert_eq!(
+            sorted(run(&db, &["SINTER", "a", "b", "c"]).await),
+            bulks(&["4"])
+        );
+        assert_eq!(
+            sorted(run(&db, &["SUNION", "a", "b", "c"]).await),
+            bulks(&["1", "2", "3", "4", "5", "6"])
+        );
+        assert_eq!(
+            sorted(run(&db, &["SDIFF", "a", "b", "c"]).await),
+            bulks(&["1", "2"])
+        );
+
+        // 不存在的键是一个空集合
+        assert_eq!(
+            run(&db, &["SINTER", "a", "missing"]).await,
+            Frame::Set(Vec::new())
+        );
+        assert_eq!(
+            sorted(run(&db, &["SUNION", "missing", "c"]).await),
+            bulks(&["4", "6"])
+        );
+        assert_eq!(
+            sorted(run(&db, &["SDIFF", "c", "missing"]).await),
+            bulks(&["4", "6"])
+        );
+        assert_eq!(
+            run(&db, &["SDIFF", "missing", "a"]).await,
+            Frame::Set(Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn wrong_type() {
+        let db = Db::new();
+        let wrongtype = Frame::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".to_string(),
+        );
+        run(&db, &["SET", "str", "v"]).await;
+        run(&db, &["SADD", "s", "a"]).await;
+        assert_eq!(run(&db, &["SADD", "str", "a"]).await, wrongtype);
+        assert_eq!(run(&db, &["SMEMBERS", "str"]).await, wrongtype);
+        assert_eq!(run(&db, &["SCARD", "str"]).await, wrongtype);
+        // 即使交集已经为空，后面的键也要检查类型
+        assert_eq!(
+            run(&db, &["SINTER", "missing", "s", "str"]).await,
+            wrongtype
+        );
+        assert_eq!(run(&db, &["SUNION", "s", "str"]).await, wrongtype);
+    }
+}
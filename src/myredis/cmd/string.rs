@@ -0,0 +1,557 @@
+//! String commands.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+
+use super::{Command, Parse, ParseError};
+use crate::myredis::{
+    db::{Expiry, Keyspace},
+    frame::Frame,
+    parse::{format_float, parse_float, parse_int},
+    Error,
+};
+
+/// The maximum length of a string value, 512MB like redis
+const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+/// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub key: String,
+    pub value: Bytes,
+    /// `None` removes any existing time to live of the key, unless `keep_ttl` is set
+    pub expiry: Option<Expiry>,
+    /// KEEPTTL: retain the time to live of the existing key
+    pub keep_ttl: bool,
+    pub condition: Option<SetCondition>,
+    /// Reply with the old value of the key
+    pub get: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetCondition {
+    /// NX: only set the key if it does not exist
+    NotExists,
+    /// XX: only set the key if it already exists
+    Exists,
+}
+
+/// GET key
+pub(super) fn get(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Get {
+        key: parse.next_string()?,
+    })
+}
+
+pub(super) fn set(parse: &mut Parse) -> Result<Command, ParseError> {
+    let mut set = Set {
+        key: parse.next_string()?,
+        value: parse.next_bytes()?,
+        expiry: None,
+        keep_ttl: false,
+        condition: None,
+        get: false,
+    };
+
+    while parse.remaining() > 0 {
+        match &parse.next_flag()?[..] {
+            "NX" if set.condition.is_none() => set.condition = Some(SetCondition::NotExists),
+            "XX" if set.condition.is_none() => set.condition = Some(SetCondition::Exists),
+            "GET" if !set.get => set.get = true,
+            "KEEPTTL" if set.expiry.is_none() && !set.keep_ttl => set.keep_ttl = true,
+            flag @ ("EX" | "PX" | "EXAT" | "PXAT") if set.expiry.is_none() && !set.keep_ttl => {
+                set.expiry = Some(expiry(flag, parse.next_int()?, "set")?);
+            }
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+
+    Ok(Command::Set(set))
+}
+
+/// GETSET key value, which is `SET key value GET`
+pub(super) fn getset(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Set(Set {
+        key: parse.next_string()?,
+        value: parse.next_bytes()?,
+        expiry: None,
+        keep_ttl: false,
+        condition: None,
+        get: true,
+    }))
+}
+
+/// MGET key [key ...]
+pub(super) fn mget(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Mget {
+        keys: parse.rest_strings()?,
+    })
+}
+
+/// MSET key value [key value ...]
+pub(super) fn mset(parse: &mut Parse) -> Result<Command, ParseError> {
+    if !parse.remaining().is_multiple_of(2) {
+        return Err(ParseError::WrongArity("mset".to_string()));
+    }
+    let mut pairs = Vec::with_capacity(parse.remaining() / 2);
+    while parse.remaining() > 0 {
+        pairs.push((parse.next_string()?, parse.next_bytes()?));
+    }
+    Ok(Command::Mset { pairs })
+}
+
+/// INCR key
+pub(super) fn incr(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Incrby {
+        key: parse.next_string()?,
+        increment: 1,
+    })
+}
+
+/// DECR key
+pub(super) fn decr(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Incrby {
+        key: parse.next_string()?,
+        increment: -1,
+    })
+}
+
+/// INCRBY key increment
+pub(super) fn incrby(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Incrby {
+        key: parse.next_string()?,
+        increment: parse.next_int()?,
+    })
+}
+
+/// DECRBY key decrement
+pub(super) fn decrby(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let increment = parse
+        .next_int()?
+        .checked_neg()
+        .ok_or_else(|| ParseError::Invalid("ERR decrement would overflow".to_string()))?;
+    Ok(Command::Incrby { key, increment })
+}
+
+/// INCRBYFLOAT key increment
+pub(super) fn incrbyfloat(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Incrbyfloat {
+        key: parse.next_string()?,
+        increment: parse.next_float()?,
+    })
+}
+
+/// APPEND key value
+pub(super) fn append(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Append {
+        key: parse.next_string()?,
+        value: parse.next_bytes()?,
+    })
+}
+
+/// GETRANGE key start end
+pub(super) fn getrange(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Getrange {
+        key: parse.next_string()?,
+        start: parse.next_int()?,
+        end: parse.next_int()?,
+    })
+}
+
+/// SETRANGE key offset value
+pub(super) fn setrange(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let offset = usize::try_from(parse.next_int()?)
+        .map_err(|_| ParseError::Invalid("ERR offset is out of range".to_string()))?;
+    Ok(Command::Setrange {
+        key,
+        offset,
+        value: parse.next_bytes()?,
+    })
+}
+
+/// STRLEN key
+pub(super) fn strlen(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Strlen {
+        key: parse.next_string()?,
+    })
+}
+
+/// Build the expiry given by an `EX`, `PX`, `EXAT` or `PXAT` option of the command `name`
+pub(super) fn expiry(flag: &str, value: i64, name: &str) -> Result<Expiry, ParseError> {
+    let invalid = || ParseError::Invalid(format!("ERR invalid expire time in '{}' command", name));
+
+    // 秒数换算成毫秒后也不能溢出
+    if value <= 0 || (flag.starts_with("EX") && value > i64::MAX / 1000) {
+        return Err(invalid());
+    }
+    let value = value as u64;
+
+    Ok(match flag {
+        "EX" => Expiry::After(Duration::from_secs(value)),
+        "PX" => Expiry::After(Duration::from_millis(value)),
+        "EXAT" => Expiry::At(UNIX_EPOCH + Duration::from_secs(value)),
+        _ => Expiry::At(UNIX_EPOCH + Duration::from_millis(value)),
+    })
+}
+
+/// Reply with OK, or null if the NX or XX condition is not met. With GET reply with the old
+/// value instead, which must be a string.
+pub(super) fn apply_set(db: &impl Keyspace, set: Set) -> Result<Frame, Error> {
+    let key = set.key.clone();
+    db.with_shard(&key, |shard| {
+        let exists = shard.value(&key).is_some();
+        let old = match set.get {
+            true => shard.get(&key)?,
+            false => None,
+        };
+
+        let allowed = match set.condition {
+            None => true,
+            Some(SetCondition::NotExists) => !exists,
+            Some(SetCondition::Exists) => exists,
+        };
+        if allowed {
+            shard.set(set.key, set.value, set.expiry, set.keep_ttl);
+        }
+
+        Ok(match (set.get, allowed) {
+            (true, _) => old.into(),
+            (false, true) => Frame::ok(),
+            (false, false) => Frame::Null,
+        })
+    })
+}
+
+/// Reply with the values of `keys`, null for the keys that do not hold a string
+pub(super) fn apply_mget(db: &impl Keyspace, keys: &[String]) -> Frame {
+    db.with_keys(keys, |shards| {
+        let values = keys
+            .iter()
+            .map(|key| shards.shard(key).get(key).ok().flatten().into())
+            .collect();
+        Frame::Array(values)
+    })
+}
+
+/// Set all the keys at once, removing their time to live
+pub(super) fn apply_mset(db: &impl Keyspace, pairs: Vec<(String, Bytes)>) -> Frame {
+    let keys: Vec<&str> = pairs.iter().map(|(key, _)| key.as_str()).collect();
+    db.with_keys(&keys, |shards| {
+        for (key, value) in pairs.iter().cloned() {
+            shards.shard(&key).set(key, value, None, false);
+        }
+    });
+    Frame::ok()
+}
+
+/// Reply with the value after the increment, a missing key counts as 0. The time to live of the
+/// key is kept.
+pub(super) fn apply_incrby(db: &impl Keyspace, key: &str, increment: i64) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let current = match shard.get(key)? {
+            Some(value) => parse_int(&value).ok_or(ParseError::NotInteger)?,
+            None => 0,
+        };
+        let value = current
+            .checked_add(increment)
+            .ok_or("ERR increment or decrement would overflow")?;
+        shard.set(key.to_string(), Bytes::from(value.to_string()), None, true);
+        Ok(Frame::Integer(value))
+    })
+}
+
+/// Reply with the value after the increment, a missing key counts as 0. The time to live of the
+/// key is kept.
+pub(super) fn apply_incrbyfloat(
+    db: &impl Keyspace,
+    key: &str,
+    increment: f64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let current = match shard.get(key)? {
+            Some(value) => parse_float(&value).ok_or(ParseError::NotFloat)?,
+            None => 0.0,
+        };
+        let value = current + increment;
+        if !value.is_finite() {
+            return Err("ERR increment would produce NaN or Infinity".into());
+        }
+        let value = Bytes::from(format_float(value));
+        shard.set(key.to_string(), value.clone(), None, true);
+        Ok(Frame::Bulk(value))
+    })
+}
+
+/// Reply with the length of the string after appending `value`
+pub(super) fn apply_append(db: &impl Keyspace, key: &str, value: &[u8]) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let current = shard.get(key)?.unwrap_or_default();
+        check_len(current.len() + value.len())?;
+
+        let mut buf = BytesMut::with_capacity(current.len() + value.len());
+        buf.extend_from_slice(&current);
+        buf.extend_from_slice(value);
+        let len = buf.len();
+        shard.set(key.to_string(), buf.freeze(), None, true);
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+/// Reply with the substring in the inclusive range, negative offsets count from the end
+pub(super) fn apply_getrange(
+    db: &impl Keyspace,
+    key: &str,
+    start: i64,
+    end: i64,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let value = shard.get(key)?.unwrap_or_default();
+        // 和 redis 一样，两个负数下标反过来时不做换算，直接返回空串
+        if start < 0 && end < 0 && start > end {
+            return Ok(Frame::Bulk(Bytes::new()));
+        }
+        Ok(Frame::Bulk(match substring(value.len(), start, end) {
+            Some((start, end)) => value.slice(start..=end),
+            None => Bytes::new(),
+        }))
+    })
+}
+
+/// Turn the inclusive range of GETRANGE into indices of a string of length `len`. Unlike
+/// `list::range`, a negative end before the start of the string is clamped to the first byte.
+fn substring(len: usize, start: i64, end: i64) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let clamp = |index: i64| {
+        if index < 0 {
+            (index + len).max(0)
+        } else {
+            index
+        }
+    };
+    let (start, end) = (clamp(start), clamp(end).min(len - 1));
+    (start <= end).then_some((start as usize, end as usize))
+}
+
+/// Overwrite the string from `offset`, padding it with zero bytes if it is too short, and reply
+/// with its new length
+pub(super) fn apply_setrange(
+    db: &impl Keyspace,
+    key: &str,
+    offset: usize,
+    value: &[u8],
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let current = shard.get(key)?.unwrap_or_default();
+        // 空的 value 不会修改字符串，也不会创建键
+        if value.is_empty() {
+            return Ok(Frame::Integer(current.len() as i64));
+        }
+        let end = offset.saturating_add(value.len());
+        check_len(end)?;
+
+        let mut buf = BytesMut::from(&current[..]);
+        if buf.len() < end {
+            buf.resize(end, 0);
+        }
+        buf[offset..end].copy_from_slice(value);
+        let len = buf.len();
+        shard.set(key.to_string(), buf.freeze(), None, true);
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+pub(super) fn apply_strlen(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    let len = db.get(key)?.map_or(0, |value| value.len());
+    Ok(Frame::Integer(len as i64))
+}
+
+fn check_len(len: usize) -> Result<(), Error> {
+    if len > MAX_STRING_LEN {
+        return Err("ERR string exceeds maximum allowed size (proto-max-bulk-len)".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::{
+        cmd::tests::{parse, run, wrong_arity},
+        db::Db,
+    };
+
+    fn set(key: &str, value: &'static str) -> Set {
+        Set {
+            key: key.to_string(),
+            value: Bytes::from(value),
+            expiry: None,
+            keep_ttl: false,
+            condition: None,
+            get: false,
+        }
+    }
+
+    #[test]
+    fn set_options() {
+        assert_eq!(parse(&["set", "k", "v"]), Ok(Command::Set(set("k", "v"))));
+        assert_eq!(
+            parse(&["set", "k", "v", "nx", "Get", "px", "100"]),
+            Ok(Command::Set(Set {
+                condition: Some(SetCondition::NotExists),
+                get: true,
+                expiry: Some(Expiry::After(Duration::from_millis(100))),
+                ..set("k", "v")
+            }))
+        );
+        assert_eq!(
+            parse(&["SET", "k", "v", "XX", "KEEPTTL"]),
+            Ok(Command::Set(Set {
+                condition: Some(SetCondition::Exists),
+                keep_ttl: true,
+                ..set("k", "v")
+            }))
+        );
+        assert_eq!(
+            parse(&["set", "k", "v", "exat", "10"]),
+            Ok(Command::Set(Set {
+                expiry: Some(Expiry::At(UNIX_EPOCH + Duration::from_secs(10))),
+                ..set("k", "v")
+            }))
+        );
+        assert_eq!(
+            parse(&["getset", "k", "v"]),
+            Ok(Command::Set(Set {
+                get: true,
+                ..set("k", "v")
+            }))
+        );
+    }
+
+    #[test]
+    fn set_conflicting_options() {
+        let syntax = Err("ERR syntax error".to_string());
+        assert_eq!(parse(&["set", "k", "v", "NX", "XX"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "nx", "nx"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "EX", "1", "PX", "1"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "EX", "1", "KEEPTTL"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "KEEPTTL", "PXAT", "1"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "GET", "GET"]), syntax);
+        assert_eq!(parse(&["set", "k", "v", "NOPE"]), syntax);
+        // EX 缺少了值
+        assert_eq!(parse(&["set", "k", "v", "EX"]), syntax);
+    }
+
+    #[test]
+    fn set_expire_time() {
+        let invalid = Err("ERR invalid expire time in 'set' command".to_string());
+        assert_eq!(parse(&["set", "k", "v", "EX", "0"]), invalid);
+        assert_eq!(parse(&["set", "k", "v", "PX", "-1"]), invalid);
+        assert_eq!(
+            parse(&["set", "k", "v", "EX", &i64::MAX.to_string()]),
+            invalid
+        );
+        assert_eq!(
+            parse(&["set", "k", "v", "EX", "1.5"]),
+            Err("ERR value is not an integer or out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["getset", "k"]), wrong_arity("getset"));
+        assert_eq!(parse(&["mget"]), wrong_arity("mget"));
+        assert_eq!(parse(&["mset", "a", "1", "b"]), wrong_arity("mset"));
+        assert_eq!(parse(&["incr"]), wrong_arity("incr"));
+        assert_eq!(parse(&["incrby", "k"]), wrong_arity("incrby"));
+        assert_eq!(parse(&["getrange", "k", "0"]), wrong_arity("getrange"));
+        assert_eq!(parse(&["strlen", "k", "k"]), wrong_arity("strlen"));
+    }
+
+    #[test]
+    fn integer_arguments() {
+        let not_integer = Err("ERR value is not an integer or out of range".to_string());
+        assert_eq!(parse(&["incrby", "k", "one"]), not_integer);
+        assert_eq!(parse(&["incrby", "k", "+1"]), not_integer);
+        assert_eq!(parse(&["incrby", "k", "01"]), not_integer);
+        assert_eq!(parse(&["incrby", "k", "9223372036854775808"]), not_integer);
+        assert_eq!(parse(&["getrange", "k", "0", "x"]), not_integer);
+        assert_eq!(
+            parse(&["decr", "k"]),
+            Ok(Command::Incrby {
+                key: "k".to_string(),
+                increment: -1
+            })
+        );
+        assert_eq!(
+            parse(&["decrby", "k", &i64::MIN.to_string()]),
+            Err("ERR decrement would overflow".to_string())
+        );
+        assert_eq!(
+            parse(&["setrange", "k", "-1", "v"]),
+            Err("ERR offset is out of range".to_string())
+        );
+    }
+
+    #[test]
+    fn float_arguments() {
+        let not_float = Err("ERR value is not a valid float".to_string());
+        assert_eq!(parse(&["incrbyfloat", "k", "x"]), not_float);
+        assert_eq!(parse(&["incrbyfloat", "k", "nan"]), not_float);
+        assert_eq!(parse(&["incrbyfloat", "k", " 1"]), not_float);
+        assert_eq!(
+            parse(&["incrbyfloat", "k", "1.5e2"]),
+            Ok(Command::Incrbyfloat {
+                key: "k".to_string(),
+                increment: 150.0
+            })
+        );
+    }
+
+    #[test]
+    fn substring_indexes() {
+        assert_eq!(substring(5, 0, -1), Some((0, 4)));
+        assert_eq!(substring(5, 1, 3), Some((1, 3)));
+        assert_eq!(substring(5, -3, -2), Some((2, 3)));
+        assert_eq!(substring(5, 2, 100), Some((2, 4)));
+        // 超出开头的负数下标都被限制到第一个字节
+        assert_eq!(substring(5, 0, -10), Some((0, 0)));
+        assert_eq!(substring(5, -10, -10), Some((0, 0)));
+        assert_eq!(substring(5, -100, 1), Some((0, 1)));
+        assert_eq!(substring(5, 3, 1), None);
+        assert_eq!(substring(5, 5, 10), None);
+        assert_eq!(substring(0, 0, -1), None);
+        assert_eq!(substring(0, -1, 0), None);
+    }
+
+    #[tokio::test]
+    async fn getrange() {
+        let db = Db::new();
+        run(&db, &["SET", "k", "hello"]).await;
+        for (start, end, expected) in [
+            ("0", "-1", "hello"),
+            ("1", "3", "ell"),
+            ("-3", "-1", "llo"),
+            ("0", "-10", "h"),
+            ("-100", "-10", "h"),
+            ("3", "100", "lo"),
+            ("-1", "-5", ""),
+            ("2", "1", ""),
+            ("5", "5", ""),
+        ] {
+            assert_eq!(
+                run(&db, &["GETRANGE", "k", start, end]).await,
+                Frame::Bulk(Bytes::from(expected)),
+                "GETRANGE k {} {}",
+                start,
+                end
+            );
+        }
+        assert_eq!(
+            run(&db, &["GETRANGE", "missing", "0", "-1"]).await,
+            Frame::Bulk(Bytes::new())
+        );
+    }
+}
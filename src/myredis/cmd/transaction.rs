@@ -0,0 +1,59 @@
+//! Transaction commands.
+
+use super::{Command, Parse, ParseError};
+
+/// MULTI
+pub(super) fn multi(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Multi)
+}
+
+/// EXEC
+pub(super) fn exec(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Exec)
+}
+
+/// DISCARD
+pub(super) fn discard(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Discard)
+}
+
+/// WATCH key [key ...]
+pub(super) fn watch(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Watch {
+        keys: parse.rest_strings()?,
+    })
+}
+
+/// UNWATCH
+pub(super) fn unwatch(_parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Unwatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::cmd::tests::{parse, wrong_arity};
+
+    #[test]
+    fn transaction_commands() {
+        assert_eq!(parse(&["Multi"]), Ok(Command::Multi));
+        assert_eq!(parse(&["EXEC"]), Ok(Command::Exec));
+        assert_eq!(parse(&["discard"]), Ok(Command::Discard));
+        assert_eq!(parse(&["unwatch"]), Ok(Command::Unwatch));
+        assert_eq!(
+            parse(&["watch", "a", "b"]),
+            Ok(Command::Watch {
+                keys: vec!["a".to_string(), "b".to_string()]
+            })
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["multi", "x"]), wrong_arity("multi"));
+        assert_eq!(parse(&["exec", "x"]), wrong_arity("exec"));
+        assert_eq!(parse(&["discard", "x"]), wrong_arity("discard"));
+        assert_eq!(parse(&["unwatch", "x"]), wrong_arity("unwatch"));
+        assert_eq!(parse(&["watch"]), wrong_arity("watch"));
+    }
+}
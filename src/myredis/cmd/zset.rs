@@ -0,0 +1,794 @@
+//! Sorted set commands.
+
+use std::{cmp::Ordering, ops::Bound};
+
+use bytes::Bytes;
+
+use super::{list, Command, Parse, ParseError, SetCondition};
+use crate::myredis::{
+    db::Keyspace,
+    frame::Frame,
+    parse::{format_float, parse_float, parse_int},
+    zset::{LexBound, SortedSet},
+    Error,
+};
+
+/// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zadd {
+    pub key: String,
+    /// NX: only add new members, XX: only update existing members
+    pub condition: Option<SetCondition>,
+    /// GT or LT: only update a member if its new score compares so to its current score
+    pub comparison: Option<Ordering>,
+    /// CH: reply with the number of members that were added or whose score changed
+    pub changed: bool,
+    /// INCR: increment the score of the single member and reply with the new score
+    pub incr: bool,
+    pub members: Vec<(f64, Bytes)>,
+}
+
+/// ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zrange {
+    pub key: String,
+    pub by: ZrangeBy,
+    /// Walk from the highest score down
+    pub rev: bool,
+    /// Skip `offset` members then return up to `count` members, all of them if `count` is
+    /// negative
+    pub limit: Option<(i64, i64)>,
+    pub with_scores: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZrangeBy {
+    /// Inclusive range of ranks, negative ranks count from the end
+    Rank(i64, i64),
+    /// BYSCORE: range of scores, from min to max
+    Score(Bound<f64>, Bound<f64>),
+    /// BYLEX: range of members, from min to max
+    Lex(LexBound, LexBound),
+}
+
+pub(super) fn zadd(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let (mut nx, mut xx, mut gt, mut lt, mut changed, mut incr) = Default::default();
+
+    // 选项都在第一个分数之前
+    let first = loop {
+        let arg = parse.next_bytes().map_err(|_| ParseError::Syntax)?;
+        match &arg.to_ascii_uppercase()[..] {
+            b"NX" => nx = true,
+            b"XX" => xx = true,
+            b"GT" => gt = true,
+            b"LT" => lt = true,
+            b"CH" => changed = true,
+            b"INCR" => incr = true,
+            _ => break arg,
+        }
+    };
+    if parse.remaining() % 2 != 1 {
+        return Err(ParseError::Syntax);
+    }
+    let mut pairs = vec![(first, parse.next_bytes()?)];
+    while parse.remaining() > 0 {
+        pairs.push((parse.next_bytes()?, parse.next_bytes()?));
+    }
+
+    let invalid = |msg: &str| Err(ParseError::Invalid(msg.to_string()));
+    if incr && pairs.len() > 1 {
+        return invalid("ERR INCR option supports a single increment-element pair");
+    }
+    if nx && xx {
+        return invalid("ERR XX and NX options at the same time are not compatible");
+    }
+    if (nx && (gt || lt)) || (gt && lt) {
+        return invalid("ERR GT, LT, and/or NX options at the same time are not compatible");
+    }
+
+    let members = pairs
+        .into_iter()
+        .map(|(score, member)| Ok((parse_float(&score).ok_or(ParseError::NotFloat)?, member)))
+        .collect::<Result<_, ParseError>>()?;
+    Ok(Command::Zadd(Zadd {
+        key,
+        condition: match (nx, xx) {
+            (true, _) => Some(SetCondition::NotExists),
+            (_, true) => Some(SetCondition::Exists),
+            _ => None,
+        },
+        comparison: match (gt, lt) {
+            (true, _) => Some(Ordering::Greater),
+            (_, true) => Some(Ordering::Less),
+            _ => None,
+        },
+        changed,
+        incr,
+        members,
+    }))
+}
+
+/// ZINCRBY key increment member, which is `ZADD key INCR increment member`
+pub(super) fn zincrby(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let increment = parse.next_float()?;
+    Ok(Command::Zadd(Zadd {
+        key,
+        condition: None,
+        comparison: None,
+        changed: false,
+        incr: true,
+        members: vec![(increment, parse.next_bytes()?)],
+    }))
+}
+
+pub(super) fn zrange(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let (start, stop) = (parse.next_bytes()?, parse.next_bytes()?);
+
+    let (mut by_score, mut by_lex, mut rev, mut limit, mut with_scores) = Default::default();
+    while parse.remaining() > 0 {
+        match &parse.next_flag()?[..] {
+            "BYSCORE" => (by_score, by_lex) = (true, false),
+            "BYLEX" => (by_score, by_lex) = (false, true),
+            "REV" => rev = true,
+            "LIMIT" => limit = Some((parse.next_int()?, parse.next_int()?)),
+            "WITHSCORES" => with_scores = true,
+            _ => return Err(ParseError::Syntax),
+        }
+    }
+
+    let invalid = |msg: &str| ParseError::Invalid(msg.to_string());
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(invalid(
+            "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        ));
+    }
+    if with_scores && by_lex {
+        return Err(invalid(
+            "ERR syntax error, WITHSCORES not supported in combination with BYLEX",
+        ));
+    }
+
+    // 和 redis 一样，按分数或字典序倒序时先给出最大值
+    let (min, max) = if rev {
+        (stop.clone(), start.clone())
+    } else {
+        (start.clone(), stop.clone())
+    };
+    let by = if by_score {
+        let bound =
+            |arg: &Bytes| score_bound(arg).ok_or_else(|| invalid("ERR min or max is not a float"));
+        ZrangeBy::Score(bound(&min)?, bound(&max)?)
+    } else if by_lex {
+        let bound = |arg: Bytes| {
+            lex_bound(arg).ok_or_else(|| invalid("ERR min or max not valid string range item"))
+        };
+        ZrangeBy::Lex(bound(min)?, bound(max)?)
+    } else {
+        let rank = |arg: &Bytes| parse_int(arg).ok_or(ParseError::NotInteger);
+        ZrangeBy::Rank(rank(&start)?, rank(&stop)?)
+    };
+
+    Ok(Command::Zrange(Zrange {
+        key,
+        by,
+        rev,
+        limit,
+        with_scores,
+    }))
+}
+
+/// ZRANK key member [WITHSCORE]
+pub(super) fn zrank(parse: &mut Parse) -> Result<Command, ParseError> {
+    let key = parse.next_string()?;
+    let member = parse.next_bytes()?;
+    let with_score = match parse.remaining() {
+        0 => false,
+        _ if parse.next_flag()? == "WITHSCORE" => true,
+        _ => return Err(ParseError::Syntax),
+    };
+    Ok(Command::Zrank {
+        key,
+        member,
+        with_score,
+    })
+}
+
+/// ZREM key member [member ...]
+pub(super) fn zrem(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Zrem {
+        key: parse.next_string()?,
+        members: parse.rest_bytes()?,
+    })
+}
+
+/// ZCARD key
+pub(super) fn zcard(parse: &mut Parse) -> Result<Command, ParseError> {
+    Ok(Command::Zcard {
+        key: parse.next_string()?,
+    })
+}
+
+/// `(score` is exclusive, `score` is inclusive, `-inf` and `+inf` are valid scores
+fn score_bound(arg: &[u8]) -> Option<Bound<f64>> {
+    match arg.strip_prefix(b"(") {
+        Some(score) => parse_float(score).map(Bound::Excluded),
+        None => parse_float(arg).map(Bound::Included),
+    }
+}
+
+/// `[member` is inclusive, `(member` is exclusive, `-` and `+` are the infinities
+fn lex_bound(arg: Bytes) -> Option<LexBound> {
+    match arg.first()? {
+        b'-' if arg.len() == 1 => Some(LexBound::Min),
+        b'+' if arg.len() == 1 => Some(LexBound::Max),
+        b'[' => Some(LexBound::Included(arg.slice(1..))),
+        b'(' => Some(LexBound::Excluded(arg.slice(1..))),
+        _ => None,
+    }
+}
+
+/// Reply with the number of added members, or with the new score for INCR
+pub(super) fn apply_zadd(db: &impl Keyspace, zadd: Zadd) -> Result<Frame, Error> {
+    let key = &zadd.key;
+    db.with_shard(key, |shard| {
+        // 没有任何成员被加入时(比如 XX)，不会留下一个空的有序集合
+        let reply = add(shard.sorted_set_or_insert(key)?, &zadd);
+        shard.remove_if_empty(key);
+        reply
+    })
+}
+
+fn add(zset: &mut SortedSet, zadd: &Zadd) -> Result<Frame, Error> {
+    let (mut added, mut updated) = (0, 0);
+    let mut score = None;
+
+    for (increment, member) in &zadd.members {
+        let current = zset.score(member);
+        match (current, zadd.condition) {
+            (Some(_), Some(SetCondition::NotExists)) | (None, Some(SetCondition::Exists)) => {
+                continue
+            }
+            _ => {}
+        }
+
+        let new = match current {
+            Some(current) if zadd.incr => current + increment,
+            _ => *increment,
+        };
+        if new.is_nan() {
+            return Err("ERR resulting score is not a number (NaN)".into());
+        }
+        if let (Some(current), Some(comparison)) = (current, zadd.comparison) {
+            if new.partial_cmp(&current) != Some(comparison) {
+                continue;
+            }
+        }
+
+        if zset.insert(member.clone(), new) {
+            added += 1;
+        } else if current != Some(new) {
+            updated += 1;
+        }
+        score = Some(new);
+    }
+
+    Ok(if zadd.incr {
+        // 条件不满足时 INCR 回复 nil
+        score.map_or(Frame::Null, Frame::Double)
+    } else if zadd.changed {
+        Frame::Integer(added + updated)
+    } else {
+        Frame::Integer(added)
+    })
+}
+
+/// Reply with the members in the range, each followed by its score with WITHSCORES
+pub(super) fn apply_zrange(db: &impl Keyspace, zrange: &Zrange) -> Result<Frame, Error> {
+    db.with_shard(&zrange.key, |shard| {
+        let Some(zset) = shard.sorted_set(&zrange.key)? else {
+            return Ok(Frame::Array(Vec::new()));
+        };
+        let rev = zrange.rev;
+
+        let members: Vec<_> = match &zrange.by {
+            ZrangeBy::Rank(start, stop) => match list::range(zset.len(), *start, *stop) {
+                Some((start, stop)) => zset
+                    .iter_from_rank(start, rev)
+                    .take(stop - start + 1)
+                    .collect(),
+                None => Vec::new(),
+            },
+            ZrangeBy::Score(min, max) => limit(zset.range_by_score(*min, *max, rev), zrange.limit),
+            ZrangeBy::Lex(min, max) => limit(
+                zset.range_by_lex(min.clone(), max.clone(), rev),
+                zrange.limit,
+            ),
+        };
+
+        let elements = members.into_iter().flat_map(|(member, score)| {
+            let score = zrange.with_scores.then(|| Bytes::from(format_float(score)));
+            [member.clone()].into_iter().chain(score)
+        });
+        Ok(Frame::bulks(elements))
+    })
+}
+
+/// Apply LIMIT offset count, a negative offset gives nothing and a negative count gives all
+fn limit<'a>(
+    members: impl Iterator<Item = (&'a Bytes, f64)>,
+    limit: Option<(i64, i64)>,
+) -> Vec<(&'a Bytes, f64)> {
+    let (offset, count) = limit.unwrap_or((0, -1));
+    let Ok(offset) = usize::try_from(offset) else {
+        return Vec::new();
+    };
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    members.skip(offset).take(count).collect()
+}
+
+/// Reply with the rank of the member, and its score with WITHSCORE
+pub(super) fn apply_zrank(
+    db: &impl Keyspace,
+    key: &str,
+    member: &[u8],
+    with_score: bool,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(zset) = shard.sorted_set(key)? else {
+            return Ok(Frame::Null);
+        };
+        let (Some(rank), Some(score)) = (zset.rank(member), zset.score(member)) else {
+            return Ok(Frame::Null);
+        };
+        Ok(match with_score {
+            true => Frame::Array(vec![Frame::Integer(rank as i64), Frame::Double(score)]),
+            false => Frame::Integer(rank as i64),
+        })
+    })
+}
+
+/// Reply with the number of members that were removed, deleting the key if none is left
+pub(super) fn apply_zrem(
+    db: &impl Keyspace,
+    key: &str,
+    members: Vec<Bytes>,
+) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let Some(zset) = shard.sorted_set(key)? else {
+            return Ok(Frame::Integer(0));
+        };
+        let removed = members.iter().filter(|member| zset.remove(member)).count();
+        shard.remove_if_empty(key);
+        Ok(Frame::Integer(removed as i64))
+    })
+}
+
+pub(super) fn apply_zcard(db: &impl Keyspace, key: &str) -> Result<Frame, Error> {
+    db.with_shard(key, |shard| {
+        let len = shard.sorted_set(key)?.map_or(0, |zset| zset.len());
+        Ok(Frame::Integer(len as i64))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::{
+        cmd::tests::{parse, run, wrong_arity},
+        db::Db,
+    };
+
+    fn zadd(members: Vec<(f64, Bytes)>) -> Zadd {
+        Zadd {
+            key: "z".to_string(),
+            condition: None,
+            comparison: None,
+            changed: false,
+            incr: false,
+            members,
+        }
+    }
+
+    fn zrange(by: ZrangeBy) -> Zrange {
+        Zrange {
+            key: "z".to_string(),
+            by,
+            rev: false,
+            limit: None,
+            with_scores: false,
+        }
+    }
+
+    #[test]
+    fn zadd_options() {
+        assert_eq!(
+            parse(&["zadd", "z", "1", "a", "2.5", "b"]),
+            Ok(Command::Zadd(zadd(vec![
+                (1.0, "a".into()),
+                (2.5, "b".into())
+            ])))
+        );
+        assert_eq!(
+            parse(&["ZADD", "z", "xx", "Gt", "ch", "1", "a"]),
+            Ok(Command::Zadd(Zadd {
+                condition: Some(SetCondition::Exists),
+                comparison: Some(Ordering::Greater),
+                changed: true,
+                ..zadd(vec![(1.0, "a".into())])
+            }))
+        );
+        assert_eq!(
+            parse(&["zadd", "z", "NX", "incr", "-inf", "a"]),
+            Ok(Command::Zadd(Zadd {
+                condition: Some(SetCondition::NotExists),
+                incr: true,
+                ..zadd(vec![(f64::NEG_INFINITY, "a".into())])
+            }))
+        );
+        assert_eq!(
+            parse(&["zincrby", "z", "2", "a"]),
+            Ok(Command::Zadd(Zadd {
+                incr: true,
+                ..zadd(vec![(2.0, "a".into())])
+            }))
+        );
+    }
+
+    #[test]
+    fn zadd_conflicting_options() {
+        let invalid = |msg: &str| Err(msg.to_string());
+        assert_eq!(
+            parse(&["zadd", "z", "NX", "XX", "1", "m"]),
+            invalid("ERR XX and NX options at the same time are not compatible")
+        );
+        let incompatible =
+            invalid("ERR GT, LT, and/or NX options at the same time are not compatible");
+        assert_eq!(parse(&["zadd", "z", "NX", "GT", "1", "m"]), incompatible);
+        assert_eq!(parse(&["zadd", "z", "nx", "lt", "1", "m"]), incompatible);
+        assert_eq!(parse(&["zadd", "z", "GT", "LT", "1", "m"]), incompatible);
+        assert_eq!(
+            parse(&["zadd", "z", "INCR", "1", "a", "2", "b"]),
+            invalid("ERR INCR option supports a single increment-element pair")
+        );
+    }
+
+    #[test]
+    fn zadd_scores() {
+        let not_float = Err("ERR value is not a valid float".to_string());
+        assert_eq!(parse(&["zadd", "z", "one", "a"]), not_float);
+        assert_eq!(parse(&["zadd", "z", "1", "a", "nan", "b"]), not_float);
+        assert_eq!(parse(&["zincrby", "z", "x", "a"]), not_float);
+        // 分数和成员不成对
+        let syntax = Err("ERR syntax error".to_string());
+        assert_eq!(parse(&["zadd", "z", "1", "a", "2"]), syntax);
+        assert_eq!(parse(&["zadd", "z", "NX", "CH"]), syntax);
+    }
+
+    #[test]
+    fn zrange_options() {
+        assert_eq!(
+            parse(&["zrange", "z", "0", "-1", "withscores"]),
+            Ok(Command::Zrange(Zrange {
+                with_scores: true,
+                ..zrange(ZrangeBy::Rank(0, -1))
+            }))
+        );
+        assert_eq!(
+            parse(&["ZRANGE", "z", "(1", "+inf", "ByScore", "limit", "1", "2"]),
+            Ok(Command::Zrange(Zrange {
+                limit: Some((1, 2)),
+                ..zrange(ZrangeBy::Score(
+                    Bound::Excluded(1.0),
+                    Bound::Included(f64::INFINITY)
+                ))
+            }))
+        );
+        // REV 时先给出最大值
+        assert_eq!(
+            parse(&["zrange", "z", "+", "[a", "bylex", "rev"]),
+            Ok(Command::Zrange(Zrange {
+                rev: true,
+                ..zrange(ZrangeBy::Lex(LexBound::Included("a".into()), LexBound::Max))
+            }))
+        );
+    }
+
+    #[test]
+    fn zrange_errors() {
+        let invalid = |msg: &str| Err(msg.to_string());
+        assert_eq!(
+            parse(&["zrange", "z", "0", "-1", "LIMIT", "0", "1"]),
+            invalid(
+                "ERR syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+            )
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "-", "+", "BYLEX", "WITHSCORES"]),
+            invalid("ERR syntax error, WITHSCORES not supported in combination with BYLEX")
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "low", "1", "BYSCORE"]),
+            invalid("ERR min or max is not a float")
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "a", "+", "BYLEX"]),
+            invalid("ERR min or max not valid string range item")
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "0", "x"]),
+            invalid("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "0", "1", "BYSCORE", "LIMIT", "0", "x"]),
+            invalid("ERR value is not an integer or out of range")
+        );
+        assert_eq!(
+            parse(&["zrange", "z", "0", "1", "BYRANK"]),
+            invalid("ERR syntax error")
+        );
+    }
+
+    #[test]
+    fn zrank() {
+        assert_eq!(
+            parse(&["zrank", "z", "a", "withscore"]),
+            Ok(Command::Zrank {
+                key: "z".to_string(),
+                member: "a".into(),
+                with_score: true
+            })
+        );
+        assert_eq!(
+            parse(&["zrank", "z", "a", "WITHSCORES"]),
+            Err("ERR syntax error".to_string())
+        );
+    }
+
+    #[test]
+    fn arity() {
+        assert_eq!(parse(&["zadd", "z", "1"]), wrong_arity("zadd"));
+        assert_eq!(parse(&["zincrby", "z", "1"]), wrong_arity("zincrby"));
+        assert_eq!(parse(&["zrange", "z", "0"]), wrong_arity("zrange"));
+        assert_eq!(parse(&["zrank", "z"]), wrong_arity("zrank"));
+        assert_eq!(parse(&["zrem", "z"]), wrong_arity("zrem"));
+        assert_eq!(parse(&["zcard", "z", "y"]), wrong_arity("zcard"));
+    }
+
+    /// A database holding the sorted set `z` with the members a to e scored 1 to 5
+    async fn db() -> Db {
+        let db = Db::new();
+        let args = [
+            "ZADD", "z", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e",
+        ];
+        run(&db, &args).await;
+        db
+    }
+
+    #[tokio::test]
+    async fn zadd_conditions() {
+        let db = db().await;
+        assert_eq!(
+            run(&db, &["ZADD", "z", "10", "a", "6", "f"]).await,
+            Frame::Integer(1)
+        );
+        // CH 也计入分数被修改的成员，分数没变的不算
+        assert_eq!(
+            run(&db, &["ZADD", "z", "CH", "10", "a", "7", "b", "8", "g"]).await,
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&db, &["ZADD", "z", "NX", "0", "a", "9", "h"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&db, &["ZADD", "z", "XX", "CH", "0", "c", "0", "x"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(
+                &db,
+                &["ZADD", "z", "GT", "CH", "1", "d", "5", "d", "11", "new"]
+            )
+            .await,
+            Frame::Integer(2)
+        );
+        assert_eq!(
+            run(&db, &["ZADD", "z", "LT", "CH", "6", "e", "4", "e"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "0", "-1", "WITHSCORES"]).await,
+            Frame::bulks([
+                "c", "0", "e", "4", "d", "5", "f", "6", "b", "7", "g", "8", "h", "9", "a", "10",
+                "new", "11"
+            ])
+        );
+
+        // XX 没有加入任何成员时不会创建键
+        assert_eq!(
+            run(&db, &["ZADD", "other", "XX", "1", "a"]).await,
+            Frame::Integer(0)
+        );
+        assert_eq!(run(&db, &["EXISTS", "other"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn zadd_incr() {
+        let db = db().await;
+        assert_eq!(
+            run(&db, &["ZINCRBY", "z", "1.5", "a"]).await,
+            Frame::Double(2.5)
+        );
+        assert_eq!(
+            run(&db, &["ZADD", "z", "INCR", "-1", "x"]).await,
+            Frame::Double(-1.0)
+        );
+        // 条件不满足时回复 nil
+        assert_eq!(
+            run(&db, &["ZADD", "z", "INCR", "NX", "1", "a"]).await,
+            Frame::Null
+        );
+        assert_eq!(
+            run(&db, &["ZADD", "z", "INCR", "GT", "-1", "a"]).await,
+            Frame::Null
+        );
+        run(&db, &["ZADD", "z", "inf", "top"]).await;
+        assert_eq!(
+            run(&db, &["ZINCRBY", "z", "-inf", "top"]).await,
+            Frame::Error("ERR resulting score is not a number (NaN)".to_string())
+        );
+        assert_eq!(
+            run(&db, &["ZRANK", "z", "top", "WITHSCORE"]).await,
+            Frame::Array(vec![Frame::Integer(6), Frame::Double(f64::INFINITY)])
+        );
+    }
+
+    #[tokio::test]
+    async fn zrange_by_rank() {
+        let db = db().await;
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "1", "-2"]).await,
+            Frame::bulks(["b", "c", "d"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "-100", "1", "WITHSCORES"]).await,
+            Frame::bulks(["a", "1", "b", "2"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "0", "1", "REV"]).await,
+            Frame::bulks(["e", "d"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "3", "100", "REV"]).await,
+            Frame::bulks(["b", "a"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "3", "1"]).await,
+            Frame::Array(Vec::new())
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "5", "10"]).await,
+            Frame::Array(Vec::new())
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "missing", "0", "-1"]).await,
+            Frame::Array(Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn zrange_by_score() {
+        let db = db().await;
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "2", "(4", "BYSCORE"]).await,
+            Frame::bulks(["b", "c"])
+        );
+        assert_eq!(
+            run(
+                &db,
+                &["ZRANGE", "z", "-inf", "+inf", "BYSCORE", "LIMIT", "1", "2"]
+            )
+            .await,
+            Frame::bulks(["b", "c"])
+        );
+        assert_eq!(
+            run(
+                &db,
+                &["ZRANGE", "z", "(1", "3", "BYSCORE", "LIMIT", "1", "-1"]
+            )
+            .await,
+            Frame::bulks(["c"])
+        );
+        // 倒序时先给出最大值
+        assert_eq!(
+            run(
+                &db,
+                &["ZRANGE", "z", "4", "(2", "BYSCORE", "REV", "WITHSCORES"]
+            )
+            .await,
+            Frame::bulks(["d", "4", "c", "3"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "+inf", "(4", "BYSCORE", "REV"]).await,
+            Frame::bulks(["e"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "(3", "(3", "BYSCORE"]).await,
+            Frame::Array(Vec::new())
+        );
+        assert_eq!(
+            run(
+                &db,
+                &["ZRANGE", "z", "0", "10", "BYSCORE", "LIMIT", "-1", "2"]
+            )
+            .await,
+            Frame::Array(Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn zrange_by_lex() {
+        let db = Db::new();
+        let args = [
+            "ZADD", "z", "0", "apple", "0", "banana", "0", "cherry", "0", "date",
+        ];
+        run(&db, &args).await;
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "[banana", "(date", "BYLEX"]).await,
+            Frame::bulks(["banana", "cherry"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "-", "[c", "BYLEX"]).await,
+            Frame::bulks(["apple", "banana"])
+        );
+        assert_eq!(
+            run(
+                &db,
+                &["ZRANGE", "z", "+", "(banana", "BYLEX", "REV", "LIMIT", "0", "2"]
+            )
+            .await,
+            Frame::bulks(["date", "cherry"])
+        );
+        assert_eq!(
+            run(&db, &["ZRANGE", "z", "+", "-", "BYLEX"]).await,
+            Frame::Array(Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn zrank_zrem_and_zcard() {
+        let db = db().await;
+        assert_eq!(run(&db, &["ZRANK", "z", "c"]).await, Frame::Integer(2));
+        assert_eq!(
+            run(&db, &["ZRANK", "z", "e", "WITHSCORE"]).await,
+            Frame::Array(vec![Frame::Integer(4), Frame::Double(5.0)])
+        );
+        assert_eq!(run(&db, &["ZRANK", "z", "x"]).await, Frame::Null);
+        assert_eq!(run(&db, &["ZRANK", "missing", "a"]).await, Frame::Null);
+
+        assert_eq!(
+            run(&db, &["ZREM", "z", "a", "c", "x"]).await,
+            Frame::Integer(2)
+        );
+        assert_eq!(run(&db, &["ZRANK", "z", "d"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["ZCARD", "z"]).await, Frame::Integer(3));
+        // 移除最后一个成员时键也被删除
+        assert_eq!(
+            run(&db, &["ZREM", "z", "b", "d", "e"]).await,
+            Frame::Integer(3)
+        );
+        assert_eq!(run(&db, &["EXISTS", "z"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["ZCARD", "z"]).await, Frame::Integer(0));
+
+        run(&db, &["SET", "s", "v"]).await;
+        assert_eq!(
+            run(&db, &["ZADD", "s", "1", "a"]).await,
+            Frame::Error(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            )
+        );
+    }
+}
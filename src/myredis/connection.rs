@@ -1,55 +1,63 @@
-use std::io::Cursor;
-
-use bytes::{Buf, BytesMut};
-use mini_redis::frame::Error::Incomplete;
-use mini_redis::{Frame, Result};
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
 
+use super::decoder::Decoder;
+use super::frame::{Frame, Limits, Protocol};
+use super::Result;
+
 const BUFFER_LEN: usize = 4096;
 
-pub struct Connection {
-    stream: BufWriter<TcpStream>,
+/// Reads and writes frames on a stream, a `TcpStream` or any other byte stream such as a TLS
+/// stream
+pub struct Connection<S = TcpStream> {
+    stream: BufWriter<S>,
     // 对frame进行读写的缓冲区，这里使用 BytesMut 作为缓冲区类型，它是 Bytes 的可变版本。
     buffer: BytesMut,
+    // 从缓冲区里解析帧
+    decoder: Decoder,
+    // 写出的帧使用的协议版本
+    protocol: Protocol,
+    // 读取的帧的限制
+    limits: Limits,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Self {
         Connection {
             stream: BufWriter::new(stream),
             buffer: BytesMut::with_capacity(BUFFER_LEN),
+            decoder: Decoder::new(),
+            protocol: Protocol::default(),
+            limits: Limits::default(),
         }
     }
 
+    /// Read the next frames within `limits`, instead of the default limits
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// The protocol the frames are written in, RESP2 until `set_protocol` is called
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Write the next frames in `protocol`, the frames are read in any protocol
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
     // 解析一个帧。
     // 如果缓冲区有足够一个帧的数据，则解析并返回，然后把这个帧的数据从缓冲区移除；
-    // 如果数据不足一个帧，则返回 Ok(None)；
+    // 如果数据不足一个帧，则返回 Ok(None)，已经解析的部分由解码器保存，读到更多数据以后接着解析；
     // 如果数据错误，则返回 Err
+    // 不是以帧类型开头的数据是内联命令，例如在 telnet 里直接输入的 `GET foo`
     fn parse_frame(&mut self) -> Result<Option<Frame>> {
-        let mut buf: Cursor<&[u8]> = Cursor::new(&self.buffer[..]);
-
-        match Frame::check(&mut buf) {
-            Ok(_) => {
-                let frame_len = buf.position();
-
-                // 解析之前游标先移到初始位置
-                buf.set_position(0);
-
-                // 解析帧
-                let frame = Frame::parse(&mut buf)?;
-
-                // 解析完了以后，把缓冲区里这个帧的数据移除
-                self.buffer.advance(frame_len as usize);
-
-                Ok(Some(frame))
-            }
-            Err(Incomplete) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+        Ok(self.decoder.decode(&mut self.buffer, &self.limits)?)
     }
 
-    // 当 read_frame 的底层调用 TcpStream::read 读取到部分帧时，会将数据先缓冲起来，接着继续等待并读取数据。
+    // 当 read_frame 的底层调用流的 read 读取到部分帧时，会将数据先缓冲起来，接着继续等待并读取数据。
     // 如果读到多个帧，那第一个帧会被返回，然后剩下的数据依然被缓冲起来，等待下一次 read_frame 被调用。
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
         loop {
@@ -58,14 +66,20 @@ impl Connection {
                 return Ok(Some(frame));
             }
 
+            // 缓冲了这么多数据还不是一个完整的帧，对端可能永远不会把它发完，例如一行永远没有结尾
+            if self.decoder.pending_len() + self.buffer.len() >= self.limits.max_buffer_len {
+                return Err("too big request".into());
+            }
+
             // 如果缓冲区中的数据还不足一个数据帧，那么我们需要从 socket 中读取更多的数据
             //
             // 读取成功时，会返回读取到的字节数，0 代表着读到了数据流的末尾
             if 0 == self.stream.read_buf(&mut self.buffer).await? {
                 // 代码能执行到这里，说明了对端关闭了连接，
                 // 需要看看缓冲区是否还有数据，若没有数据，说明所有数据成功被处理，
-                // 若还有数据，说明对端在发送帧的过程中断开了连接，导致只发送了部分数据
-                if self.buffer.is_empty() {
+                // 若还有数据，说明对端在发送帧的过程中断开了连接，导致只发送了部分数据，
+                // 这部分数据可能已经被解码器取走了
+                if self.buffer.is_empty() && self.decoder.pending_len() == 0 {
                     return Ok(None);
                 } else {
                     return Err("connection reset by peer".into());
@@ -74,54 +88,138 @@ impl Connection {
         }
     }
 
+    /// Parse a frame the peer already sent without reading the socket, `Ok(None)` when the
+    /// buffered data does not hold a whole frame.
+    ///
+    /// A pipelining peer sends several frames before waiting for the replies, they are all read
+    /// into the buffer at once.
+    pub fn read_buffered_frame(&mut self) -> Result<Option<Frame>> {
+        self.parse_frame()
+    }
+
     // 为了降低系统调用的次数，我们需要使用一个写入缓冲区，当写入一个帧时，首先会写入该缓冲区，
     // 然后等缓冲区数据足够多时，再集中将其中的数据写入到 socket 中，这样就将多次系统调用优化减少到一次。
     pub async fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        match frame {
-            Frame::Simple(val) => {
-                self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Error(val) => {
-                self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(val) => {
-                self.stream.write_u8(b':').await?;
-                self.write_decimal(*val).await?;
-            }
-            Frame::Bulk(val) => {
-                let len = val.len();
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
-                self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Null => {
-                self.stream.write_all(b"$-1\r\n").await?;
-            }
-            Frame::Array(_) => todo!(),
-        }
-        self.stream.flush().await?;
+        self.write_frame_buffered(frame).await?;
+        self.flush().await
+    }
+
+    /// Write a frame to the write buffer without sending it, `flush` sends the buffered frames.
+    /// The buffer is only written to the socket early when it is full.
+    pub async fn write_frame_buffered(&mut self, frame: &Frame) -> Result<()> {
+        let mut buf = Vec::new();
+        frame.encode(self.protocol, &mut buf);
+        self.stream.write_all(&buf).await?;
+        Ok(())
+    }
 
+    /// Send the frames written by `write_frame_buffered`
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.flush().await?;
         Ok(())
     }
+}
 
-    /// Write a decimal frame to the stream
-    async fn write_decimal(&mut self, val: u64) -> Result<()> {
-        use std::io::Write;
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
 
-        // Convert the value to a string
-        let mut buf = [0u8; 20];
-        let mut buf = Cursor::new(&mut buf[..]);
-        write!(&mut buf, "{}", val)?;
+    use bytes::Bytes;
+    use tokio::io::{duplex, DuplexStream};
 
-        let pos = buf.position() as usize;
-        self.stream.write_all(&buf.get_ref()[..pos]).await?;
-        self.stream.write_all(b"\r\n").await?;
+    use super::*;
 
-        Ok(())
+    const LIMITS: Limits = Limits {
+        max_bulk_len: 16,
+        max_array_len: 4,
+        max_depth: 2,
+        max_buffer_len: 64,
+    };
+
+    /// A connection with small limits, and the stream of its peer
+    fn connection() -> (Connection<DuplexStream>, DuplexStream) {
+        let (stream, peer) = duplex(1024);
+        let mut connection = Connection::new(stream);
+        connection.set_limits(LIMITS);
+        (connection, peer)
+    }
+
+    /// The error of reading what the peer sent, the peer is kept open
+    async fn read_error(data: &[u8]) -> String {
+        let (mut connection, mut peer) = connection();
+        peer.write_all(data).await.unwrap();
+        match connection.read_frame().await {
+            Ok(frame) => panic!("read {:?}", frame),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    /// Whether reading what the peer sent waits for more data
+    async fn waits(data: &[u8]) -> bool {
+        let (mut connection, mut peer) = connection();
+        peer.write_all(data).await.unwrap();
+        let read = tokio::time::timeout(Duration::from_millis(20), connection.read_frame());
+        read.await.is_err()
+    }
+
+    #[tokio::test]
+    async fn frames_within_the_limits() {
+        let (mut connection, mut peer) = connection();
+        let value = Bytes::from("x".repeat(16));
+        let frame = Frame::bulks([Bytes::from("SET"), Bytes::from("k"), value]);
+        let mut buf = Vec::new();
+        frame.encode(Protocol::Resp2, &mut buf);
+        buf.extend_from_slice(b"*1\r\n*1\r\n:1\r\n");
+        peer.write_all(&buf).await.unwrap();
+
+        assert_eq!(connection.read_frame().await.unwrap(), Some(frame));
+        let nested = Frame::Array(vec![Frame::Array(vec![Frame::Integer(1)])]);
+        assert_eq!(connection.read_frame().await.unwrap(), Some(nested));
+    }
+
+    #[tokio::test]
+    async fn bulk_length() {
+        // 长度一到就出错，不等数据到达
+        assert_eq!(
+            read_error(b"*2\r\n$3\r\nGET\r\n$17\r\n").await,
+            "invalid bulk length"
+        );
+        assert_eq!(read_error(b"!17\r\n").await, "invalid bulk length");
+        assert!(waits(b"$16\r\nxxx").await);
+    }
+
+    #[tokio::test]
+    async fn array_length() {
+        assert_eq!(read_error(b"*5\r\n").await, "invalid multibulk length");
+        assert_eq!(read_error(b"%5\r\n").await, "invalid multibulk length");
+        assert!(waits(b"*4\r\n").await);
+    }
+
+    #[tokio::test]
+    async fn depth() {
+        assert_eq!(read_error(b"*1\r\n*1\r\n*1\r\n").await, "too deep nesting");
+        assert_eq!(read_error(b"*1\r\n~1\r\n>1\r\n").await, "too deep nesting");
+        assert!(waits(b"*1\r\n*1\r\n").await);
+    }
+
+    #[tokio::test]
+    async fn unterminated_line() {
+        let line = vec![b'a'; 64];
+        // 永远没有结尾的内联命令和 RESP 的行都会出错
+        assert_eq!(read_error(&line).await, "too big request");
+        let mut simple = line.clone();
+        simple[0] = b'+';
+        assert_eq!(read_error(&simple).await, "too big request");
+        assert!(waits(&line[..63]).await);
+
+        // 一次读取就可能超过限制，已经收到的帧还是可以读出来
+        let (mut connection, mut peer) = connection();
+        let mut data = b"PING\r\n".to_vec();
+        data.extend_from_slice(&line);
+        peer.write_all(&data).await.unwrap();
+        let ping = connection.read_frame().await.unwrap();
+        assert_eq!(ping, Some(Frame::bulks(["PING"])));
+        let err = connection.read_frame().await.unwrap_err();
+        assert_eq!(err.to_string(), "too big request");
     }
 }
@@ -0,0 +1,1921 @@
+//! Database.
+//! 键值对按键的哈希值分散到多个分片(shard)中，每个分片有自己的锁，不同分片上的命令可以并发执行。
+//! 每个分片的键值对保存在 `HashMap` 中，设置了过期时间的键还会被记录在一个可以随机取样的集合里。
+//! 过期的键有两种删除方式：读取时发现已经过期就删除(惰性删除)，以及后台任务定期随机取样删除(主动删除)，
+//! 和 redis 的做法一样。
+//! 阻塞在列表上的客户端按先来后到的顺序排队，有元素被推入列表时，直接把元素交给排在最前面的客户端。
+//! BLMOVE 的客户端要同时修改两个列表，推入元素的命令只把列表标记为就绪，之后在同时锁住两个列表的分片时移动元素。
+//! 开启追加日志时，交给阻塞的客户端的元素被记录下来，由推入元素的命令和它一起写进日志。
+
+use std::{
+    cell::RefCell,
+    collections::{hash_map::RandomState, HashMap, HashSet, VecDeque},
+    fmt,
+    future::Future,
+    hash::BuildHasher,
+    mem,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::sync::oneshot;
+
+use super::zset::SortedSet;
+use crate::timer_future::Clock;
+
+/// The number of shards of `Db::new` and `Db::with_clock`
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// How often the background task samples keys with a time to live
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of keys sampled in one round of the background task
+const SWEEP_SAMPLES: usize = 20;
+
+/// Keep sampling while more than this percentage of the sampled keys had expired
+const SWEEP_REPEAT_PERCENT: usize = 25;
+
+/// Upper bound on the rounds of one cycle, so that the background task never holds a lock for long
+const SWEEP_MAX_ROUNDS: usize = 16;
+
+/// A handle to the database, cloning it is cheap and the clones share the same data
+#[derive(Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    shards: Box<[Mutex<Shard>]>,
+    /// Picks the shard of a key
+    hasher: RandomState,
+    clock: Clock,
+    ready: Arc<ReadyLists>,
+    handoffs: Arc<Handoffs>,
+}
+
+/// The keys whose hash falls into one shard, accessed through `Db::with_shard` and `Db::with_keys`
+pub struct Shard {
+    entries: HashMap<String, Entry>,
+    /// The keys that have a time to live, sampled by the background task
+    expires: ExpireSet,
+    rng: XorShift,
+    /// The clients blocked on each key, in the order they blocked
+    blocked: HashMap<String, VecDeque<Arc<Waiter>>>,
+    /// The watched keys: how many clients watch each, and its version, which changes whenever the
+    /// key does
+    watched: HashMap<String, (usize, u64)>,
+    /// The time of the clock when the shard was locked, keys expire against it
+    now: Instant,
+    /// The clock of the database, which also converts absolute expiries
+    clock: Clock,
+    /// Shared by all the shards
+    ready: Arc<ReadyLists>,
+    handoffs: Arc<Handoffs>,
+}
+
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+/// The value stored at a key
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    SortedSet(SortedSet),
+}
+
+/// A copy of a key with its value and expiry, as saved by persistence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub key: String,
+    pub value: Value,
+    pub expires_at: Option<SystemTime>,
+}
+
+/// The end of a list a command works on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// A client blocked on one or more lists, registered on each of them
+struct Waiter {
+    /// The end of the list the client pops from
+    end: ListEnd,
+    /// Taken by whoever serves the client first, so that it receives a single element
+    sender: Mutex<Option<oneshot::Sender<Served>>>,
+    /// For BLMOVE, the list the element is pushed on and its end
+    destination: Option<(String, ListEnd)>,
+}
+
+/// What a blocked client receives: the key it popped from and the element, or the error of a
+/// BLMOVE whose destination is not a list
+type Served = Result<(String, Bytes), WrongType>;
+
+/// A pop from the first non-empty list of several keys that waits until an element is pushed on
+/// one of them, created by `Db::blocking_pop` and `Db::blocking_move`. Resolves to the key and
+/// the popped element.
+///
+/// Dropping it stops waiting, an element that was popped but not received is put back.
+pub struct BlockedPop {
+    db: Db,
+    keys: Vec<String>,
+    waiter: Arc<Waiter>,
+    receiver: oneshot::Receiver<Served>,
+}
+
+/// The lists whose first blocked client is a BLMOVE that can be served, see `Db::serve_ready`
+#[derive(Default)]
+struct ReadyLists {
+    /// Whether `keys` is not empty, checked without taking the lock
+    any: AtomicBool,
+    keys: Mutex<Vec<String>>,
+}
+
+/// The changes made to lists while serving blocked clients, recorded once enabled by
+/// `Db::record_handoffs`
+#[derive(Default)]
+struct Handoffs {
+    enabled: AtomicBool,
+    changes: Mutex<Vec<Handoff>>,
+}
+
+/// A change made to a list for a blocked client rather than by the command that ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Handoff {
+    /// An element popped for a blocked client
+    Pop { key: String, end: ListEnd },
+    /// An element pushed by BLMOVE, or put back after the client stopped waiting
+    Push {
+        key: String,
+        end: ListEnd,
+        element: Bytes,
+    },
+}
+
+/// Error of a command run against a key holding another type of value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WrongType;
+
+/// When a key expires, as given by commands like `SET ... EX` or `EXPIREAT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// Expire after the duration
+    After(Duration),
+    /// Expire at the unix time
+    At(SystemTime),
+}
+
+/// Condition of the `EXPIRE` family of commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpireCondition {
+    /// NX: only if the key has no expiry
+    NotExists,
+    /// XX: only if the key has an expiry
+    Exists,
+    /// GT: only if the new expiry is greater than the current one, no expiry counts as infinite
+    Greater,
+    /// LT: only if the new expiry is less than the current one, no expiry counts as infinite
+    Less,
+}
+
+/// The time to live of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTtl {
+    /// The key does not exist
+    Missing,
+    /// The key exists but has no expiry
+    Persistent,
+    Expires(Duration),
+}
+
+impl Db {
+    /// Create an empty database that uses the system clock
+    pub fn new() -> Db {
+        Db::with_clock(Clock::system())
+    }
+
+    /// Create an empty database whose keys expire according to `clock`
+    pub fn with_clock(clock: Clock) -> Db {
+        Db::with_shards(DEFAULT_SHARDS, clock)
+    }
+
+    /// Create an empty database split into `shards` shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is not a power of two, see `Db::scan`.
+    pub fn with_shards(shards: usize, clock: Clock) -> Db {
+        assert!(
+            shards.is_power_of_two(),
+            "the number of shards must be a power of two"
+        );
+
+        let now = clock.now();
+        let ready = Arc::new(ReadyLists::default());
+        let handoffs = Arc::new(Handoffs::default());
+        let shards = (0..shards)
+            .map(|_| {
+                Mutex::new(Shard {
+                    entries: HashMap::new(),
+                    expires: ExpireSet::default(),
+                    rng: XorShift::new(),
+                    blocked: HashMap::new(),
+                    watched: HashMap::new(),
+                    now,
+                    clock: clock.clone(),
+                    ready: ready.clone(),
+                    handoffs: handoffs.clone(),
+                })
+            })
+            .collect();
+        Db {
+            shared: Arc::new(Shared {
+                shards,
+                hasher: RandomState::new(),
+                clock,
+                ready,
+                handoffs,
+            }),
+        }
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.shared.clock
+    }
+
+    /// Start recording the changes made to lists while serving blocked clients, for the
+    /// append-only file: the command that served them did not make these changes itself.
+    pub fn record_handoffs(&self) {
+        self.shared.handoffs.enabled.store(true, Ordering::Release);
+    }
+
+    /// Take the changes recorded since the last call, in the order they were made
+    pub fn take_handoffs(&self) -> Vec<Handoff> {
+        if !self.shared.handoffs.enabled.load(Ordering::Acquire) {
+            return Vec::new();
+        }
+        mem::take(&mut *self.shared.handoffs.changes.lock().unwrap())
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shared.shards.len()
+    }
+
+    /// The number of clients blocked on `key`, including those that stopped waiting but have not
+    /// been removed from the queue yet
+    pub fn blocked_clients(&self, key: &str) -> usize {
+        self.with_shard(key, |shard| shard.blocked.get(key).map_or(0, VecDeque::len))
+    }
+
+    /// Copy the live keys, one shard at a time: each item of the iterator locks a shard only while
+    /// its keys are copied, so commands keep running while the copy is saved. The copy is
+    /// consistent within a shard but not across shards.
+    pub fn dump(&self) -> impl Iterator<Item = Vec<Record>> + '_ {
+        (0..self.shard_count()).map(|index| {
+            let shard = self.lock(index);
+            let now = self.shared.clock.system_time();
+            shard
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.is_expired(shard.now))
+                .map(|(key, entry)| Record {
+                    key: key.clone(),
+                    value: entry.value.clone(),
+                    expires_at: entry
+                        .expires_at
+                        .map(|deadline| now + deadline.saturating_duration_since(shard.now)),
+                })
+                .collect()
+        })
+    }
+
+    /// Insert a saved key, replacing any existing value. Nothing is inserted if it has expired,
+    /// returns whether the key was inserted.
+    pub fn restore(&self, record: Record) -> bool {
+        let index = self.shard_index(&record.key);
+        let mut shard = self.lock(index);
+        let expires_at = match record.expires_at {
+            Some(at) => match shard.deadline(Expiry::At(at)) {
+                Some(deadline) => Some(deadline),
+                None => return false,
+            },
+            None => None,
+        };
+        let entry = Entry {
+            value: record.value,
+            expires_at,
+        };
+        shard.insert(record.key, entry);
+        true
+    }
+
+    /// Pop an element from the first non-empty list of `keys`, or wait for one to be pushed.
+    ///
+    /// Clients blocked on the same key are served in the order they blocked.
+    pub fn blocking_pop(&self, keys: Vec<String>, end: ListEnd) -> Result<BlockedPop, WrongType> {
+        let blocked = BlockedPop::new(self, keys, end, None);
+
+        // 依次检查每个键，列表为空时排队等待，这样在检查之后推入的元素也不会错过
+        for key in &blocked.keys {
+            let served = self.with_shard(key, |shard| {
+                let Some(list) = shard.list(key)?.filter(|list| !list.is_empty()) else {
+                    shard
+                        .blocked
+                        .entry(key.clone())
+                        .or_default()
+                        .push_back(blocked.waiter.clone());
+                    return Ok(false);
+                };
+                // 已经排队的键可能在这期间推入了元素，客户端已经拿到了一个元素
+                if let Some(sender) = blocked.waiter.claim() {
+                    let element = list.pop(end).unwrap();
+                    shard.remove_if_empty(key);
+                    shard.touch(key);
+                    shard.record(Handoff::Pop {
+                        key: key.clone(),
+                        end,
+                    });
+                    let _ = sender.send(Ok((key.clone(), element)));
+                }
+                Ok(true)
+            })?;
+            if served {
+                break;
+            }
+        }
+        Ok(blocked)
+    }
+
+    /// Move an element from `source` to `destination`, or wait for one to be pushed on `source`.
+    /// Fails without waiting if `destination` is not a list.
+    ///
+    /// The element is popped and pushed with the shards of both lists locked. Clients blocked on
+    /// `source` are served in the order they blocked, whether they pop or move.
+    pub fn blocking_move(
+        &self,
+        source: String,
+        destination: String,
+        (from, to): (ListEnd, ListEnd),
+    ) -> Result<BlockedPop, WrongType> {
+        let blocked = BlockedPop::new(self, vec![source], from, Some((destination, to)));
+        let source = &blocked.keys[0];
+        let destination = &blocked.waiter.destination.as_ref().unwrap().0;
+
+        let moved = self.with_keys(&[source, destination], |shards| {
+            shards.shard(destination).list(destination)?;
+            let shard = shards.shard(source);
+            let Some(element) = shard.list(source)?.and_then(|list| list.pop(from)) else {
+                shard
+                    .blocked
+                    .entry(source.clone())
+                    .or_default()
+                    .push_back(blocked.waiter.clone());
+                return Ok(false);
+            };
+            shard.remove_if_empty(source);
+            shard.touch(source);
+            shard.record(Handoff::Pop {
+                key: source.clone(),
+                end: from,
+            });
+            let shard = shards.shard(destination);
+            shard.list_or_insert(destination)?.push(to, element.clone());
+            shard.record(Handoff::Push {
+                key: destination.clone(),
+                end: to,
+                element: element.clone(),
+            });
+            shard.serve_blocked(destination);
+            shard.touch(destination);
+
+            let sender = blocked.waiter.claim().unwrap();
+            let _ = sender.send(Ok((source.clone(), element)));
+            Ok(true)
+        })?;
+        // 目标列表上可能也有 BLMOVE 的客户端在等待
+        if moved {
+            self.serve_ready();
+        }
+        Ok(blocked)
+    }
+
+    /// Serve the BLMOVE clients at the front of the lists marked ready by `Shard::serve_blocked`.
+    /// Each element is moved with the shards of its source and destination locked.
+    ///
+    /// Must be called after a command that pushed on a list, once it released its locks.
+    pub fn serve_ready(&self) {
+        while let Some(source) = self.shared.ready.pop() {
+            // 排在最前面的客户端可能在锁住两个分片之前放弃等待，这时重新检查下一个客户端
+            while let Some(waiter) = self.with_shard(&source, |shard| shard.serve_pops(&source)) {
+                let destination = &waiter.destination.as_ref().unwrap().0;
+                let served = self.with_keys(&[&source, destination], |shards| {
+                    move_to_destination(shards, &source, &waiter)
+                });
+                if !served {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// One cycle of active expiration on every shard. Returns the number of deleted keys.
+    pub fn purge_expired(&self) -> usize {
+        // 每次只锁一个分片，其他分片上的命令不受影响
+        (0..self.shard_count())
+            .map(|index| self.lock(index).purge_expired())
+            .sum()
+    }
+
+    fn shard_index(&self, key: &str) -> usize {
+        // 分片数是 2 的幂，取哈希值的低位，见 `Db::scan`
+        (self.shared.hasher.hash_one(key) & (self.shard_count() as u64 - 1)) as usize
+    }
+
+    /// Run `f` with the shards holding `keys` locked, or with every shard locked if `keys` is
+    /// `None`, for a transaction whose commands must run without any other command in between
+    pub fn with_locked<K, R>(&self, keys: Option<&[K]>, f: impl FnOnce(&Locked<'_>) -> R) -> R
+    where
+        K: AsRef<str>,
+    {
+        let mut indices: Vec<usize> = match keys {
+            Some(keys) => keys
+                .iter()
+                .map(|key| self.shard_index(key.as_ref()))
+                .collect(),
+            None => (0..self.shard_count()).collect(),
+        };
+        indices.sort_unstable();
+        indices.dedup();
+        let res = f(&Locked {
+            shards: RefCell::new(self.lock_shards(indices)),
+        });
+        self.serve_ready();
+        res
+    }
+
+    /// Lock the shards at `indices`, which must be sorted
+    fn lock_shards(&self, indices: Vec<usize>) -> Shards<'_> {
+        let guards = indices
+            .into_iter()
+            .map(|index| (index, self.lock(index)))
+            .collect();
+        Shards { db: self, guards }
+    }
+
+    /// Lock a shard and bring its time up to date
+    fn lock(&self, index: usize) -> MutexGuard<'_, Shard> {
+        let mut shard = self.shared.shards[index].lock().unwrap();
+        shard.now = self.shared.clock.now();
+        shard
+    }
+}
+
+impl Default for Db {
+    fn default() -> Self {
+        Db::new()
+    }
+}
+
+/// Access to the shards holding the keys, through which commands run: `Db` locks the shards on
+/// demand, `Locked` gives the shards a transaction holds locked.
+pub trait Keyspace {
+    /// The database the shards belong to
+    fn db(&self) -> &Db;
+
+    /// Run `f` with the shard holding `key` locked.
+    ///
+    /// Commands that read and then write a key should do both inside one call, so that no other
+    /// command can change the key in between.
+    fn with_shard<R>(&self, key: &str, f: impl FnOnce(&mut Shard) -> R) -> R;
+
+    /// Run `f` with the shards holding all of `keys` locked, for commands touching several keys.
+    fn with_keys<K, R>(&self, keys: &[K], f: impl FnOnce(&mut Shards<'_>) -> R) -> R
+    where
+        K: AsRef<str>;
+
+    /// Run `f` with the shard at `index` locked, for commands visiting every shard
+    fn with_shard_at<R>(&self, index: usize, f: impl FnOnce(&mut Shard) -> R) -> R;
+
+    /// Get the string value of `key`
+    fn get(&self, key: &str) -> Result<Option<Bytes>, WrongType> {
+        self.with_shard(key, |shard| shard.get(key))
+    }
+
+    /// Set `key` to `value`. Without `expiry` any existing time to live is removed, unless
+    /// `keep_ttl` is set.
+    fn set(&self, key: String, value: Bytes, expiry: Option<Expiry>, keep_ttl: bool) {
+        let shard_key = key.clone();
+        self.with_shard(&shard_key, |shard| shard.set(key, value, expiry, keep_ttl))
+    }
+
+    /// Delete `keys`, returning the number of keys that existed
+    fn del<K: AsRef<str>>(&self, keys: &[K]) -> usize {
+        self.with_keys(keys, |shards| {
+            keys.iter()
+                .filter(|key| shards.shard(key.as_ref()).del(key.as_ref()))
+                .count()
+        })
+    }
+
+    /// Count how many of `keys` exist, a key given several times is counted several times
+    fn exists<K: AsRef<str>>(&self, keys: &[K]) -> usize {
+        self.with_keys(keys, |shards| {
+            keys.iter()
+                .filter(|key| shards.shard(key.as_ref()).value(key.as_ref()).is_some())
+                .count()
+        })
+    }
+
+    /// Move the value and the time to live of `key` to `new_key`, overwriting `new_key`.
+    /// Returns `false` if `key` does not exist.
+    fn rename(&self, key: &str, new_key: &str) -> bool {
+        self.with_keys(&[key, new_key], |shards| {
+            let Some(entry) = shards.shard(key).take(key) else {
+                return false;
+            };
+            let shard = shards.shard(new_key);
+            shard.insert(new_key.to_string(), entry);
+            // 新的键可能是一个有客户端在等待的列表
+            shard.serve_blocked(new_key);
+            true
+        })
+    }
+
+    /// The keys for which `filter` returns true. The shards are locked one at a time, so the keys
+    /// are not a snapshot of the whole database.
+    fn keys(&self, mut filter: impl FnMut(&str) -> bool) -> Vec<String> {
+        let mut keys = Vec::new();
+        for index in 0..self.db().shard_count() {
+            self.with_shard_at(index, |shard| {
+                let candidates: Vec<String> = shard
+                    .entries
+                    .keys()
+                    .filter(|key| filter(key))
+                    .cloned()
+                    .collect();
+                keys.extend(
+                    candidates
+                        .into_iter()
+                        .filter(|key| shard.live(key).is_some()),
+                );
+            });
+        }
+        keys
+    }
+
+    /// Visit up to `count` keys from `cursor`, returning the cursor to continue from, which is 0
+    /// once every key has been visited.
+    ///
+    /// Like the buckets of a redis hash table, keys are visited in the order of their hash with
+    /// its bits reversed: the low bits of the hash pick the shard, so each shard is visited in one
+    /// go, and a key that exists during the whole iteration is always returned. The cursor is
+    /// the hash of the next key to visit.
+    ///
+    /// A call takes time linear in the size of the shard it visits, whatever `count` is.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<String>) {
+        let hasher = &self.db().shared.hasher;
+        let mask = self.db().shard_count() as u64 - 1;
+        let mut cursor = cursor;
+        let mut keys = Vec::new();
+
+        loop {
+            let next = self.with_shard_at((cursor & mask) as usize, |shard| {
+                let mut remaining: Vec<(u64, &String)> = shard
+                    .entries
+                    .keys()
+                    .map(|key| (hasher.hash_one(key).reverse_bits(), key))
+                    .filter(|(position, _)| *position >= cursor.reverse_bits())
+                    .collect();
+
+                // 只需要找出最前面的几个键，不用排序整个分片
+                let wanted = count - keys.len();
+                let next = (remaining.len() > wanted).then(|| {
+                    remaining.select_nth_unstable(wanted);
+                    remaining.truncate(wanted + 1);
+                    remaining.pop().unwrap().0.reverse_bits()
+                });
+                let visited: Vec<String> =
+                    remaining.into_iter().map(|(_, key)| key.clone()).collect();
+                keys.extend(visited.into_iter().filter(|key| shard.live(key).is_some()));
+                next
+            });
+
+            cursor = match next {
+                Some(next) => next,
+                // 这个分片已经遍历完了，和 redis 一样把游标的高位置 1 后反向加一，换到下一个分片
+                None => (cursor | !mask)
+                    .reverse_bits()
+                    .wrapping_add(1)
+                    .reverse_bits(),
+            };
+            if cursor == 0 || next.is_some() || keys.len() >= count {
+                return (cursor, keys);
+            }
+        }
+    }
+
+    /// The number of keys, including the expired keys that have not been deleted yet
+    fn len(&self) -> usize {
+        (0..self.db().shard_count())
+            .map(|index| self.with_shard_at(index, |shard| shard.entries.len()))
+            .sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Delete every key. Clients blocked on a list keep waiting.
+    fn flush(&self) {
+        for index in 0..self.db().shard_count() {
+            self.with_shard_at(index, |shard| {
+                shard.entries.clear();
+                shard.expires = ExpireSet::default();
+            });
+        }
+    }
+
+    /// Set the expiry of `key`, returning `false` if the key does not exist or the condition is not met.
+    ///
+    /// An expiry in the past deletes the key.
+    fn expire(&self, key: &str, expiry: Expiry, condition: Option<ExpireCondition>) -> bool {
+        self.with_shard(key, |shard| shard.expire(key, expiry, condition))
+    }
+
+    /// Remove the expiry of `key`, returning `false` if the key does not exist or has no expiry
+    fn persist(&self, key: &str) -> bool {
+        self.with_shard(key, |shard| shard.persist(key))
+    }
+
+    fn ttl(&self, key: &str) -> KeyTtl {
+        self.with_shard(key, |shard| shard.ttl(key))
+    }
+}
+
+impl Keyspace for Db {
+    fn db(&self) -> &Db {
+        self
+    }
+
+    fn with_shard<R>(&self, key: &str, f: impl FnOnce(&mut Shard) -> R) -> R {
+        f(&mut self.lock(self.shard_index(key)))
+    }
+
+    /// The shards are always locked in ascending order, so two multi-key commands cannot deadlock.
+    fn with_keys<K, R>(&self, keys: &[K], f: impl FnOnce(&mut Shards<'_>) -> R) -> R
+    where
+        K: AsRef<str>,
+    {
+        let mut indices: Vec<usize> = keys
+            .iter()
+            .map(|key| self.shard_index(key.as_ref()))
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        f(&mut self.lock_shards(indices))
+    }
+
+    fn with_shard_at<R>(&self, index: usize, f: impl FnOnce(&mut Shard) -> R) -> R {
+        f(&mut self.lock(index))
+    }
+}
+
+/// The shards locked by `Db::with_locked` for a transaction, commands run through them without
+/// locking anything else
+pub struct Locked<'a> {
+    shards: RefCell<Shards<'a>>,
+}
+
+impl Keyspace for Locked<'_> {
+    fn db(&self) -> &Db {
+        self.shards.borrow().db
+    }
+
+    fn with_shard<R>(&self, key: &str, f: impl FnOnce(&mut Shard) -> R) -> R {
+        f(self.shards.borrow_mut().shard(key))
+    }
+
+    fn with_keys<K, R>(&self, _keys: &[K], f: impl FnOnce(&mut Shards<'_>) -> R) -> R
+    where
+        K: AsRef<str>,
+    {
+        f(&mut self.shards.borrow_mut())
+    }
+
+    fn with_shard_at<R>(&self, index: usize, f: impl FnOnce(&mut Shard) -> R) -> R {
+        f(self.shards.borrow_mut().at(index))
+    }
+}
+
+/// Runs the commands that change keys: each key a command touches is marked as changed for the
+/// clients watching it, see `Watch`. A key that exists neither before nor after the command has
+/// not changed.
+pub struct Touching<'a, K>(pub &'a K);
+
+impl<K: Keyspace> Keyspace for Touching<'_, K> {
+    fn db(&self) -> &Db {
+        self.0.db()
+    }
+
+    fn with_shard<R>(&self, key: &str, f: impl FnOnce(&mut Shard) -> R) -> R {
+        self.0.with_shard(key, |shard| {
+            let existed = shard.watched_exists(key);
+            let res = f(shard);
+            shard.touch_if_changed(key, existed);
+            res
+        })
+    }
+
+    fn with_keys<T, R>(&self, keys: &[T], f: impl FnOnce(&mut Shards<'_>) -> R) -> R
+    where
+        T: AsRef<str>,
+    {
+        self.0.with_keys(keys, |shards| {
+            let existed: Vec<_> = keys
+                .iter()
+                .map(|key| shards.shard(key.as_ref()).watched_exists(key.as_ref()))
+                .collect();
+            let res = f(shards);
+            for (key, existed) in keys.iter().zip(existed) {
+                shards
+                    .shard(key.as_ref())
+                    .touch_if_changed(key.as_ref(), existed);
+            }
+            res
+        })
+    }
+
+    fn with_shard_at<R>(&self, index: usize, f: impl FnOnce(&mut Shard) -> R) -> R {
+        self.0.with_shard_at(index, |shard| {
+            let res = f(shard);
+            shard.touch_all();
+            res
+        })
+    }
+}
+
+/// The shards locked by `Keyspace::with_keys`
+pub struct Shards<'a> {
+    db: &'a Db,
+    guards: Vec<(usize, MutexGuard<'a, Shard>)>,
+}
+
+impl Shards<'_> {
+    /// The shard holding `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` was not one of the keys given to `Keyspace::with_keys`.
+    pub fn shard(&mut self, key: &str) -> &mut Shard {
+        let index = self.db.shard_index(key);
+        match self
+            .guards
+            .binary_search_by_key(&index, |(index, _)| *index)
+        {
+            Ok(i) => &mut self.guards[i].1,
+            Err(_) => panic!("the shard of key `{}` is not locked", key),
+        }
+    }
+
+    /// The shard at `index`, which must be locked
+    fn at(&mut self, index: usize) -> &mut Shard {
+        match self
+            .guards
+            .binary_search_by_key(&index, |(index, _)| *index)
+        {
+            Ok(i) => &mut self.guards[i].1,
+            Err(_) => panic!("shard {} is not locked", index),
+        }
+    }
+}
+
+/// The keys watched by a client for a transaction, with their versions when they were watched.
+///
+/// Dropping it stops watching them.
+pub struct Watch {
+    db: Db,
+    keys: Vec<(String, u64)>,
+}
+
+impl Watch {
+    pub fn new(db: Db) -> Watch {
+        Watch {
+            db,
+            keys: Vec::new(),
+        }
+    }
+
+    /// Watch `key`, which is marked as changed by any command that writes it from now on
+    pub fn add(&mut self, key: String) {
+        if self.keys.iter().any(|(watched, _)| *watched == key) {
+            return;
+        }
+        let version = self.db.with_shard(&key, |shard| shard.watch(&key));
+        self.keys.push((key, version));
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.keys.iter().map(|(key, _)| &key[..])
+    }
+
+    /// Whether none of the watched keys changed, their shards must be locked by `locked`. A key
+    /// that expired counts as changed.
+    pub fn unchanged(&self, locked: &Locked<'_>) -> bool {
+        self.keys
+            .iter()
+            .all(|(key, version)| locked.with_shard(key, |shard| shard.version(key) == *version))
+    }
+
+    /// Stop watching every key
+    pub fn clear(&mut self) {
+        for (key, _) in self.keys.drain(..) {
+            self.db.with_shard(&key, |shard| shard.unwatch(&key));
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Run active expiration on `db` every 100 milliseconds of its clock, until the database is dropped
+pub async fn purge_expired_keys(db: Db) {
+    let mut interval = db.clock().interval(SWEEP_INTERVAL);
+    // 后台任务不应该让数据库一直存活
+    let shared: Weak<Shared> = Arc::downgrade(&db.shared);
+    drop(db);
+
+    while interval.next().await.is_some() {
+        let Some(shared) = shared.upgrade() else {
+            break;
+        };
+        Db { shared }.purge_expired();
+    }
+}
+
+impl BlockedPop {
+    fn new(
+        db: &Db,
+        keys: Vec<String>,
+        end: ListEnd,
+        destination: Option<(String, ListEnd)>,
+    ) -> Self {
+        let (sender, receiver) = oneshot::channel();
+        BlockedPop {
+            db: db.clone(),
+            keys,
+            waiter: Arc::new(Waiter {
+                end,
+                sender: Mutex::new(Some(sender)),
+                destination,
+            }),
+            receiver,
+        }
+    }
+
+    /// Stop waiting, returning what the client was served if it was served in the meantime.
+    ///
+    /// A client that times out while an element is handed over replies with the element, which
+    /// then stays popped.
+    pub fn cancel(mut self) -> Option<Result<(String, Bytes), WrongType>> {
+        self.take_served()
+    }
+
+    fn take_served(&mut self) -> Option<Served> {
+        // 先拿走发送端并关闭接收端，之后就不会再有元素交给这个客户端了
+        drop(self.waiter.claim());
+        self.receiver.close();
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl Future for BlockedPop {
+    type Output = Result<(String, Bytes), WrongType>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 发送端只会在发送之后被丢弃
+        Pin::new(&mut self.receiver)
+            .poll(cx)
+            .map(|res| res.expect("blocked pop abandoned"))
+    }
+}
+
+impl Drop for BlockedPop {
+    fn drop(&mut self) {
+        // BLMOVE 移动的元素已经在目标列表里了，不用放回去
+        if let (Some(Ok((key, element))), None) = (self.take_served(), &self.waiter.destination) {
+            self.db.with_shard(&key, |shard| {
+                if let Ok(list) = shard.list_or_insert(&key) {
+                    list.push(self.waiter.end, element.clone());
+                    shard.record(Handoff::Push {
+                        key: key.clone(),
+                        end: self.waiter.end,
+                        element,
+                    });
+                    shard.serve_blocked(&key);
+                    shard.touch(&key);
+                }
+            });
+            self.db.serve_ready();
+        }
+
+        for key in &self.keys {
+            self.db
+                .with_shard(key, |shard| shard.unblock(key, &self.waiter));
+        }
+    }
+}
+
+impl Waiter {
+    fn claim(&self) -> Option<oneshot::Sender<Served>> {
+        self.sender.lock().unwrap().take()
+    }
+
+    /// Whether the client stopped waiting or was served
+    fn is_gone(&self) -> bool {
+        self.sender.lock().unwrap().is_none()
+    }
+}
+
+impl ReadyLists {
+    fn push(&self, key: &str) {
+        let mut keys = self.keys.lock().unwrap();
+        keys.push(key.to_string());
+        self.any.store(true, Ordering::Release);
+    }
+
+    fn pop(&self) -> Option<String> {
+        // 大多数命令执行之后都没有就绪的列表，不需要加锁
+        if !self.any.load(Ordering::Acquire) {
+            return None;
+        }
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys.pop();
+        self.any.store(!keys.is_empty(), Ordering::Release);
+        key
+    }
+}
+
+/// Move an element from `source` to the destination of `waiter`, a BLMOVE client, which must be
+/// the first client blocked on `source`. Returns whether the next client can be served.
+fn move_to_destination(shards: &mut Shards<'_>, source: &str, waiter: &Arc<Waiter>) -> bool {
+    let (destination, to) = waiter.destination.as_ref().unwrap();
+    let shard = shards.shard(source);
+    // 两次加锁之间，其他客户端可能已经弹出了元素，或者排在前面的客户端已经放弃等待
+    let front = shard
+        .blocked
+        .get(source)
+        .and_then(|waiters| waiters.front());
+    if !front.is_some_and(|front| Arc::ptr_eq(front, waiter)) {
+        return true;
+    }
+    if !matches!(shard.list(source), Ok(Some(list)) if !list.is_empty()) {
+        return false;
+    }
+    let Some(sender) = waiter.claim() else {
+        shard.unblock(source, waiter);
+        return true;
+    };
+
+    // 和 redis 一样，目标键不是列表时客户端收到错误，元素留在原来的列表里
+    if let Err(err) = shards.shard(destination).list(destination) {
+        shards.shard(source).unblock(source, waiter);
+        let _ = sender.send(Err(err));
+        return true;
+    }
+
+    let shard = shards.shard(source);
+    shard.unblock(source, waiter);
+    let list = shard.list(source).unwrap().unwrap();
+    let element = list.pop(waiter.end).unwrap();
+    if sender
+        .send(Ok((source.to_string(), element.clone())))
+        .is_err()
+    {
+        // 客户端刚好放弃等待，元素放回原处
+        list.push(waiter.end, element);
+        return true;
+    }
+    shard.remove_if_empty(source);
+    shard.touch(source);
+    shard.record(Handoff::Pop {
+        key: source.to_string(),
+        end: waiter.end,
+    });
+
+    let shard = shards.shard(destination);
+    shard
+        .list_or_insert(destination)
+        .unwrap()
+        .push(*to, element.clone());
+    shard.record(Handoff::Push {
+        key: destination.clone(),
+        end: *to,
+        element,
+    });
+    shard.serve_blocked(destination);
+    shard.touch(destination);
+    true
+}
+
+impl Shard {
+    /// See `Db::get`
+    pub fn get(&mut self, key: &str) -> Result<Option<Bytes>, WrongType> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(WrongType),
+        }
+    }
+
+    /// The value of `key`, of any type
+    pub fn value(&mut self, key: &str) -> Option<&mut Value> {
+        self.live(key).map(|entry| &mut entry.value)
+    }
+
+    /// The list at `key`, `None` if the key does not exist
+    pub fn list(&mut self, key: &str) -> Result<Option<&mut VecDeque<Bytes>>, WrongType> {
+        self.typed(key, Value::as_list)
+    }
+
+    /// The list at `key`, an empty list is created if the key does not exist.
+    ///
+    /// Call `remove_if_empty` once done, redis never keeps an empty list.
+    pub fn list_or_insert(&mut self, key: &str) -> Result<&mut VecDeque<Bytes>, WrongType> {
+        self.typed_or_insert(key, Value::as_list, || Value::List(VecDeque::new()))
+    }
+
+    /// The hash at `key`, `None` if the key does not exist
+    pub fn hash(&mut self, key: &str) -> Result<Option<&mut HashMap<Bytes, Bytes>>, WrongType> {
+        self.typed(key, Value::as_hash)
+    }
+
+    /// The hash at `key`, an empty hash is created if the key does not exist.
+    ///
+    /// Call `remove_if_empty` once done, redis never keeps an empty hash.
+    pub fn hash_or_insert(&mut self, key: &str) -> Result<&mut HashMap<Bytes, Bytes>, WrongType> {
+        self.typed_or_insert(key, Value::as_hash, || Value::Hash(HashMap::new()))
+    }
+
+    /// The set at `key`, `None` if the key does not exist
+    pub fn members(&mut self, key: &str) -> Result<Option<&mut HashSet<Bytes>>, WrongType> {
+        self.typed(key, Value::as_set)
+    }
+
+    /// The set at `key`, an empty set is created if the key does not exist.
+    ///
+    /// Call `remove_if_empty` once done, redis never keeps an empty set.
+    pub fn members_or_insert(&mut self, key: &str) -> Result<&mut HashSet<Bytes>, WrongType> {
+        self.typed_or_insert(key, Value::as_set, || Value::Set(HashSet::new()))
+    }
+
+    /// The sorted set at `key`, `None` if the key does not exist
+    pub fn sorted_set(&mut self, key: &str) -> Result<Option<&mut SortedSet>, WrongType> {
+        self.typed(key, Value::as_sorted_set)
+    }
+
+    /// The sorted set at `key`, an empty sorted set is created if the key does not exist.
+    ///
+    /// Call `remove_if_empty` once done, redis never keeps an empty sorted set.
+    pub fn sorted_set_or_insert(&mut self, key: &str) -> Result<&mut SortedSet, WrongType> {
+        self.typed_or_insert(key, Value::as_sorted_set, || {
+            Value::SortedSet(SortedSet::new())
+        })
+    }
+
+    /// Hand the elements of the list at `key` to the clients blocked on it, oldest first. A
+    /// BLMOVE client needs the shard of its destination too, the list is marked ready and the
+    /// client is served by `Db::serve_ready`.
+    ///
+    /// Must be called after pushing on a list.
+    pub fn serve_blocked(&mut self, key: &str) {
+        if self.serve_pops(key).is_some() {
+            self.ready.push(key);
+        }
+    }
+
+    /// Hand the elements of the list at `key` to the BLPOP and BRPOP clients at the front of its
+    /// queue. Returns the BLMOVE client it stopped at, if the list still has elements.
+    fn serve_pops(&mut self, key: &str) -> Option<Arc<Waiter>> {
+        while let Some(waiter) = self
+            .blocked
+            .get(key)
+            .and_then(|waiters| waiters.front().cloned())
+        {
+            let Ok(Some(list)) = self.list(key) else {
+                break;
+            };
+            if list.is_empty() {
+                break;
+            }
+            if waiter.destination.is_some() && !waiter.is_gone() {
+                return Some(waiter);
+            }
+
+            // 客户端可能已经从另一个键拿到了元素，或者已经放弃等待
+            if let Some(sender) = waiter.claim() {
+                let element = list.pop(waiter.end).unwrap();
+                match sender.send(Ok((key.to_string(), element))) {
+                    Ok(()) => self.record(Handoff::Pop {
+                        key: key.to_string(),
+                        end: waiter.end,
+                    }),
+                    // 客户端刚好放弃等待，元素放回原处
+                    Err(served) => list.push(waiter.end, served.unwrap().1),
+                }
+            }
+
+            let waiters = self.blocked.get_mut(key).unwrap();
+            waiters.pop_front();
+            if waiters.is_empty() {
+                self.blocked.remove(key);
+            }
+        }
+        self.remove_if_empty(key);
+        None
+    }
+
+    /// Record a change made for a blocked client, see `Db::record_handoffs`
+    fn record(&self, handoff: Handoff) {
+        if self.handoffs.enabled.load(Ordering::Acquire) {
+            self.handoffs.changes.lock().unwrap().push(handoff);
+        }
+    }
+
+    /// Remove `waiter` from the clients blocked on `key`
+    fn unblock(&mut self, key: &str, waiter: &Arc<Waiter>) {
+        if let Some(waiters) = self.blocked.get_mut(key) {
+            waiters.retain(|other| !Arc::ptr_eq(other, waiter));
+            if waiters.is_empty() {
+                self.blocked.remove(key);
+            }
+        }
+    }
+
+    /// Mark `key` as changed for the clients watching it
+    pub fn touch(&mut self, key: &str) {
+        // 大多数时候没有被监视的键，不需要计算哈希值
+        if self.watched.is_empty() {
+            return;
+        }
+        if let Some((_, version)) = self.watched.get_mut(key) {
+            *version += 1;
+        }
+    }
+
+    /// Whether `key` exists, `None` if nobody watches it
+    fn watched_exists(&mut self, key: &str) -> Option<bool> {
+        if self.watched.is_empty() || !self.watched.contains_key(key) {
+            return None;
+        }
+        Some(self.value(key).is_some())
+    }
+
+    /// Mark a watched key as changed by a write, unless it did not exist before and still does not
+    fn touch_if_changed(&mut self, key: &str, existed: Option<bool>) {
+        if let Some(existed) = existed {
+            if existed || self.value(key).is_some() {
+                self.touch(key);
+            }
+        }
+    }
+
+    /// Mark every watched key of the shard as changed
+    fn touch_all(&mut self) {
+        for (_, version) in self.watched.values_mut() {
+            *version += 1;
+        }
+    }
+
+    /// Start watching `key`, returning its version
+    fn watch(&mut self, key: &str) -> u64 {
+        // 已经过期的键先删除，之后它过期不算被修改
+        self.live(key);
+        let (watchers, version) = self.watched.entry(key.to_string()).or_default();
+        *watchers += 1;
+        *version
+    }
+
+    fn unwatch(&mut self, key: &str) {
+        if let Some((watchers, _)) = self.watched.get_mut(key) {
+            *watchers -= 1;
+            if *watchers == 0 {
+                self.watched.remove(key);
+            }
+        }
+    }
+
+    /// The version of a watched key, after deleting it if it has expired
+    fn version(&mut self, key: &str) -> u64 {
+        self.live(key);
+        self.watched.get(key).map_or(0, |(_, version)| *version)
+    }
+
+    /// Delete `key` if it holds an empty collection
+    pub fn remove_if_empty(&mut self, key: &str) {
+        if self.value(key).is_some_and(|value| value.is_empty()) {
+            self.remove(key);
+        }
+    }
+
+    /// See `Db::set`
+    pub fn set(&mut self, key: String, value: Bytes, expiry: Option<Expiry>, keep_ttl: bool) {
+        let expires_at = match expiry {
+            Some(expiry) => match self.deadline(expiry) {
+                Some(deadline) => Some(deadline),
+                // 过期时间已经过去了，相当于设置之后马上被删除
+                None => {
+                    self.remove(&key);
+                    return;
+                }
+            },
+            None if keep_ttl => self.live(&key).and_then(|entry| entry.expires_at),
+            None => None,
+        };
+        let value = Value::String(value);
+        self.insert(key, Entry { value, expires_at });
+    }
+
+    /// Delete `key`, returning whether it existed
+    pub fn del(&mut self, key: &str) -> bool {
+        self.live(key).is_some() && self.remove(key).is_some()
+    }
+
+    /// See `Db::expire`
+    pub fn expire(
+        &mut self,
+        key: &str,
+        expiry: Expiry,
+        condition: Option<ExpireCondition>,
+    ) -> bool {
+        let deadline = self.deadline(expiry);
+        let Some(entry) = self.live(key) else {
+            return false;
+        };
+
+        let current = entry.expires_at;
+        // 没有过期时间相当于无限长，`None` 表示新的过期时间已经过去了
+        let allowed = match condition {
+            None => true,
+            Some(ExpireCondition::NotExists) => current.is_none(),
+            Some(ExpireCondition::Exists) => current.is_some(),
+            Some(ExpireCondition::Greater) => match (deadline, current) {
+                (_, None) => false,
+                (None, Some(_)) => false,
+                (Some(deadline), Some(current)) => deadline > current,
+            },
+            Some(ExpireCondition::Less) => match (deadline, current) {
+                (None, _) => true,
+                (Some(_), None) => true,
+                (Some(deadline), Some(current)) => deadline < current,
+            },
+        };
+        if !allowed {
+            return false;
+        }
+
+        match deadline {
+            Some(deadline) => self.set_expiry(key, Some(deadline)),
+            None => {
+                self.remove(key);
+            }
+        }
+        true
+    }
+
+    /// See `Db::persist`
+    pub fn persist(&mut self, key: &str) -> bool {
+        match self.live(key) {
+            Some(entry) if entry.expires_at.is_some() => {
+                self.set_expiry(key, None);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn ttl(&mut self, key: &str) -> KeyTtl {
+        let now = self.now;
+        match self.live(key) {
+            None => KeyTtl::Missing,
+            Some(Entry {
+                expires_at: None, ..
+            }) => KeyTtl::Persistent,
+            Some(Entry {
+                expires_at: Some(deadline),
+                ..
+            }) => KeyTtl::Expires(deadline.saturating_duration_since(now)),
+        }
+    }
+
+    /// One cycle of active expiration: sample keys with a time to live and delete the expired ones,
+    /// repeating while many of the sampled keys had expired. Returns the number of deleted keys.
+    fn purge_expired(&mut self) -> usize {
+        let mut purged = 0;
+        for _ in 0..SWEEP_MAX_ROUNDS {
+            let samples = SWEEP_SAMPLES.min(self.expires.len());
+            if samples == 0 {
+                break;
+            }
+
+            let mut expired = 0;
+            for _ in 0..samples {
+                if self.expires.is_empty() {
+                    break;
+                }
+                let index = self.rng.next_u64() as usize % self.expires.len();
+                let key = self.expires.keys[index].clone();
+                if self.entries[&key].is_expired(self.now) {
+                    self.remove(&key);
+                    self.touch(&key);
+                    expired += 1;
+                }
+            }
+            purged += expired;
+
+            if expired * 100 <= samples * SWEEP_REPEAT_PERCENT {
+                break;
+            }
+        }
+        purged
+    }
+
+    fn typed<T>(
+        &mut self,
+        key: &str,
+        cast: fn(&mut Value) -> Option<&mut T>,
+    ) -> Result<Option<&mut T>, WrongType> {
+        match self.value(key) {
+            None => Ok(None),
+            Some(value) => cast(value).map(Some).ok_or(WrongType),
+        }
+    }
+
+    fn typed_or_insert<T>(
+        &mut self,
+        key: &str,
+        cast: fn(&mut Value) -> Option<&mut T>,
+        empty: fn() -> Value,
+    ) -> Result<&mut T, WrongType> {
+        if self.live(key).is_none() {
+            let entry = Entry {
+                value: empty(),
+                expires_at: None,
+            };
+            self.insert(key.to_string(), entry);
+        }
+        cast(&mut self.entries.get_mut(key).unwrap().value).ok_or(WrongType)
+    }
+
+    /// Convert an expiry into a deadline of the clock, `None` if it is already in the past
+    fn deadline(&self, expiry: Expiry) -> Option<Instant> {
+        let now = self.now;
+        let deadline = match expiry {
+            Expiry::After(duration) => now.checked_add(duration),
+            Expiry::At(at) => match at.duration_since(self.clock.system_time()) {
+                Ok(duration) => now.checked_add(duration),
+                Err(_) => return None,
+            },
+        };
+        // 超出 `Instant` 表示范围的过期时间，当作一个很久以后的时间
+        let deadline = deadline.unwrap_or_else(|| now + Duration::from_secs(u32::MAX as u64));
+        (deadline > now).then_some(deadline)
+    }
+
+    /// Look up a key, deleting it if it has expired
+    fn live(&mut self, key: &str) -> Option<&mut Entry> {
+        if self.entries.get(key)?.is_expired(self.now) {
+            self.remove(key);
+            self.touch(key);
+            return None;
+        }
+        self.entries.get_mut(key)
+    }
+
+    fn insert(&mut self, key: String, entry: Entry) {
+        if entry.expires_at.is_some() {
+            self.expires.insert(&key);
+        } else {
+            self.expires.remove(&key);
+        }
+        self.entries.insert(key, entry);
+    }
+
+    /// Remove `key` if it is live
+    fn take(&mut self, key: &str) -> Option<Entry> {
+        self.live(key)?;
+        self.remove(key)
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        if entry.expires_at.is_some() {
+            self.expires.remove(key);
+        }
+        Some(entry)
+    }
+
+    /// Change the expiry of an existing key
+    fn set_expiry(&mut self, key: &str, expires_at: Option<Instant>) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.expires_at = expires_at;
+            if expires_at.is_some() {
+                self.expires.insert(key);
+            } else {
+                self.expires.remove(key);
+            }
+        }
+    }
+}
+
+impl Value {
+    /// The name of the type as replied by TYPE
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::SortedSet(_) => "zset",
+        }
+    }
+
+    /// Whether the value is an empty collection, a string is never empty
+    fn is_empty(&self) -> bool {
+        match self {
+            Value::String(_) => false,
+            Value::List(list) => list.is_empty(),
+            Value::Hash(hash) => hash.is_empty(),
+            Value::Set(set) => set.is_empty(),
+            Value::SortedSet(zset) => zset.is_empty(),
+        }
+    }
+
+    fn as_list(&mut self) -> Option<&mut VecDeque<Bytes>> {
+        match self {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn as_hash(&mut self) -> Option<&mut HashMap<Bytes, Bytes>> {
+        match self {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn as_set(&mut self) -> Option<&mut HashSet<Bytes>> {
+        match self {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn as_sorted_set(&mut self) -> Option<&mut SortedSet> {
+        match self {
+            Value::SortedSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+}
+
+/// Push and pop at either end of a list
+pub trait ListExt {
+    fn push(&mut self, end: ListEnd, element: Bytes);
+    fn pop(&mut self, end: ListEnd) -> Option<Bytes>;
+}
+
+impl ListExt for VecDeque<Bytes> {
+    fn push(&mut self, end: ListEnd, element: Bytes) {
+        match end {
+            ListEnd::Left => self.push_front(element),
+            ListEnd::Right => self.push_back(element),
+        }
+    }
+
+    fn pop(&mut self, end: ListEnd) -> Option<Bytes> {
+        match end {
+            ListEnd::Left => self.pop_front(),
+            ListEnd::Right => self.pop_back(),
+        }
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "WRONGTYPE Operation against a key holding the wrong kind of value".fmt(f)
+    }
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// A set of keys that supports picking a random key in constant time
+#[derive(Default)]
+struct ExpireSet {
+    keys: Vec<String>,
+    index: HashMap<String, usize>,
+}
+
+impl ExpireSet {
+    fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    fn insert(&mut self, key: &str) {
+        if !self.index.contains_key(key) {
+            self.index.insert(key.to_string(), self.keys.len());
+            self.keys.push(key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(index) = self.index.remove(key) {
+            // 把最后一个键移到被删除的位置上
+            self.keys.swap_remove(index);
+            if let Some(moved) = self.keys.get(index) {
+                self.index.insert(moved.clone(), index);
+            }
+        }
+    }
+}
+
+/// A small pseudo random generator for sampling keys, it does not need to be of good quality
+struct XorShift(u64);
+
+impl XorShift {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        XorShift(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::UNIX_EPOCH;
+
+    use super::*;
+    use crate::myredis::{cmd::Command, frame::Frame};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Run the command made of `args` against `db`
+    async fn run(db: &Db, args: &[&str]) -> Frame {
+        let args: Vec<Bytes> = args
+            .iter()
+            .map(|arg| Bytes::from(arg.to_string()))
+            .collect();
+        Command::from_frame(Frame::bulks(args))
+            .unwrap()
+            .apply(db)
+            .await
+    }
+
+    fn bulk(value: &'static str) -> Frame {
+        Frame::Bulk(Bytes::from(value))
+    }
+
+    #[tokio::test]
+    async fn px_and_ex() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        run(&db, &["SET", "a", "v", "PX", "100"]).await;
+        run(&db, &["SET", "b", "v", "EX", "1"]).await;
+        assert_eq!(run(&db, &["PTTL", "a"]).await, Frame::Integer(100));
+        assert_eq!(run(&db, &["TTL", "b"]).await, Frame::Integer(1));
+
+        clock.advance(99 * MS);
+        assert_eq!(run(&db, &["GET", "a"]).await, bulk("v"));
+        assert_eq!(run(&db, &["PTTL", "a"]).await, Frame::Integer(1));
+        clock.advance(MS);
+        assert_eq!(run(&db, &["GET", "a"]).await, Frame::Null);
+        assert_eq!(run(&db, &["PTTL", "a"]).await, Frame::Integer(-2));
+
+        clock.advance(900 * MS);
+        assert_eq!(run(&db, &["EXISTS", "b"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_deleted_on_read() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        run(&db, &["SET", "a", "v", "PX", "10"]).await;
+        run(&db, &["SET", "b", "v"]).await;
+
+        // 过期的键在被读取之前还占着位置
+        clock.advance(10 * MS);
+        assert_eq!(db.len(), 2);
+        assert_eq!(run(&db, &["GET", "a"]).await, Frame::Null);
+        assert_eq!(db.len(), 1);
+
+        // 写命令看到的也是已经删除的键
+        run(&db, &["SET", "c", "v", "PX", "10"]).await;
+        clock.advance(10 * MS);
+        assert_eq!(run(&db, &["APPEND", "c", "w"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["TTL", "c"]).await, Frame::Integer(-1));
+    }
+
+    #[tokio::test]
+    async fn pexpireat_and_persist() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        run(&db, &["SET", "k", "v"]).await;
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(-1));
+
+        // 绝对时间按数据库的时钟换算成剩余的时间，暂停的时钟不会走
+        let at = clock.system_time() + Duration::from_secs(2);
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis()
+            .to_string();
+        assert_eq!(run(&db, &["PEXPIREAT", "k", &at]).await, Frame::Integer(1));
+        // 换成毫秒时舍去了不到一毫秒的部分
+        let pttl = [Frame::Integer(1999), Frame::Integer(2000)];
+        assert!(pttl.contains(&run(&db, &["PTTL", "k"]).await));
+        clock.advance(1500 * MS);
+        let pttl = [Frame::Integer(499), Frame::Integer(500)];
+        assert!(pttl.contains(&run(&db, &["PTTL", "k"]).await));
+
+        assert_eq!(run(&db, &["PERSIST", "k"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["PERSIST", "k"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(-1));
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(run(&db, &["GET", "k"]).await, bulk("v"));
+
+        // 过去的时间直接删除这个键
+        assert_eq!(run(&db, &["PEXPIREAT", "k", "1"]).await, Frame::Integer(1));
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(-2));
+        assert_eq!(run(&db, &["PERSIST", "k"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn ttl_rounds_to_the_nearest_second() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        run(&db, &["SET", "k", "v", "PX", "1500"]).await;
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(2));
+        clock.advance(1001 * MS);
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(0));
+        assert_eq!(run(&db, &["PTTL", "k"]).await, Frame::Integer(499));
+    }
+
+    #[tokio::test]
+    async fn expire_replaces_and_keepttl_keeps_the_expiry() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        run(&db, &["SET", "k", "v", "EX", "10"]).await;
+        run(&db, &["SET", "k", "w", "KEEPTTL"]).await;
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(10));
+        // 不带 KEEPTTL 的 SET 去掉过期时间
+        run(&db, &["SET", "k", "w"]).await;
+        assert_eq!(run(&db, &["TTL", "k"]).await, Frame::Integer(-1));
+
+        assert_eq!(run(&db, &["EXPIRE", "k", "5"]).await, Frame::Integer(1));
+        assert_eq!(
+            run(&db, &["EXPIRE", "k", "3", "GT"]).await,
+            Frame::Integer(0)
+        );
+        assert_eq!(
+            run(&db, &["EXPIRE", "k", "3", "LT"]).await,
+            Frame::Integer(1)
+        );
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(run(&db, &["GET", "k"]).await, Frame::Null);
+        assert_eq!(run(&db, &["EXPIRE", "k", "5"]).await, Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn sweeper_removes_expired_keys() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        for i in 0..1000 {
+            let key = format!("volatile:{}", i);
+            db.set(key, Bytes::from("v"), Some(Expiry::After(50 * MS)), false);
+        }
+        for i in 0..10 {
+            db.set(format!("key:{}", i), Bytes::from("v"), None, false);
+        }
+        tokio::spawn(purge_expired_keys(db.clone()));
+
+        // 还没过期的键不会被删除
+        clock.advance(SWEEP_INTERVAL / 4);
+        tokio::task::yield_now().await;
+        assert_eq!(db.len(), 1010);
+
+        // 所有的键都没有被读取过，只能由后台任务删除
+        for _ in 0..100 {
+            clock.advance(SWEEP_INTERVAL);
+            tokio::task::yield_now().await;
+            if db.len() == 10 {
+                break;
+            }
+        }
+        assert_eq!(db.len(), 10);
+        assert_eq!(run(&db, &["DBSIZE"]).await, Frame::Integer(10));
+    }
+
+    #[test]
+    fn purge_stops_when_few_sampled_keys_expired() {
+        let clock = Clock::paused();
+        let db = Db::with_shards(1, clock.clone());
+        for i in 0..1000 {
+            let ttl = if i < 10 { MS } else { Duration::from_secs(60) };
+            db.set(
+                format!("k{}", i),
+                Bytes::from("v"),
+                Some(Expiry::After(ttl)),
+                false,
+            );
+        }
+        clock.advance(MS);
+        // 只有 1% 的键过期了，一轮取样之后就停下来
+        assert!(db.purge_expired() <= SWEEP_SAMPLES);
+        assert!(db.len() >= 990);
+
+        clock.advance(Duration::from_secs(60));
+        let purged = db.purge_expired();
+        assert_eq!(purged, SWEEP_SAMPLES * SWEEP_MAX_ROUNDS);
+    }
+
+    #[tokio::test]
+    async fn handoffs_are_recorded() {
+        use futures::FutureExt;
+
+        let db = Db::new();
+        let pop = |end| db.blocking_pop(vec!["l".to_string()], end).unwrap();
+        let handoffs = |db: &Db| db.take_handoffs();
+
+        // 没有开始记录时什么都不记
+        run(&db, &["RPUSH", "l", "a"]).await;
+        assert!(pop(ListEnd::Left).now_or_never().is_some());
+        assert_eq!(handoffs(&db), vec![]);
+
+        db.record_handoffs();
+        run(&db, &["RPUSH", "l", "a"]).await;
+        let served = pop(ListEnd::Left).now_or_never().unwrap().unwrap();
+        assert_eq!(served, ("l".to_string(), Bytes::from("a")));
+        let popped = Handoff::Pop {
+            key: "l".to_string(),
+            end: ListEnd::Left,
+        };
+        assert_eq!(handoffs(&db), vec![popped]);
+
+        // 推入的元素交给等待的客户端，客户端没有收下就放弃，元素放回原处
+        let blocked = pop(ListEnd::Right);
+        assert_eq!(handoffs(&db), vec![]);
+        run(&db, &["RPUSH", "l", "b"]).await;
+        let popped = Handoff::Pop {
+            key: "l".to_string(),
+            end: ListEnd::Right,
+        };
+        assert_eq!(handoffs(&db), vec![popped]);
+        drop(blocked);
+        let pushed = Handoff::Push {
+            key: "l".to_string(),
+            end: ListEnd::Right,
+            element: Bytes::from("b"),
+        };
+        assert_eq!(handoffs(&db), vec![pushed]);
+        assert_eq!(
+            run(&db, &["LRANGE", "l", "0", "-1"]).await,
+            Frame::Array(vec![bulk("b")])
+        );
+    }
+
+    #[tokio::test]
+    async fn moves_are_recorded() {
+        use futures::FutureExt;
+
+        let db = Db::new();
+        db.record_handoffs();
+        let sides = (ListEnd::Left, ListEnd::Right);
+        let moved = |element: &'static str| {
+            vec![
+                Handoff::Pop {
+                    key: "src".to_string(),
+                    end: ListEnd::Left,
+                },
+                Handoff::Push {
+                    key: "dst".to_string(),
+                    end: ListEnd::Right,
+                    element: Bytes::from(element),
+                },
+            ]
+        };
+
+        run(&db, &["RPUSH", "src", "x"]).await;
+        let blocked = db
+            .blocking_move("src".to_string(), "dst".to_string(), sides)
+            .unwrap();
+        assert_eq!(db.take_handoffs(), moved("x"));
+        assert!(blocked.now_or_never().is_some());
+
+        // 移动到目标列表的元素在客户端放弃之后也不会放回去
+        let blocked = db
+            .blocking_move("src".to_string(), "dst".to_string(), sides)
+            .unwrap();
+        run(&db, &["RPUSH", "src", "y"]).await;
+        assert_eq!(db.take_handoffs(), moved("y"));
+        drop(blocked);
+        assert_eq!(db.take_handoffs(), vec![]);
+        assert_eq!(
+            run(&db, &["LRANGE", "dst", "0", "-1"]).await,
+            Frame::Array(vec![bulk("x"), bulk("y")])
+        );
+    }
+
+    #[test]
+    fn multi_key_commands_do_not_deadlock() {
+        use std::{sync::mpsc, thread};
+
+        let db = Db::new();
+        let a = "a".to_string();
+        let b = (0..)
+            .map(|i| format!("b{}", i))
+            .find(|key| db.shard_index(key) != db.shard_index(&a))
+            .unwrap();
+
+        // 一半的线程按 a、b 的顺序给出键，另一半反过来，锁的顺序都一样
+        let (done, finished) = mpsc::channel();
+        for i in 0..4 {
+            let db = db.clone();
+            let keys = match i % 2 {
+                0 => [a.clone(), b.clone()],
+                _ => [b.clone(), a.clone()],
+            };
+            let done = done.clone();
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    db.with_keys(&keys, |shards| {
+                        shards.shard(&keys[0]);
+                        thread::yield_now();
+                        shards.shard(&keys[1]);
+                    });
+                }
+                done.send(()).unwrap();
+            });
+        }
+        for _ in 0..4 {
+            finished
+                .recv_timeout(Duration::from_secs(10))
+                .expect("deadlocked");
+        }
+    }
+
+    #[tokio::test]
+    async fn restored_expiries_follow_the_clock() {
+        let clock = Clock::paused();
+        let db = Db::with_clock(clock.clone());
+        let record = |key: &str, expires_at| Record {
+            key: key.to_string(),
+            value: Value::String(Bytes::from("v")),
+            expires_at,
+        };
+        let at = clock.system_time() + Duration::from_secs(1);
+        assert!(db.restore(record("k", Some(at))));
+        assert!(!db.restore(record("old", Some(clock.system_time() - MS))));
+
+        // 保存的过期时间也按数据库的时钟换算
+        let dumped: Vec<Record> = db.dump().flatten().collect();
+        assert_eq!(dumped, vec![record("k", Some(at))]);
+        clock.advance(999 * MS);
+        assert_eq!(run(&db, &["PTTL", "k"]).await, Frame::Integer(1));
+        clock.advance(MS);
+        assert_eq!(run(&db, &["EXISTS", "k"]).await, Frame::Integer(0));
+    }
+}
@@ -0,0 +1,381 @@
+//! Incremental frame decoder.
+//! 解码器直接从连接的读缓冲区里取出帧，每个字节只扫描一次：只收到一部分的帧会记住解析的进度，
+//! 读到更多数据以后接着解析，不需要从头再来；bulk string 直接从缓冲区里切出来，不需要复制。
+
+use bytes::{Buf, Bytes, BytesMut};
+
+use super::frame::{self, Error, Frame, Limits};
+
+/// Blob strings shorter than this are copied out of the buffer: a slice would keep the whole read
+/// buffer alive as long as the value, for instance while it is stored in the database
+const COPY_BELOW: usize = 1024;
+
+/// The most entries allocated up front for an aggregate, whatever length it announces
+const MAX_PREALLOCATED: usize = 1024;
+
+/// Decodes the frames of a stream as its data arrives.
+///
+/// The data of a frame is taken out of the buffer as soon as it is decoded, the decoder keeps the
+/// part of the frame received so far until the rest arrives. Like with `Frame::check`, a frame
+/// over the limits is an error as soon as its header arrives.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// The aggregates being decoded, the innermost last
+    stack: Vec<Partial>,
+    /// The type byte and the length of the blob string whose header was decoded, but not all of
+    /// its data arrived yet
+    blob: Option<(u8, usize)>,
+    /// How far the start of the buffer was searched for the end of the current line
+    scanned: usize,
+    /// The size of the part of the current frame already taken out of the buffer
+    taken: usize,
+}
+
+#[derive(Debug)]
+struct Partial {
+    kind: u8,
+    /// The number of frames of the aggregate: twice the number of pairs of a map, plus one for
+    /// the frame an attribute describes
+    len: usize,
+    entries: Vec<Frame>,
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Decode the next frame at the start of `buf`, `Ok(None)` if it has not fully arrived yet.
+    ///
+    /// Like a connection, `buf` may start with an inline command instead of a frame.
+    pub fn decode(&mut self, buf: &mut BytesMut, limits: &Limits) -> Result<Option<Frame>, Error> {
+        loop {
+            let Some(mut frame) = self.next(buf, limits)? else {
+                return Ok(None);
+            };
+            // 把帧放进外层的聚合类型，聚合类型收齐了就继续放进更外层
+            loop {
+                let Some(partial) = self.stack.last_mut() else {
+                    self.taken = 0;
+                    return Ok(Some(frame));
+                };
+                partial.entries.push(frame);
+                if partial.entries.len() < partial.len {
+                    break;
+                }
+                frame = self.stack.pop().unwrap().into_frame();
+            }
+        }
+    }
+
+    /// The size of the data of the frame being decoded that the decoder holds, it is no longer in
+    /// the buffer
+    pub fn pending_len(&self) -> usize {
+        self.taken
+    }
+
+    /// Decode a frame that is not an aggregate, or an empty aggregate. The headers of the other
+    /// aggregates are pushed on the stack.
+    fn next(&mut self, buf: &mut BytesMut, limits: &Limits) -> Result<Option<Frame>, Error> {
+        loop {
+            if let Some((kind, len)) = self.blob {
+                if buf.len() < len + 2 {
+                    return Ok(None);
+                }
+                self.blob = None;
+                return self.take_blob(buf, kind, len).map(Some);
+            }
+
+            let Some(&kind) = buf.first() else {
+                return Ok(None);
+            };
+            if self.stack.is_empty() && !Frame::is_type_byte(kind) {
+                match self.inline(buf)? {
+                    None => return Ok(None),
+                    // 和 redis 一样忽略空行
+                    Some(args) if args.is_empty() => {
+                        self.taken = 0;
+                        continue;
+                    }
+                    Some(args) => return Ok(Some(Frame::bulks(args))),
+                }
+            }
+
+            let Some(end) = self.line_end(buf) else {
+                return Ok(None);
+            };
+            let line = &buf[1..end];
+            let frame = match kind {
+                b'+' => Frame::Simple(String::from_utf8(line.to_vec())?),
+                b'-' => Frame::Error(String::from_utf8(line.to_vec())?),
+                b':' => Frame::Integer(frame::parse_integer(line)?),
+                b',' => Frame::Double(frame::parse_double(line)?),
+                b'#' => Frame::Boolean(frame::parse_boolean(line)?),
+                b'(' => Frame::BigNumber(frame::parse_big_number(line)?),
+                b'_' => {
+                    frame::parse_null(line)?;
+                    Frame::Null
+                }
+                b'$' | b'!' | b'=' => match (kind, frame::parse_length(line)?) {
+                    (b'$', None) => Frame::Null,
+                    (_, None) => return Err("invalid blob length".into()),
+                    (_, Some(len)) if len > limits.max_bulk_len => {
+                        return Err("invalid bulk length".into())
+                    }
+                    (_, Some(len)) => {
+                        self.advance(buf, end + 2);
+                        self.blob = Some((kind, len));
+                        continue;
+                    }
+                },
+                b'*' | b'~' | b'>' | b'%' | b'|' => {
+                    let len = match (kind, frame::parse_length(line)?) {
+                        (b'*', None) => {
+                            self.advance(buf, end + 2);
+                            return Ok(Some(Frame::NullArray));
+                        }
+                        (_, None) => return Err("invalid aggregate length".into()),
+                        (_, Some(len)) if len > limits.max_array_len => {
+                            return Err("invalid multibulk length".into())
+                        }
+                        (_, Some(len)) => len,
+                    };
+                    if self.stack.len() >= limits.max_depth {
+                        return Err("too deep nesting".into());
+                    }
+                    self.advance(buf, end + 2);
+
+                    let len = match kind {
+                        b'%' => len * 2,
+                        b'|' => len * 2 + 1,
+                        _ => len,
+                    };
+                    let partial = Partial {
+                        kind,
+                        len,
+                        entries: Vec::with_capacity(len.min(MAX_PREALLOCATED)),
+                    };
+                    if len == 0 {
+                        return Ok(Some(partial.into_frame()));
+                    }
+                    self.stack.push(partial);
+                    continue;
+                }
+                actual => return Err(format!("invalid frame type byte `{}`", actual).into()),
+            };
+            self.advance(buf, end + 2);
+            return Ok(Some(frame));
+        }
+    }
+
+    /// Take the data of a blob string and its `\r\n` out of `buf`
+    fn take_blob(&mut self, buf: &mut BytesMut, kind: u8, len: usize) -> Result<Frame, Error> {
+        frame::check_blob_end(buf, len)?;
+        let data = if len < COPY_BELOW {
+            let data = Bytes::copy_from_slice(&buf[..len]);
+            buf.advance(len);
+            data
+        } else {
+            buf.split_to(len).freeze()
+        };
+        self.taken += len;
+        self.advance(buf, 2);
+
+        match kind {
+            b'$' => Ok(Frame::Bulk(data)),
+            b'!' => Ok(Frame::Error(String::from_utf8(data.to_vec())?)),
+            _ => frame::parse_verbatim(data),
+        }
+    }
+
+    /// Take an inline command out of `buf`, `None` if its line has not fully arrived
+    fn inline(&mut self, buf: &mut BytesMut) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        let Some(len) = buf[self.scanned..].iter().position(|&b| b == b'\n') else {
+            self.scanned = buf.len();
+            return Ok(None);
+        };
+        let end = self.scanned + len;
+        let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+        let args = frame::split_args(line).ok_or("unbalanced quotes in request")?;
+        self.advance(buf, end + 1);
+        Ok(Some(args))
+    }
+
+    /// The position of the `\r\n` that ends the line at the start of `buf`, after its type byte
+    fn line_end(&mut self, buf: &[u8]) -> Option<usize> {
+        let from = self.scanned.max(1);
+        match buf[from..].windows(2).position(|window| window == b"\r\n") {
+            Some(len) => Some(from + len),
+            None => {
+                // 最后一个字节可能是 `\r`，下次从它开始找
+                self.scanned = buf.len() - 1;
+                None
+            }
+        }
+    }
+
+    /// Take `n` bytes of the current frame out of `buf`
+    fn advance(&mut self, buf: &mut BytesMut, n: usize) {
+        buf.advance(n);
+        self.taken += n;
+        self.scanned = 0;
+    }
+}
+
+impl Partial {
+    fn into_frame(self) -> Frame {
+        match self.kind {
+            b'~' => Frame::Set(self.entries),
+            b'>' => Frame::Push(self.entries),
+            b'%' => Frame::Map(pairs(self.entries)),
+            b'|' => {
+                let mut entries = self.entries;
+                let frame = entries.pop().unwrap();
+                Frame::Attribute {
+                    attributes: pairs(entries),
+                    frame: Box::new(frame),
+                }
+            }
+            _ => Frame::Array(self.entries),
+        }
+    }
+}
+
+fn pairs(entries: Vec<Frame>) -> Vec<(Frame, Frame)> {
+    let mut entries = entries.into_iter();
+    let mut pairs = Vec::with_capacity(entries.len() / 2);
+    while let (Some(key), Some(value)) = (entries.next(), entries.next()) {
+        pairs.push((key, value));
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::myredis::frame::Protocol;
+
+    fn encode(frames: &[Frame]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for frame in frames {
+            frame.encode(Protocol::Resp3, &mut buf);
+        }
+        buf
+    }
+
+    fn bulk(val: &'static str) -> Frame {
+        Frame::Bulk(Bytes::from(val))
+    }
+
+    /// Frames nested in aggregates of every kind
+    fn nested() -> Frame {
+        Frame::Array(vec![
+            Frame::Map(vec![(
+                bulk("k"),
+                Frame::Set(vec![Frame::Integer(1), Frame::Double(2.5)]),
+            )]),
+            Frame::Push(vec![
+                Frame::Null,
+                Frame::Boolean(true),
+                Frame::Array(vec![]),
+            ]),
+            Frame::Attribute {
+                attributes: vec![(bulk("a"), Frame::BigNumber("123".into()))],
+                frame: Box::new(Frame::Verbatim {
+                    format: "txt".into(),
+                    data: Bytes::from("hi"),
+                }),
+            },
+            Frame::Bulk(Bytes::from(vec![b'x'; COPY_BELOW])),
+            Frame::Simple("OK".into()),
+            Frame::Error("ERR e".into()),
+        ])
+    }
+
+    /// Decode the frames in `reads`, each added to the buffer after the frames of the previous
+    /// reads were decoded
+    fn decode_reads<'a>(reads: impl IntoIterator<Item = &'a [u8]>) -> Vec<Frame> {
+        let mut decoder = Decoder::new();
+        let mut buf = BytesMut::new();
+        let mut frames = Vec::new();
+        for read in reads {
+            buf.extend_from_slice(read);
+            while let Some(frame) = decoder.decode(&mut buf, &Limits::default()).unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(decoder.pending_len(), 0);
+        frames
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let frames = vec![nested(), bulk(""), Frame::Null, Frame::bulks(["PING"])];
+        let mut data = encode(&frames[..3]);
+        data.extend_from_slice(b"\r\nPING\r\n");
+        let decoded = decode_reads(data.chunks(1));
+        assert_eq!(decoded, frames);
+    }
+
+    #[test]
+    fn nested_aggregates_split_across_reads() {
+        let frame = nested();
+        let data = encode(&[nested()]);
+        for at in 1..data.len() {
+            let (first, second) = data.split_at(at);
+            assert_eq!(decode_reads([first, second]), vec![frame.clone()], "{}", at);
+        }
+        for (a, b) in [(1, 2), (5, 60), (30, data.len() - 1)] {
+            let reads = [&data[..a], &data[a..b], &data[b..]];
+            assert_eq!(decode_reads(reads), vec![frame.clone()]);
+        }
+    }
+
+    #[test]
+    fn large_values_are_not_copied() {
+        let small = Frame::Bulk(Bytes::from(vec![b's'; COPY_BELOW - 1]));
+        let large = Frame::Bulk(Bytes::from(vec![b'l'; COPY_BELOW]));
+        let data = encode(&[Frame::Array(vec![small, large])]);
+        let mut buf = BytesMut::from(&data[..]);
+        let range = buf.as_ptr_range();
+
+        let decoded = Decoder::new().decode(&mut buf, &Limits::default());
+        let Some(Frame::Array(values)) = decoded.unwrap() else {
+            panic!("not an array");
+        };
+        let [Frame::Bulk(small), Frame::Bulk(large)] = &values[..] else {
+            panic!("not two bulk strings");
+        };
+        // 小的值复制出来，大的值和读缓冲区共用内存
+        assert!(!range.contains(&small.as_ptr()));
+        assert!(range.contains(&large.as_ptr()));
+        assert_eq!(large.len(), COPY_BELOW);
+    }
+
+    #[test]
+    fn blob_data_must_end_with_crlf() {
+        for data in [
+            &b"$3\r\nfooXY"[..],
+            b"*1\r\n!3\r\nfoo\n\n",
+            b"=6\r\ntxt:hi\r\r",
+        ] {
+            let mut buf = BytesMut::from(data);
+            let err = Decoder::new()
+                .decode(&mut buf, &Limits::default())
+                .unwrap_err();
+            assert_eq!(err.to_string(), "expected CRLF after blob data");
+        }
+
+        // 数据后面还没有收到两个字节时等待
+        let mut decoder = Decoder::new();
+        let mut buf = BytesMut::from(&b"$3\r\nfoo\r"[..]);
+        assert!(decoder
+            .decode(&mut buf, &Limits::default())
+            .unwrap()
+            .is_none());
+        buf.extend_from_slice(b"\n");
+        let frame = decoder.decode(&mut buf, &Limits::default()).unwrap();
+        assert_eq!(frame, Some(bulk("foo")));
+    }
+}
@@ -0,0 +1,904 @@
+//! RESP frame.
+//! 一个帧就是 redis 协议中的一个值，命令和响应都由帧组成。
+
+use std::{fmt, io::Cursor, num::TryFromIntError, string::FromUtf8Error};
+
+use bytes::{Buf, Bytes};
+
+/// A frame in the Redis protocol.
+///
+/// The frames after `Array` only exist in RESP3, a RESP2 connection receives them as their closest
+/// RESP2 frame, see `Frame::encode`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    /// The null reply of a command that replies with an array, like a BLPOP that timed out.
+    /// RESP2 encodes it as `*-1` instead of the `$-1` of `Null`.
+    NullArray,
+    Array(Vec<Frame>),
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    /// An integer of any size, in decimal
+    BigNumber(String),
+    /// A string to show as is, `format` is a three letters hint like `txt` or `mkd`
+    Verbatim {
+        format: String,
+        data: Bytes,
+    },
+    /// Out-of-band data such as a pub/sub message, which is not the reply to a command
+    Push(Vec<Frame>),
+    /// Auxiliary information about `frame`, a client without use for it ignores it
+    Attribute {
+        attributes: Vec<(Frame, Frame)>,
+        frame: Box<Frame>,
+    },
+}
+
+/// The version of the protocol spoken on a connection, a client switches to RESP3 with HELLO
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/// Limits on the frames read from a peer, so that a broken or malicious peer cannot make the
+/// reader buffer or recurse without bound. A frame over a limit is a protocol error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The size of the largest bulk string, 512 MiB by default like redis' `proto-max-bulk-len`
+    pub max_bulk_len: usize,
+    /// The number of entries of the largest array, set or push, or of pairs of a map
+    pub max_array_len: usize,
+    /// How deep arrays, maps and the other aggregates can be nested, a command is at depth 1
+    pub max_depth: usize,
+    /// How much data a connection buffers without holding a whole frame, 1 GiB by default like
+    /// redis' `client-query-buffer-limit`
+    pub max_buffer_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_array_len: 1024 * 1024,
+            max_depth: 64,
+            max_buffer_len: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Not enough data is available to parse a frame
+    Incomplete,
+    /// Invalid frame encoding
+    Other(super::Error),
+}
+
+impl Frame {
+    /// An array frame of bulk strings
+    pub fn bulks<I, T>(items: I) -> Frame
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Bytes>,
+    {
+        Frame::Array(
+            items
+                .into_iter()
+                .map(|item| Frame::Bulk(item.into()))
+                .collect(),
+        )
+    }
+
+    pub fn ok() -> Frame {
+        Frame::Simple("OK".to_string())
+    }
+
+    /// Append the encoding of the frame in `protocol` to `dst`.
+    ///
+    /// In RESP2 maps are flattened to arrays of keys and values, sets and pushes become arrays,
+    /// booleans integers, the other RESP3 scalars bulk strings, and attributes are left out.
+    pub fn encode(&self, protocol: Protocol, dst: &mut Vec<u8>) {
+        let resp3 = protocol == Protocol::Resp3;
+        match self {
+            Frame::Simple(val) => encode_line(b'+', val, dst),
+            Frame::Error(val) => encode_line(b'-', val, dst),
+            Frame::Integer(val) => {
+                dst.extend_from_slice(format!(":{}\r\n", val).as_bytes());
+            }
+            Frame::Bulk(val) => encode_bulk(b'$', val, dst),
+            Frame::Null if resp3 => dst.extend_from_slice(b"_\r\n"),
+            Frame::Null => dst.extend_from_slice(b"$-1\r\n"),
+            Frame::NullArray if resp3 => dst.extend_from_slice(b"_\r\n"),
+            Frame::NullArray => dst.extend_from_slice(b"*-1\r\n"),
+            Frame::Array(val) => encode_aggregate(b'*', val, protocol, dst),
+            Frame::Map(val) => {
+                // RESP2 的 map 就是键值交替的数组
+                let (kind, len) = if resp3 {
+                    (b'%', val.len())
+                } else {
+                    (b'*', val.len() * 2)
+                };
+                dst.extend_from_slice(format!("{}{}\r\n", kind as char, len).as_bytes());
+                for (key, value) in val {
+                    key.encode(protocol, dst);
+                    value.encode(protocol, dst);
+                }
+            }
+            Frame::Set(val) => {
+                encode_aggregate(if resp3 { b'~' } else { b'*' }, val, protocol, dst)
+            }
+            Frame::Push(val) => {
+                encode_aggregate(if resp3 { b'>' } else { b'*' }, val, protocol, dst)
+            }
+            Frame::Double(val) => {
+                let val = format_double(*val);
+                match resp3 {
+                    true => dst.extend_from_slice(format!(",{}\r\n", val).as_bytes()),
+                    false => encode_bulk(b'$', val.as_bytes(), dst),
+                }
+            }
+            Frame::Boolean(val) => match (resp3, val) {
+                (true, true) => dst.extend_from_slice(b"#t\r\n"),
+                (true, false) => dst.extend_from_slice(b"#f\r\n"),
+                (false, val) => Frame::Integer(*val as i64).encode(protocol, dst),
+            },
+            Frame::BigNumber(val) => match resp3 {
+                true => dst.extend_from_slice(format!("({}\r\n", val).as_bytes()),
+                false => encode_bulk(b'$', val.as_bytes(), dst),
+            },
+            Frame::Verbatim { format, data } => match resp3 {
+                true => {
+                    let mut val = Vec::with_capacity(format.len() + 1 + data.len());
+                    val.extend_from_slice(format.as_bytes());
+                    val.push(b':');
+                    val.extend_from_slice(data);
+                    encode_bulk(b'=', &val, dst);
+                }
+                false => encode_bulk(b'$', data, dst),
+            },
+            Frame::Attribute { attributes, frame } => {
+                if resp3 {
+                    dst.extend_from_slice(format!("|{}\r\n", attributes.len()).as_bytes());
+                    for (key, value) in attributes {
+                        key.encode(protocol, dst);
+                        value.encode(protocol, dst);
+                    }
+                }
+                frame.encode(protocol, dst);
+            }
+        }
+    }
+
+    /// Check if an entire frame can be decoded from `src`, the cursor is moved past the frame.
+    ///
+    /// A frame over one of the `limits` is invalid, even before all of it is received.
+    pub fn check(src: &mut Cursor<&[u8]>, limits: &Limits) -> Result<(), Error> {
+        check_nested(src, limits, 0)
+    }
+
+    /// Parse a frame, the frame must have been validated with `check`
+    pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+        match get_u8(src)? {
+            b'+' => {
+                let line = get_line(src)?.to_vec();
+                Ok(Frame::Simple(String::from_utf8(line)?))
+            }
+            b'-' => {
+                let line = get_line(src)?.to_vec();
+                Ok(Frame::Error(String::from_utf8(line)?))
+            }
+            b':' => Ok(Frame::Integer(get_integer(src)?)),
+            b',' => Ok(Frame::Double(get_double(src)?)),
+            b'#' => Ok(Frame::Boolean(get_boolean(src)?)),
+            b'_' => {
+                get_null(src)?;
+                Ok(Frame::Null)
+            }
+            b'(' => Ok(Frame::BigNumber(parse_big_number(get_line(src)?)?)),
+            b'$' => Ok(get_blob(src)?.map_or(Frame::Null, Frame::Bulk)),
+            // 二进制安全的错误
+            b'!' => {
+                let data = get_blob(src)?.ok_or("invalid blob error length")?;
+                Ok(Frame::Error(String::from_utf8(data.to_vec())?))
+            }
+            b'=' => parse_verbatim(get_blob(src)?.ok_or("invalid verbatim string length")?),
+            b'*' => match get_length(src)? {
+                None => Ok(Frame::NullArray),
+                Some(len) => Ok(Frame::Array(parse_entries(src, len)?)),
+            },
+            b'~' => {
+                let len = get_length(src)?.ok_or("invalid set length")?;
+                Ok(Frame::Set(parse_entries(src, len)?))
+            }
+            b'>' => {
+                let len = get_length(src)?.ok_or("invalid push length")?;
+                Ok(Frame::Push(parse_entries(src, len)?))
+            }
+            b'%' => {
+                let len = get_length(src)?.ok_or("invalid map length")?;
+                Ok(Frame::Map(parse_pairs(src, len)?))
+            }
+            b'|' => {
+                let len = get_length(src)?.ok_or("invalid attribute length")?;
+                let attributes = parse_pairs(src, len)?;
+                Ok(Frame::Attribute {
+                    attributes,
+                    frame: Box::new(Frame::parse(src)?),
+                })
+            }
+            actual => Err(format!("invalid frame type byte `{}`", actual).into()),
+        }
+    }
+}
+
+impl Frame {
+    /// Whether `byte` starts a frame, a request starting with any other byte is an inline command
+    pub fn is_type_byte(byte: u8) -> bool {
+        matches!(
+            byte,
+            b'+' | b'-'
+                | b':'
+                | b'$'
+                | b'*'
+                | b'_'
+                | b','
+                | b'#'
+                | b'('
+                | b'!'
+                | b'='
+                | b'%'
+                | b'~'
+                | b'>'
+                | b'|'
+        )
+    }
+
+    /// Parse an inline command, a line of arguments separated by spaces such as
+    /// `SET key "hello world"` typed in telnet, into an array of bulk strings. The cursor is moved
+    /// past the line, which may end with `\n` alone. A blank line gives an empty array.
+    ///
+    /// Like in redis, arguments in double quotes may contain the escapes `\n`, `\r`, `\t`, `\b`,
+    /// `\a`, `\xHH` and a backslash before any other character, arguments in single quotes only `\'`.
+    pub fn parse_inline(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+        let start = src.position() as usize;
+        let buf = &src.get_ref()[start..];
+        let Some(len) = buf.iter().position(|&b| b == b'\n') else {
+            return Err(Error::Incomplete);
+        };
+        src.set_position((start + len + 1) as u64);
+
+        let line = buf[..len].strip_suffix(b"\r").unwrap_or(&buf[..len]);
+        let args = split_args(line).ok_or("unbalanced quotes in request")?;
+        Ok(Frame::bulks(args))
+    }
+}
+
+/// Split an inline command into its arguments, `None` if the quotes are unbalanced
+pub(super) fn split_args(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut args = Vec::new();
+    let mut i = 0;
+    loop {
+        while line.get(i).is_some_and(u8::is_ascii_whitespace) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(args);
+        }
+
+        let mut arg = Vec::new();
+        let mut quote = None;
+        loop {
+            match (quote, line.get(i).copied()) {
+                (Some(_), None) => return None,
+                (None, None) => break,
+                (Some(b'"'), Some(b'\\')) => {
+                    let hex = line
+                        .get(i + 2..i + 4)
+                        .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                        .and_then(|hex| {
+                            u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+                        });
+                    match (line.get(i + 1), hex) {
+                        (Some(b'x'), Some(byte)) => {
+                            arg.push(byte);
+                            i += 3;
+                        }
+                        (Some(&escaped), _) => {
+                            arg.push(match escaped {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                other => other,
+                            });
+                            i += 1;
+                        }
+                        (None, _) => return None,
+                    }
+                }
+                (Some(b'\''), Some(b'\\')) if line.get(i + 1) == Some(&b'\'') => {
+                    arg.push(b'\'');
+                    i += 1;
+                }
+                (Some(closing), Some(b)) if b == closing => {
+                    // 引号结束之后必须是空白或者行尾
+                    if line.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    i += 1;
+                    break;
+                }
+                (Some(_), Some(b)) => arg.push(b),
+                (None, Some(b)) if b.is_ascii_whitespace() => break,
+                (None, Some(b)) if b == b'"' || b == b'\'' => quote = Some(b),
+                (None, Some(b)) => arg.push(b),
+            }
+            i += 1;
+        }
+        args.push(arg);
+    }
+}
+
+/// Check a frame nested in `depth` aggregates
+fn check_nested(src: &mut Cursor<&[u8]>, limits: &Limits, depth: usize) -> Result<(), Error> {
+    match get_u8(src)? {
+        b'+' | b'-' | b'(' => {
+            get_line(src)?;
+            Ok(())
+        }
+        b':' => {
+            get_integer(src)?;
+            Ok(())
+        }
+        b',' => {
+            get_double(src)?;
+            Ok(())
+        }
+        b'#' => {
+            get_boolean(src)?;
+            Ok(())
+        }
+        b'_' => get_null(src),
+        b'$' | b'!' | b'=' => match get_length(src)? {
+            // `$-1\r\n`
+            None => Ok(()),
+            Some(len) if len > limits.max_bulk_len => Err("invalid bulk length".into()),
+            Some(len) => {
+                if src.remaining() < len + 2 {
+                    return Err(Error::Incomplete);
+                }
+                check_blob_end(src.chunk(), len)?;
+                skip(src, len + 2)
+            }
+        },
+        kind @ (b'*' | b'~' | b'>' | b'%' | b'|') => {
+            let len = match (kind, get_length(src)?) {
+                (b'*', None) => return Ok(()),
+                (_, None) => return Err("invalid aggregate length".into()),
+                (_, Some(len)) if len > limits.max_array_len => {
+                    return Err("invalid multibulk length".into())
+                }
+                (_, Some(len)) => len,
+            };
+            if depth >= limits.max_depth {
+                return Err("too deep nesting".into());
+            }
+            // map 和属性的每一项是一个键值对
+            let entries = match kind {
+                b'%' | b'|' => len * 2,
+                _ => len,
+            };
+            for _ in 0..entries {
+                check_nested(src, limits, depth + 1)?;
+            }
+            // 属性后面紧跟着它描述的帧
+            match kind {
+                b'|' => check_nested(src, limits, depth + 1),
+                _ => Ok(()),
+            }
+        }
+        actual => Err(format!("invalid frame type byte `{}`", actual).into()),
+    }
+}
+
+/// Encode a simple string or a simple error, a line break in it would end the frame early so it
+/// is replaced by a space like redis does
+fn encode_line(kind: u8, val: &str, dst: &mut Vec<u8>) {
+    dst.push(kind);
+    dst.extend(
+        val.bytes()
+            .map(|b| if b == b'\r' || b == b'\n' { b' ' } else { b }),
+    );
+    dst.extend_from_slice(b"\r\n");
+}
+
+/// Encode a blob string: the kind byte, the length and the data
+fn encode_bulk(kind: u8, val: &[u8], dst: &mut Vec<u8>) {
+    dst.extend_from_slice(format!("{}{}\r\n", kind as char, val.len()).as_bytes());
+    dst.extend_from_slice(val);
+    dst.extend_from_slice(b"\r\n");
+}
+
+fn encode_aggregate(kind: u8, entries: &[Frame], protocol: Protocol, dst: &mut Vec<u8>) {
+    dst.extend_from_slice(format!("{}{}\r\n", kind as char, entries.len()).as_bytes());
+    for entry in entries {
+        entry.encode(protocol, dst);
+    }
+}
+
+/// Format a double the way RESP3 spells it, with `inf`, `-inf` and `nan`
+fn format_double(val: f64) -> String {
+    match val {
+        val if val.is_nan() => "nan".to_string(),
+        f64::INFINITY => "inf".to_string(),
+        f64::NEG_INFINITY => "-inf".to_string(),
+        val => val.to_string(),
+    }
+}
+
+fn parse_entries(src: &mut Cursor<&[u8]>, len: usize) -> Result<Vec<Frame>, Error> {
+    let mut entries = Vec::with_capacity(len);
+    for _ in 0..len {
+        entries.push(Frame::parse(src)?);
+    }
+    Ok(entries)
+}
+
+fn parse_pairs(src: &mut Cursor<&[u8]>, len: usize) -> Result<Vec<(Frame, Frame)>, Error> {
+    let mut pairs = Vec::with_capacity(len);
+    for _ in 0..len {
+        pairs.push((Frame::parse(src)?, Frame::parse(src)?));
+    }
+    Ok(pairs)
+}
+
+impl From<Bytes> for Frame {
+    fn from(data: Bytes) -> Self {
+        Frame::Bulk(data)
+    }
+}
+
+impl From<Option<Bytes>> for Frame {
+    fn from(data: Option<Bytes>) -> Self {
+        data.map_or(Frame::Null, Frame::Bulk)
+    }
+}
+
+impl From<i64> for Frame {
+    fn from(value: i64) -> Self {
+        Frame::Integer(value)
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Simple(val) | Frame::BigNumber(val) => val.fmt(f),
+            Frame::Error(val) => write!(f, "(error) {}", val),
+            Frame::Integer(val) => write!(f, "(integer) {}", val),
+            Frame::Bulk(val) => write!(f, "{:?}", val),
+            Frame::Null | Frame::NullArray => "(nil)".fmt(f),
+            Frame::Array(entries) | Frame::Set(entries) | Frame::Push(entries) => {
+                for (i, entry) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    entry.fmt(f)?;
+                }
+                Ok(())
+            }
+            Frame::Map(pairs) => {
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{} => {}", key, value)?;
+                }
+                Ok(())
+            }
+            Frame::Double(val) => write!(f, "(double) {}", format_double(*val)),
+            Frame::Boolean(val) => write!(f, "({})", val),
+            Frame::Verbatim { data, .. } => String::from_utf8_lossy(data).fmt(f),
+            Frame::Attribute { frame, .. } => frame.fmt(f),
+        }
+    }
+}
+
+fn get_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+    Ok(src.get_u8())
+}
+
+fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
+    if src.remaining() < n {
+        return Err(Error::Incomplete);
+    }
+    src.advance(n);
+    Ok(())
+}
+
+/// Read a new-line terminated integer
+fn get_integer(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+    parse_integer(get_line(src)?)
+}
+
+/// Read the length of a bulk string or an array, `-1` stands for null
+fn get_length(src: &mut Cursor<&[u8]>) -> Result<Option<usize>, Error> {
+    parse_length(get_line(src)?)
+}
+
+/// Read the data of a blob string, `None` for the RESP2 null bulk string
+fn get_blob(src: &mut Cursor<&[u8]>) -> Result<Option<Bytes>, Error> {
+    let Some(len) = get_length(src)? else {
+        return Ok(None);
+    };
+    if src.remaining() < len + 2 {
+        return Err(Error::Incomplete);
+    }
+    check_blob_end(src.chunk(), len)?;
+    let data = Bytes::copy_from_slice(&src.chunk()[..len]);
+    skip(src, len + 2)?;
+    Ok(Some(data))
+}
+
+/// Read a new-line terminated double, which may be `inf`, `-inf` or `nan`
+fn get_double(src: &mut Cursor<&[u8]>) -> Result<f64, Error> {
+    parse_double(get_line(src)?)
+}
+
+fn get_boolean(src: &mut Cursor<&[u8]>) -> Result<bool, Error> {
+    parse_boolean(get_line(src)?)
+}
+
+/// Read the rest of a RESP3 null, which is an empty line
+fn get_null(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+    parse_null(get_line(src)?)
+}
+
+/// Read a line terminated by `\r\n`, the cursor is moved past the terminator
+fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
+    let start = src.position() as usize;
+    let buf = *src.get_ref();
+
+    match buf[start..].windows(2).position(|window| window == b"\r\n") {
+        Some(len) => {
+            src.set_position((start + len + 2) as u64);
+            Ok(&buf[start..start + len])
+        }
+        None => Err(Error::Incomplete),
+    }
+}
+
+/// Check that the `len` bytes of data of a blob string at the start of `buf` are followed by
+/// `\r\n`, `buf` must hold them
+pub(super) fn check_blob_end(buf: &[u8], len: usize) -> Result<(), Error> {
+    match &buf[len..len + 2] {
+        b"\r\n" => Ok(()),
+        _ => Err("expected CRLF after blob data".into()),
+    }
+}
+
+// 下面的函数解析一行的内容，不包括开头的类型字节和结尾的 `\r\n`，`Decoder` 也使用它们
+
+pub(super) fn parse_integer(line: &[u8]) -> Result<i64, Error> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| "invalid integer".into())
+}
+
+/// The length of a blob string or an aggregate, `-1` stands for null
+pub(super) fn parse_length(line: &[u8]) -> Result<Option<usize>, Error> {
+    match parse_integer(line)? {
+        -1 => Ok(None),
+        len => Ok(Some(usize::try_from(len)?)),
+    }
+}
+
+pub(super) fn parse_double(line: &[u8]) -> Result<f64, Error> {
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(|| "invalid double".into())
+}
+
+pub(super) fn parse_boolean(line: &[u8]) -> Result<bool, Error> {
+    match line {
+        b"t" => Ok(true),
+        b"f" => Ok(false),
+        _ => Err("invalid boolean".into()),
+    }
+}
+
+pub(super) fn parse_null(line: &[u8]) -> Result<(), Error> {
+    match line {
+        b"" => Ok(()),
+        _ => Err("invalid null".into()),
+    }
+}
+
+pub(super) fn parse_big_number(line: &[u8]) -> Result<String, Error> {
+    let number = String::from_utf8(line.to_vec())?;
+    let digits = number.strip_prefix('-').unwrap_or(&number);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err("invalid big number".into());
+    }
+    Ok(number)
+}
+
+/// The frame of the data of a verbatim string, which starts with its three letters format
+pub(super) fn parse_verbatim(data: Bytes) -> Result<Frame, Error> {
+    // 前三个字节是格式，后面跟着一个冒号
+    if data.len() < 4 || data[3] != b':' {
+        return Err("invalid verbatim string".into());
+    }
+    Ok(Frame::Verbatim {
+        format: String::from_utf8(data[..3].to_vec())?,
+        data: data.slice(4..),
+    })
+}
+
+impl From<String> for Error {
+    fn from(src: String) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(src: &str) -> Error {
+        src.to_string().into()
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(_src: FromUtf8Error) -> Error {
+        "invalid frame format".into()
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(_src: TryFromIntError) -> Error {
+        "invalid frame format".into()
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Incomplete => "stream ended early".fmt(f),
+            Error::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(frame: &Frame, protocol: Protocol) -> String {
+        let mut buf = Vec::new();
+        frame.encode(protocol, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn decode(buf: &[u8]) -> Frame {
+        let mut cursor = Cursor::new(buf);
+        Frame::check(&mut cursor, &Limits::default()).unwrap();
+        assert_eq!(cursor.position() as usize, buf.len());
+        cursor.set_position(0);
+        Frame::parse(&mut cursor).unwrap()
+    }
+
+    fn bulk(val: &'static str) -> Frame {
+        Frame::Bulk(Bytes::from(val))
+    }
+
+    /// Frames of every kind, with their RESP3 and RESP2 encodings
+    fn frames() -> Vec<(Frame, &'static str, &'static str)> {
+        vec![
+            (Frame::Simple("OK".into()), "+OK\r\n", "+OK\r\n"),
+            (Frame::Error("ERR no".into()), "-ERR no\r\n", "-ERR no\r\n"),
+            (Frame::Integer(-7), ":-7\r\n", ":-7\r\n"),
+            (bulk("a\r\nb"), "$4\r\na\r\nb\r\n", "$4\r\na\r\nb\r\n"),
+            (Frame::Null, "_\r\n", "$-1\r\n"),
+            (
+                Frame::Array(vec![bulk("a"), Frame::Integer(1)]),
+                "*2\r\n$1\r\na\r\n:1\r\n",
+                "*2\r\n$1\r\na\r\n:1\r\n",
+            ),
+            (
+                Frame::Map(vec![(bulk("k"), Frame::Boolean(true))]),
+                "%1\r\n$1\r\nk\r\n#t\r\n",
+                "*2\r\n$1\r\nk\r\n:1\r\n",
+            ),
+            (
+                Frame::Set(vec![bulk("m")]),
+                "~1\r\n$1\r\nm\r\n",
+                "*1\r\n$1\r\nm\r\n",
+            ),
+            (Frame::Double(1.5), ",1.5\r\n", "$3\r\n1.5\r\n"),
+            (
+                Frame::Double(f64::NEG_INFINITY),
+                ",-inf\r\n",
+                "$4\r\n-inf\r\n",
+            ),
+            (Frame::Boolean(false), "#f\r\n", ":0\r\n"),
+            (
+                Frame::BigNumber("-123456789012345678901234567890".into()),
+                "(-123456789012345678901234567890\r\n",
+                "$31\r\n-123456789012345678901234567890\r\n",
+            ),
+            (
+                Frame::Verbatim {
+                    format: "txt".into(),
+                    data: Bytes::from("hi"),
+                },
+                "=6\r\ntxt:hi\r\n",
+                "$2\r\nhi\r\n",
+            ),
+            (
+                Frame::Push(vec![bulk("message"), bulk("c"), bulk("x")]),
+                ">3\r\n$7\r\nmessage\r\n$1\r\nc\r\n$1\r\nx\r\n",
+                "*3\r\n$7\r\nmessage\r\n$1\r\nc\r\n$1\r\nx\r\n",
+            ),
+            (
+                Frame::Attribute {
+                    attributes: vec![(bulk("ttl"), Frame::Integer(3))],
+                    frame: Box::new(Frame::Integer(1)),
+                },
+                "|1\r\n$3\r\nttl\r\n:3\r\n:1\r\n",
+                ":1\r\n",
+            ),
+        ]
+    }
+
+    #[test]
+    fn encode_resp3() {
+        for (frame, resp3, _) in frames() {
+            assert_eq!(encode(&frame, Protocol::Resp3), resp3, "{:?}", frame);
+        }
+        // RESP3 只有一种空值，解析回来是 `Frame::Null`
+        assert_eq!(encode(&Frame::NullArray, Protocol::Resp3), "_\r\n");
+    }
+
+    #[test]
+    fn encode_resp2() {
+        for (frame, _, resp2) in frames() {
+            assert_eq!(encode(&frame, Protocol::Resp2), resp2, "{:?}", frame);
+        }
+        assert_eq!(encode(&Frame::NullArray, Protocol::Resp2), "*-1\r\n");
+        // RESP2 的数组里的 RESP3 帧也会转换
+        let nested = Frame::Array(vec![Frame::Null, Frame::Set(vec![Frame::Boolean(true)])]);
+        assert_eq!(
+            encode(&nested, Protocol::Resp2),
+            "*2\r\n$-1\r\n*1\r\n:1\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_resp3() {
+        for (frame, resp3, _) in frames() {
+            assert_eq!(decode(resp3.as_bytes()), frame);
+        }
+        let Frame::Double(nan) = decode(b",nan\r\n") else {
+            panic!("not a double");
+        };
+        assert!(nan.is_nan());
+        assert_eq!(decode(b",inf\r\n"), Frame::Double(f64::INFINITY));
+        assert_eq!(decode(b"!5\r\nERR x\r\n"), Frame::Error("ERR x".into()));
+        assert_eq!(decode(b"*-1\r\n"), Frame::NullArray);
+    }
+
+    fn split(line: &str) -> Option<Vec<String>> {
+        let args = split_args(line.as_bytes())?;
+        let args = args.into_iter().map(|arg| String::from_utf8(arg).unwrap());
+        Some(args.collect())
+    }
+
+    #[test]
+    fn split_inline_arguments() {
+        let cases: &[(&str, &[&str])] = &[
+            ("PING", &["PING"]),
+            ("  GET \t k  ", &["GET", "k"]),
+            ("", &[]),
+            (r#"SET k "hello world""#, &["SET", "k", "hello world"]),
+            (r#""a\x41\n\"\\b""#, &["aA\n\"\\b"]),
+            // 不是十六进制数字的 `\x` 就是 `x`
+            (r#""\xZZ""#, &["xZZ"]),
+            (r"'it\'s' 'a\nb'", &["it's", r"a\nb"]),
+            (r#""" ''"#, &["", ""]),
+            (r#"a"b c" d"#, &["ab c", "d"]),
+        ];
+        for (line, args) in cases {
+            assert_eq!(split(line).unwrap(), *args, "{}", line);
+        }
+
+        for unbalanced in [r#""abc"#, "'abc", r#""abc"def"#, "'abc'def", r#""abc\"#] {
+            assert_eq!(split(unbalanced), None, "{}", unbalanced);
+        }
+    }
+
+    #[test]
+    fn parse_inline_lines() {
+        let buf = b"SET k 'a b'\r\nGET k\n\r\nPING";
+        let mut cursor = Cursor::new(&buf[..]);
+        let parsed = Frame::parse_inline(&mut cursor).unwrap();
+        assert_eq!(parsed, Frame::bulks(["SET", "k", "a b"]));
+        // 只有 `\n` 的行也可以
+        let parsed = Frame::parse_inline(&mut cursor).unwrap();
+        assert_eq!(parsed, Frame::bulks(["GET", "k"]));
+        assert_eq!(
+            Frame::parse_inline(&mut cursor).unwrap(),
+            Frame::Array(vec![])
+        );
+        assert!(matches!(
+            Frame::parse_inline(&mut cursor),
+            Err(Error::Incomplete)
+        ));
+
+        let mut cursor = Cursor::new(&b"GET \"k\r\n"[..]);
+        let Err(Error::Other(err)) = Frame::parse_inline(&mut cursor) else {
+            panic!("unbalanced quotes were parsed");
+        };
+        assert_eq!(err.to_string(), "unbalanced quotes in request");
+    }
+
+    #[test]
+    fn check_limits() {
+        let limits = Limits {
+            max_bulk_len: 3,
+            max_array_len: 2,
+            max_depth: 2,
+            ..Limits::default()
+        };
+        let check = |buf: &[u8]| match Frame::check(&mut Cursor::new(buf), &limits) {
+            Ok(()) => "ok".to_string(),
+            Err(Error::Incomplete) => "incomplete".to_string(),
+            Err(Error::Other(err)) => err.to_string(),
+        };
+
+        assert_eq!(check(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n"), "ok");
+        assert_eq!(check(b"*1\r\n*2\r\n:1\r\n:2\r\n"), "ok");
+        // 超过限制的帧不等剩下的数据到达
+        assert_eq!(check(b"$4\r\n"), "invalid bulk length");
+        assert_eq!(check(b"=4\r\n"), "invalid bulk length");
+        assert_eq!(check(b"*3\r\n"), "invalid multibulk length");
+        assert_eq!(check(b"|3\r\n"), "invalid multibulk length");
+        assert_eq!(check(b"*1\r\n%1\r\n*1\r\n"), "too deep nesting");
+        assert_eq!(check(b"*1\r\n*1\r\n"), "incomplete");
+    }
+
+    #[test]
+    fn blob_data_must_end_with_crlf() {
+        for data in [
+            &b"$3\r\nfooXY"[..],
+            b"*1\r\n!3\r\nfoo\n\n",
+            b"=6\r\ntxt:hi\r\r",
+        ] {
+            let Err(Error::Other(err)) = Frame::check(&mut Cursor::new(data), &Limits::default())
+            else {
+                panic!("{:?} was checked", data);
+            };
+            assert_eq!(err.to_string(), "expected CRLF after blob data");
+            let Err(Error::Other(err)) = Frame::parse(&mut Cursor::new(data)) else {
+                panic!("{:?} was parsed", data);
+            };
+            assert_eq!(err.to_string(), "expected CRLF after blob data");
+        }
+        let check = Frame::check(&mut Cursor::new(&b"$3\r\nfoo\r"[..]), &Limits::default());
+        assert!(matches!(check, Err(Error::Incomplete)));
+    }
+}
@@ -0,0 +1,82 @@
+//! Glob-style pattern matching.
+//! 和 redis 的 `KEYS`、`SCAN ... MATCH`、`PSUBSCRIBE` 使用的模式一样：
+//! `*` 匹配任意多个字节，`?` 匹配一个字节，`[abc]`、`[^abc]`、`[a-z]` 匹配字符集合，`\` 转义下一个字符。
+
+/// Whether `string` matches the glob-style `pattern`
+pub fn matches(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // 最近一个 `*` 之后的模式位置，以及它当前匹配到的字符串位置，匹配失败时从这里回溯
+    let mut backtrack = None;
+
+    while s < string.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => match_class(pattern, p + 1, string[s]),
+            Some(b'\\') if p + 1 < pattern.len() => (pattern[p + 1] == string[s]).then_some(p + 2),
+            Some(&c) => (c == string[s]).then_some(p + 1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            // 让上一个 `*` 多匹配一个字节再试
+            (None, Some((star, matched))) => {
+                p = star;
+                s = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    // 字符串已经匹配完了，剩下的模式只能是 `*`
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+/// Match `c` against the class starting right after `[`, returning the position after `]`
+fn match_class(pattern: &[u8], mut p: usize, c: u8) -> Option<usize> {
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern.get(p) {
+            // 和 redis 一样，没有闭合的 `]` 时把模式的结尾当作 `]`
+            None => break,
+            Some(b']') => {
+                p += 1;
+                break;
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == c;
+                p += 2;
+            }
+            Some(&start) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let end = pattern[p + 2];
+                let (low, high) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= (low..=high).contains(&c);
+                p += 3;
+            }
+            Some(&class) => {
+                matched |= class == c;
+                p += 1;
+            }
+        }
+    }
+
+    (matched != negate).then_some(p)
+}
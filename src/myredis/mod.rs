@@ -1,2 +1,20 @@
+pub mod aof;
+pub mod client;
+pub mod cmd;
 pub mod connection;
+pub mod db;
+pub mod decoder;
+pub mod frame;
+pub mod glob;
+pub mod parse;
+pub mod pubsub;
+pub mod server;
+pub mod snapshot;
+pub mod tls;
+pub mod zset;
 pub use connection::Connection;
+
+/// Error returned by most functions of the myredis module
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+pub type Result<T> = std::result::Result<T, Error>;
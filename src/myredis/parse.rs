@@ -0,0 +1,177 @@
+//! Utility for parsing a command.
+//! 命令是一个由 bulk string 组成的数组帧，`Parse` 提供了一个游标，依次读出命令的各个参数。
+
+use std::{fmt, str, vec};
+
+use bytes::Bytes;
+
+use super::frame::Frame;
+
+pub struct Parse {
+    parts: vec::IntoIter<Frame>,
+}
+
+/// Error encountered while parsing a command, it is sent back to the client as an error frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The frame is not an array of strings
+    Protocol(String),
+    /// The command name is not known, holds the name and the first arguments
+    UnknownCommand(String, Vec<String>),
+    /// The number of arguments does not match the command, holds the command name
+    WrongArity(String),
+    /// An argument is not a valid integer
+    NotInteger,
+    /// An argument is not a valid float
+    NotFloat,
+    /// An option is not recognised or options conflict with each other
+    Syntax,
+    /// An argument has the right type but an invalid value, holds the full message
+    Invalid(String),
+}
+
+impl Parse {
+    /// Create a new `Parse` to parse the contents of `frame`, which must be an array frame
+    pub fn new(frame: Frame) -> Result<Parse, ParseError> {
+        match frame {
+            Frame::Array(array) => Ok(Parse {
+                parts: array.into_iter(),
+            }),
+            frame => Err(ParseError::Protocol(format!(
+                "expected array, got {}",
+                frame
+            ))),
+        }
+    }
+
+    /// The number of entries that have not been consumed yet
+    pub fn remaining(&self) -> usize {
+        self.parts.len()
+    }
+
+    /// Return the next entry as raw bytes
+    pub fn next_bytes(&mut self) -> Result<Bytes, ParseError> {
+        match self.parts.next() {
+            Some(Frame::Simple(s)) => Ok(Bytes::from(s.into_bytes())),
+            Some(Frame::Bulk(data)) => Ok(data),
+            Some(frame) => Err(ParseError::Protocol(format!(
+                "expected simple frame or bulk frame, got {}",
+                frame
+            ))),
+            // 命令的参数个数在解析前已经检查过了，这里只可能是选项缺少了值
+            None => Err(ParseError::Syntax),
+        }
+    }
+
+    /// Return the next entry as a UTF-8 string
+    pub fn next_string(&mut self) -> Result<String, ParseError> {
+        let data = self.next_bytes()?;
+        str::from_utf8(&data)
+            .map(|s| s.to_string())
+            .map_err(|_| ParseError::Protocol("invalid string".to_string()))
+    }
+
+    /// Return the next entry as an uppercase string, used for option flags which are case-insensitive
+    pub fn next_flag(&mut self) -> Result<String, ParseError> {
+        Ok(self.next_string()?.to_uppercase())
+    }
+
+    /// Return the next entry parsed as a 64 bit signed integer
+    pub fn next_int(&mut self) -> Result<i64, ParseError> {
+        let data = self.next_bytes()?;
+        parse_int(&data).ok_or(ParseError::NotInteger)
+    }
+
+    /// Return the next entry parsed as a float, `inf` and `-inf` are accepted
+    pub fn next_float(&mut self) -> Result<f64, ParseError> {
+        let data = self.next_bytes()?;
+        parse_float(&data).ok_or(ParseError::NotFloat)
+    }
+
+    /// Return all the remaining entries as strings
+    pub fn rest_strings(&mut self) -> Result<Vec<String>, ParseError> {
+        let mut rest = Vec::with_capacity(self.remaining());
+        while self.remaining() > 0 {
+            rest.push(self.next_string()?);
+        }
+        Ok(rest)
+    }
+
+    /// Return all the remaining entries as raw bytes
+    pub fn rest_bytes(&mut self) -> Result<Vec<Bytes>, ParseError> {
+        let mut rest = Vec::with_capacity(self.remaining());
+        while self.remaining() > 0 {
+            rest.push(self.next_bytes()?);
+        }
+        Ok(rest)
+    }
+
+    /// Ensure there are no more entries in the array
+    pub fn finish(&mut self) -> Result<(), ParseError> {
+        if self.parts.next().is_none() {
+            Ok(())
+        } else {
+            Err(ParseError::Syntax)
+        }
+    }
+}
+
+/// Parse an integer the way redis does: no leading `+`, no spaces, no leading zeros
+pub fn parse_int(data: &[u8]) -> Option<i64> {
+    let s = str::from_utf8(data).ok()?;
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    if digits.is_empty() || (digits.len() > 1 && digits.starts_with('0')) {
+        return None;
+    }
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse a float, rejecting NaN
+pub fn parse_float(data: &[u8]) -> Option<f64> {
+    let s = str::from_utf8(data).ok()?;
+    if s.is_empty() || s.starts_with(char::is_whitespace) || s.ends_with(char::is_whitespace) {
+        return None;
+    }
+    let value: f64 = match s.to_lowercase().as_str() {
+        "inf" | "+inf" | "infinity" | "+infinity" => f64::INFINITY,
+        "-inf" | "-infinity" => f64::NEG_INFINITY,
+        _ => s.parse().ok()?,
+    };
+    (!value.is_nan()).then_some(value)
+}
+
+/// Format a float the way redis replies with one, without exponent nor trailing zeros
+pub fn format_float(value: f64) -> String {
+    value.to_string()
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Protocol(msg) => write!(f, "ERR Protocol error: {}", msg),
+            ParseError::UnknownCommand(name, args) => {
+                write!(
+                    f,
+                    "ERR unknown command '{}', with args beginning with:",
+                    name
+                )?;
+                for arg in args {
+                    write!(f, " '{}'", arg)?;
+                }
+                Ok(())
+            }
+            ParseError::WrongArity(name) => {
+                write!(f, "ERR wrong number of arguments for '{}' command", name)
+            }
+            ParseError::NotInteger => "ERR value is not an integer or out of range".fmt(f),
+            ParseError::NotFloat => "ERR value is not a valid float".fmt(f),
+            ParseError::Syntax => "ERR syntax error".fmt(f),
+            ParseError::Invalid(msg) => msg.fmt(f),
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Publish/subscribe registry.
+//! 每个订阅者有一个有界的消息队列，注册表记录每个频道和每个模式的订阅者。
+//! 发布消息时直接把消息放进订阅者的队列，不会等待：队列满了说明订阅者处理得太慢，
+//! 和 redis 关闭输出缓冲区超限的客户端一样，这个订阅者会被移除并断开连接，不会拖慢发布者。
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, Notify};
+
+use super::{frame::Frame, glob};
+
+/// The number of messages a subscriber can have waiting before it is disconnected
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The registry of the subscribers of all channels and patterns, cloning it gives another handle
+/// to the same registry
+#[derive(Clone)]
+pub struct PubSub {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct State {
+    channels: HashMap<String, HashMap<u64, Mailbox>>,
+    patterns: HashMap<String, HashMap<u64, Mailbox>>,
+    next_id: u64,
+}
+
+/// The sending half of the queue of a subscriber
+#[derive(Clone)]
+struct Mailbox {
+    messages: mpsc::Sender<Message>,
+    overflow: Arc<Notify>,
+}
+
+/// A message delivered to a subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Published on a subscribed channel
+    Message { channel: String, payload: Bytes },
+    /// Published on a channel matching a subscribed pattern
+    Pmessage {
+        pattern: String,
+        channel: String,
+        payload: Bytes,
+    },
+}
+
+/// The channels and patterns of one subscriber, and the queue of the messages published on them.
+///
+/// Dropping it unsubscribes from everything.
+pub struct Subscription {
+    id: u64,
+    pubsub: PubSub,
+    channels: HashSet<String>,
+    patterns: HashSet<String>,
+    messages: mpsc::Receiver<Message>,
+    mailbox: Mailbox,
+}
+
+/// Error returned by `Subscription::recv` once the subscriber's queue overflowed
+#[derive(Debug)]
+pub struct Overflow(usize);
+
+impl PubSub {
+    pub fn new() -> PubSub {
+        PubSub::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Create a registry whose subscribers can have up to `capacity` messages waiting
+    pub fn with_capacity(capacity: usize) -> PubSub {
+        PubSub {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State::default()),
+                capacity,
+            }),
+        }
+    }
+
+    /// Create a subscriber, not subscribed to anything yet
+    pub fn subscriber(&self) -> Subscription {
+        let (sender, messages) = mpsc::channel(self.shared.capacity);
+        let mut state = self.shared.state.lock().unwrap();
+        state.next_id += 1;
+        Subscription {
+            id: state.next_id,
+            pubsub: self.clone(),
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            messages,
+            mailbox: Mailbox {
+                messages: sender,
+                overflow: Arc::new(Notify::new()),
+            },
+        }
+    }
+
+    /// Publish `payload` on `channel`, returning the number of subscriptions it was delivered to.
+    ///
+    /// A subscriber subscribed to the channel and to matching patterns receives one message for each.
+    pub fn publish(&self, channel: &str, payload: Bytes) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        let mut delivered = 0;
+
+        for mailbox in state
+            .channels
+            .get(channel)
+            .into_iter()
+            .flat_map(|s| s.values())
+        {
+            let message = Message::Message {
+                channel: channel.to_string(),
+                payload: payload.clone(),
+            };
+            delivered += mailbox.deliver(message) as usize;
+        }
+
+        // 和 redis 一样，每次发布都要和所有的模式匹配一遍
+        let patterns = state
+            .patterns
+            .iter()
+            .filter(|(pattern, _)| glob::matches(pattern.as_bytes(), channel.as_bytes()));
+        for (pattern, subscribers) in patterns {
+            for mailbox in subscribers.values() {
+                let message = Message::Pmessage {
+                    pattern: pattern.clone(),
+                    channel: channel.to_string(),
+                    payload: payload.clone(),
+                };
+                delivered += mailbox.deliver(message) as usize;
+            }
+        }
+        delivered
+    }
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        PubSub::new()
+    }
+}
+
+impl Mailbox {
+    /// Queue a message without waiting, returning `false` if the subscriber is gone or too slow
+    fn deliver(&self, message: Message) -> bool {
+        match self.messages.try_send(message) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                self.overflow.notify_one();
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+impl Subscription {
+    /// Subscribe to `channel`, returning the number of channels and patterns subscribed to
+    pub fn subscribe(&mut self, channel: String) -> usize {
+        if self.channels.insert(channel.clone()) {
+            let mut state = self.pubsub.shared.state.lock().unwrap();
+            let subscribers = state.channels.entry(channel).or_default();
+            subscribers.insert(self.id, self.mailbox.clone());
+        }
+        self.count()
+    }
+
+    /// Unsubscribe from `channel`, returning the number of channels and patterns still subscribed to
+    pub fn unsubscribe(&mut self, channel: &str) -> usize {
+        if self.channels.remove(channel) {
+            let mut state = self.pubsub.shared.state.lock().unwrap();
+            unregister(&mut state.channels, channel, self.id);
+        }
+        self.count()
+    }
+
+    /// Subscribe to the channels matching the glob-style `pattern`, returning the number of
+    /// channels and patterns subscribed to
+    pub fn psubscribe(&mut self, pattern: String) -> usize {
+        if self.patterns.insert(pattern.clone()) {
+            let mut state = self.pubsub.shared.state.lock().unwrap();
+            let subscribers = state.patterns.entry(pattern).or_default();
+            subscribers.insert(self.id, self.mailbox.clone());
+        }
+        self.count()
+    }
+
+    /// Unsubscribe from `pattern`, returning the number of channels and patterns still subscribed to
+    pub fn punsubscribe(&mut self, pattern: &str) -> usize {
+        if self.patterns.remove(pattern) {
+            let mut state = self.pubsub.shared.state.lock().unwrap();
+            unregister(&mut state.patterns, pattern, self.id);
+        }
+        self.count()
+    }
+
+    /// The subscribed channels
+    pub fn channels(&self) -> impl Iterator<Item = &String> {
+        self.channels.iter()
+    }
+
+    /// The subscribed patterns
+    pub fn patterns(&self) -> impl Iterator<Item = &String> {
+        self.patterns.iter()
+    }
+
+    /// The number of channels and patterns subscribed to
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    /// Wait for the next message. Fails once a message could not be queued because the queue was
+    /// full, the subscription should then be dropped.
+    pub async fn recv(&mut self) -> Result<Message, Overflow> {
+        let capacity = self.pubsub.shared.capacity;
+        tokio::select! {
+            // 先检查是否溢出，溢出之后队列里剩下的消息已经不完整了
+            biased;
+            _ = self.mailbox.overflow.notified() => Err(Overflow(capacity)),
+            message = self.messages.recv() => {
+                // `mailbox` 持有队列的一个发送端，队列不会被关闭
+                Ok(message.expect("the subscription holds a sender"))
+            }
+        }
+    }
+
+    /// Wait until a message could not be queued because the queue was full. A subscriber that
+    /// stopped reading is stuck sending the previous message, it waits for this at the same time.
+    pub async fn overflowed(&self) -> Overflow {
+        self.mailbox.overflow.notified().await;
+        Overflow(self.pubsub.shared.capacity)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut state = self.pubsub.shared.state.lock().unwrap();
+        for channel in &self.channels {
+            unregister(&mut state.channels, channel, self.id);
+        }
+        for pattern in &self.patterns {
+            unregister(&mut state.patterns, pattern, self.id);
+        }
+    }
+}
+
+/// Remove the subscriber `id` of a channel or pattern, which is dropped once it has no subscriber
+fn unregister(subscribers: &mut HashMap<String, HashMap<u64, Mailbox>>, name: &str, id: u64) {
+    if let Some(mailboxes) = subscribers.get_mut(name) {
+        mailboxes.remove(&id);
+        if mailboxes.is_empty() {
+            subscribers.remove(name);
+        }
+    }
+}
+
+impl Message {
+    /// The push frame sent to the subscriber
+    pub fn into_frame(self) -> Frame {
+        match self {
+            Message::Message { channel, payload } => Frame::Push(vec![
+                Frame::Bulk(Bytes::from_static(b"message")),
+                Frame::Bulk(Bytes::from(channel)),
+                Frame::Bulk(payload),
+            ]),
+            Message::Pmessage {
+                pattern,
+                channel,
+                payload,
+            } => Frame::Push(vec![
+                Frame::Bulk(Bytes::from_static(b"pmessage")),
+                Frame::Bulk(Bytes::from(pattern)),
+                Frame::Bulk(Bytes::from(channel)),
+                Frame::Bulk(payload),
+            ]),
+        }
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subscriber is too slow, its {} queued messages were not read",
+            self.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(channel: &str, payload: &'static str) -> Message {
+        Message::Message {
+            channel: channel.to_string(),
+            payload: Bytes::from(payload),
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_to_channel_subscribers() {
+        let pubsub = PubSub::new();
+        let mut first = pubsub.subscriber();
+        let mut second = pubsub.subscriber();
+        assert_eq!(first.subscribe("news".to_string()), 1);
+        assert_eq!(first.subscribe("news".to_string()), 1);
+        assert_eq!(first.subscribe("sport".to_string()), 2);
+        second.subscribe("news".to_string());
+
+        assert_eq!(pubsub.publish("news", Bytes::from("a")), 2);
+        assert_eq!(pubsub.publish("sport", Bytes::from("b")), 1);
+        assert_eq!(pubsub.publish("weather", Bytes::from("c")), 0);
+        assert_eq!(first.recv().await.unwrap(), message("news", "a"));
+        assert_eq!(first.recv().await.unwrap(), message("sport", "b"));
+        assert_eq!(second.recv().await.unwrap(), message("news", "a"));
+
+        assert_eq!(first.unsubscribe("news"), 1);
+        assert_eq!(first.unsubscribe("news"), 1);
+        assert_eq!(pubsub.publish("news", Bytes::from("d")), 1);
+        // 订阅者被丢弃时取消所有订阅
+        drop(second);
+        assert_eq!(pubsub.publish("news", Bytes::from("e")), 0);
+        assert!(!pubsub
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .channels
+            .contains_key("news"));
+    }
+
+    #[tokio::test]
+    async fn deliver_to_pattern_subscribers() {
+        let pubsub = PubSub::new();
+        let mut subscriber = pubsub.subscriber();
+        assert_eq!(subscriber.psubscribe("news.*".to_string()), 1);
+        assert_eq!(subscriber.psubscribe("*.it".to_string()), 2);
+        assert_eq!(subscriber.subscribe("news.it".to_string()), 3);
+
+        // 频道和两个模式都匹配，收到三条消息
+        assert_eq!(pubsub.publish("news.it", Bytes::from("x")), 3);
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(subscriber.recv().await.unwrap());
+        }
+        assert!(received.contains(&message("news.it", "x")));
+        for pattern in ["news.*", "*.it"] {
+            assert!(received.contains(&Message::Pmessage {
+                pattern: pattern.to_string(),
+                channel: "news.it".to_string(),
+                payload: Bytes::from("x"),
+            }));
+        }
+
+        assert_eq!(pubsub.publish("sport.fr", Bytes::from("y")), 0);
+        assert_eq!(subscriber.punsubscribe("news.*"), 2);
+        assert_eq!(pubsub.publish("news.fr", Bytes::from("z")), 0);
+        assert_eq!(subscriber.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_overflows() {
+        let pubsub = PubSub::with_capacity(2);
+        let mut slow = pubsub.subscriber();
+        let mut fast = pubsub.subscriber();
+        slow.subscribe("c".to_string());
+        fast.subscribe("c".to_string());
+
+        assert_eq!(pubsub.publish("c", Bytes::from("1")), 2);
+        fast.recv().await.unwrap();
+        assert_eq!(pubsub.publish("c", Bytes::from("2")), 2);
+        fast.recv().await.unwrap();
+        // 慢的订阅者的队列已经满了，发布不会等待它
+        assert_eq!(pubsub.publish("c", Bytes::from("3")), 1);
+        assert_eq!(fast.recv().await.unwrap(), message("c", "3"));
+
+        let overflow = slow.recv().await.unwrap_err();
+        assert_eq!(
+            overflow.to_string(),
+            "subscriber is too slow, its 2 queued messages were not read"
+        );
+    }
+}
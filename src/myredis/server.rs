@@ -0,0 +1,1377 @@
+//! Server.
+//! 接受连接并为每个连接启动一个任务，命令在共享的 `Db` 上执行，发布/订阅由连接自己处理。
+
+use std::{
+    fs::File,
+    future::Future,
+    io, mem,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Poll,
+};
+
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info};
+
+use super::{
+    aof::{self, Aof, Entry, Fsync},
+    cmd::Command,
+    db::{self, Db, Watch},
+    frame::{Frame, Limits, Protocol},
+    pubsub::{PubSub, Subscription},
+    snapshot::{self, Snapshotter},
+    tls::ServerTls,
+    Connection, Error, Result,
+};
+
+/// Server configuration
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The snapshot file, loaded on startup and written by SAVE and BGSAVE. Without it the
+    /// database is not persisted.
+    pub snapshot: Option<PathBuf>,
+    /// The append-only file, which logs every write. When it exists on startup the database is
+    /// restored from it instead of from the snapshot.
+    pub appendonly: Option<PathBuf>,
+    pub appendfsync: Fsync,
+    /// The limits on the requests, a client sending a request over them is disconnected
+    pub limits: Limits,
+    /// With a TLS configuration, the clients connect over TLS instead of plain TCP
+    pub tls: Option<ServerTls>,
+}
+
+/// Accept connections on `listener` and serve them from `db`, until accepting fails.
+///
+/// A background task deletes the expired keys of `db` while the server runs.
+pub async fn run(listener: &TcpListener, db: Db) -> Result<()> {
+    run_with(listener, db, Config::default()).await
+}
+
+/// Like `run`, with the given configuration. The database is first restored from the
+/// append-only file or the snapshot, the server does not start if they are corrupt.
+pub async fn run_with(listener: &TcpListener, db: Db, config: Config) -> Result<()> {
+    // 证书有问题时不启动
+    let tls = config.tls.as_ref().map(ServerTls::acceptor).transpose()?;
+    let aof = restore(&db, &config).await?;
+    let context = Context {
+        db: db.clone(),
+        pubsub: PubSub::new(),
+        snapshotter: config
+            .snapshot
+            .map(|path| Snapshotter::new(db.clone(), path)),
+        aof: aof.clone(),
+        limits: config.limits,
+        tls,
+    };
+
+    let purge = tokio::spawn(db::purge_expired_keys(db));
+    let sync = aof
+        .filter(|aof| aof.fsync() == Fsync::Everysec)
+        .map(|aof| tokio::spawn(aof.sync_every_second()));
+    let res = accept(listener, &context).await;
+    purge.abort();
+    if let Some(sync) = sync {
+        sync.abort();
+    }
+    res
+}
+
+/// Load the append-only file or the snapshot into `db`, and open the append-only file
+async fn restore(db: &Db, config: &Config) -> Result<Option<Aof>> {
+    // 和 redis 一样，追加日志比快照更新，存在时只从日志恢复
+    let replay = config.appendonly.as_ref().filter(|path| path.exists());
+    match (replay, &config.snapshot) {
+        (Some(path), _) => match aof::replay(db, path, &config.limits).await {
+            Ok(replayed) => info!(replayed, path = %path.display(), "append-only file replayed"),
+            Err(err) => {
+                let msg = format!("failed to replay {}: {}", path.display(), err);
+                return Err(msg.into());
+            }
+        },
+        (None, Some(path)) => match snapshot::load(db, path) {
+            Ok(loaded) => info!(loaded, path = %path.display(), "snapshot loaded"),
+            // 第一次启动时还没有快照
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => {
+                let msg = format!("failed to load snapshot {}: {}", path.display(), err);
+                return Err(msg.into());
+            }
+        },
+        (None, None) => {}
+    }
+
+    let Some(path) = &config.appendonly else {
+        return Ok(None);
+    };
+    if replay.is_none() {
+        // 新建的日志从快照的内容开始，否则下次启动时只重放日志，快照里的数据就丢了
+        let records: Vec<_> = db.dump().flatten().collect();
+        let mut file = File::create(path)?;
+        aof::write_records(&records, &mut file)?;
+        file.sync_all()?;
+    }
+    let aof = Aof::open(path.clone(), config.appendfsync)?;
+    // 之后交给阻塞的客户端的元素都要写进日志
+    db.record_handoffs();
+    Ok(Some(aof))
+}
+
+/// What the connections share
+#[derive(Clone)]
+struct Context {
+    db: Db,
+    pubsub: PubSub,
+    snapshotter: Option<Snapshotter>,
+    aof: Option<Aof>,
+    limits: Limits,
+    tls: Option<TlsAcceptor>,
+}
+
+async fn accept(listener: &TcpListener, context: &Context) -> Result<()> {
+    loop {
+        let (socket, addr) = listener.accept().await?;
+        debug!("socket addr: {:?}", addr);
+        // 回复已经攒成批再发送，不需要 Nagle 算法再等待
+        socket.set_nodelay(true)?;
+        let context = context.clone();
+        tokio::spawn(async move {
+            // 握手在连接自己的任务里进行，慢的客户端不会耽误接受其他连接
+            let res = match context.tls.clone() {
+                Some(tls) => match tls.accept(socket).await {
+                    Ok(stream) => handle_connection(stream, context).await,
+                    Err(err) => Err(format!("TLS handshake failed: {}", err).into()),
+                },
+                None => handle_connection(socket, context).await,
+            };
+            if let Err(err) = res {
+                error!(cause = ?err, "connection error");
+            }
+        });
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    context: Context,
+) -> Result<()> {
+    let Context { db, pubsub, .. } = &context;
+    // `Connection` 对于 redis 的读写进行了抽象封装，因此我们读到的是一个一个数据帧frame(数据帧 = redis命令 + 数据)，而不是字节流
+    // `Connection` 是在 myredis 模块中定义
+    let mut connection = Connection::new(stream);
+    connection.set_limits(context.limits);
+    let mut client = Client {
+        id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+        transaction: None,
+        watch: Watch::new(db.clone()),
+        subscription: None,
+    };
+    // 阻塞命令执行期间读到的下一条命令
+    let mut pending = None;
+
+    loop {
+        let frame = match pending.take() {
+            Some(frame) => frame,
+            None => match next_frame(&mut connection, &mut client).await? {
+                Some(frame) => frame,
+                None => break,
+            },
+        };
+        debug!(?frame, "got frame");
+        // RESP2 的连接订阅之后只能执行订阅相关的命令，RESP3 的连接可以在订阅的同时执行其他命令
+        if client.subscription.is_some() && connection.protocol() == Protocol::Resp2 {
+            if let Some(err) = subscriber_mode_error(&frame) {
+                connection.write_frame_buffered(&Frame::Error(err)).await?;
+                continue;
+            }
+        }
+        // 写命令原样记录到追加日志里
+        let request = context.aof.as_ref().map(|_| frame.clone());
+
+        // 命令出错时只返回错误帧，连接继续保持
+        match dispatch(frame, request.clone(), &context, &mut client).await {
+            Ok(Reply::Frame(response)) => connection.write_frame_buffered(&response).await?,
+            Ok(Reply::Exec(transaction)) => {
+                let response = exec(&context, &mut client, transaction).await?;
+                connection.write_frame_buffered(&response).await?
+            }
+            Ok(Reply::Execute(cmd)) if cmd.is_blocking() => {
+                // 先把流水线里前面命令的回复发出去，客户端可能在等它们
+                connection.flush().await?;
+                // 列表不为空时第一次执行就会弹出元素，要和其他写命令一样持有日志锁
+                let mut response = Box::pin(cmd.apply(db));
+                let first = match &context.aof {
+                    Some(aof) => {
+                        let mut log = aof.lock().await;
+                        let first = futures::poll!(&mut response);
+                        log.append_handoffs(db)?;
+                        drop(log);
+                        sync_always(aof).await?;
+                        first
+                    }
+                    None => futures::poll!(&mut response),
+                };
+                // 阻塞期间继续读取连接，这样客户端断开连接时可以放弃等待，否则交给它的元素会丢失。
+                // 读到的下一条命令留到阻塞的命令完成之后再执行。
+                // 等待期间不持有日志锁，交给这个客户端的元素由推入它的命令记录
+                let response = match first {
+                    Poll::Ready(response) => response,
+                    Poll::Pending => loop {
+                        tokio::select! {
+                            response = &mut response => break response,
+                            frame = read_frame(&mut connection), if pending.is_none() => match frame {
+                                Ok(Some(frame)) => pending = Some(frame),
+                                Ok(None) => return abandon(&context, response).await,
+                                Err(err) => {
+                                    abandon(&context, response).await?;
+                                    return Err(err);
+                                }
+                            },
+                        }
+                    },
+                };
+                connection.write_frame_buffered(&response).await?;
+            }
+            // RESP2 的连接订阅之后，PING 的回复和推送的消息一样是一个数组
+            Ok(Reply::Execute(Command::Ping { message }))
+                if client.subscription.is_some() && connection.protocol() == Protocol::Resp2 =>
+            {
+                let response =
+                    Frame::bulks([Bytes::from_static(b"pong"), message.unwrap_or_default()]);
+                connection.write_frame_buffered(&response).await?
+            }
+            Ok(Reply::Execute(cmd)) => {
+                let response = execute(&context, cmd, request).await?;
+                connection.write_frame_buffered(&response).await?
+            }
+            Ok(Reply::Hello(protocol)) => {
+                if let Some(protocol) = protocol {
+                    connection.set_protocol(protocol);
+                }
+                let response = hello_frame(&client, connection.protocol());
+                connection.write_frame_buffered(&response).await?
+            }
+            Ok(Reply::Subscribe(cmd)) => {
+                subscribe(&mut connection, &mut client, pubsub, cmd).await?
+            }
+            Err(err) => connection.write_frame_buffered(&Frame::Error(err)).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a command that does not block, a write is logged to the append-only file
+async fn execute(context: &Context, cmd: Command, request: Option<Frame>) -> Result<Frame> {
+    let (Some(aof), Some(request), true) = (&context.aof, request, cmd.is_write()) else {
+        return Ok(cmd.apply(&context.db).await);
+    };
+
+    let entry = Entry::new(&cmd, request);
+    let mut log = aof.lock().await;
+    let response = cmd.apply(&context.db).await;
+    log.append(&entry.frames(&response))?;
+    // 推入的元素可能交给了阻塞的客户端，紧接着命令记录下来
+    log.append_handoffs(&context.db)?;
+    drop(log);
+    sync_always(aof).await?;
+    Ok(response)
+}
+
+/// Stop waiting for a blocking command whose client is gone. An element already handed to it is
+/// put back on its list, which is logged like the commands.
+async fn abandon<F: Future>(context: &Context, response: F) -> Result<()> {
+    let Some(aof) = &context.aof else {
+        return Ok(());
+    };
+    let mut log = aof.lock().await;
+    drop(response);
+    log.append_handoffs(&context.db)?;
+    drop(log);
+    sync_always(aof).await
+}
+
+/// With the `always` policy, flush the append-only file to disk before replying
+async fn sync_always(aof: &Aof) -> Result<()> {
+    if aof.fsync() == Fsync::Always {
+        aof.sync().await?;
+    }
+    Ok(())
+}
+
+/// Read the next command of the main loop. The commands a client pipelines arrive together, they
+/// are all executed before the replies are sent with a single flush.
+///
+/// While the client is subscribed, the messages published on its channels are pushed to it as
+/// they arrive.
+async fn next_frame<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    client: &mut Client,
+) -> Result<Option<Frame>> {
+    match connection.read_buffered_frame() {
+        Ok(Some(frame)) => return Ok(Some(frame)),
+        Ok(None) => {}
+        Err(err) => return Err(protocol_error(connection, err).await),
+    }
+    // 缓冲区里没有完整的命令了，等待之前把回复发出去
+    connection.flush().await?;
+
+    let Some(subscription) = &mut client.subscription else {
+        return read_frame(connection).await;
+    };
+    loop {
+        tokio::select! {
+            // 订阅者处理得太慢，消息队列满了时断开连接
+            message = subscription.recv() => {
+                let frame = message?.into_frame();
+                // 客户端不再读取时写入会一直等待，这期间队列满了也要断开连接
+                tokio::select! {
+                    res = connection.write_frame(&frame) => res?,
+                    overflow = subscription.overflowed() => return Err(overflow.into()),
+                }
+            }
+            frame = read_frame(connection) => return frame,
+        }
+    }
+}
+
+/// Read a frame, a malformed frame is answered with a protocol error before the connection is closed
+async fn read_frame<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+) -> Result<Option<Frame>> {
+    match connection.read_frame().await {
+        Ok(frame) => Ok(frame),
+        Err(err) => Err(protocol_error(connection, err).await),
+    }
+}
+
+/// Reply to a malformed frame with a protocol error, the error is returned to close the connection
+async fn protocol_error<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    err: Error,
+) -> Error {
+    // 字节流已经无法继续解析，只能关闭连接
+    let response = Frame::Error(format!("ERR Protocol error: {}", err));
+    connection.write_frame(&response).await.err().unwrap_or(err)
+}
+
+/// The id of the next connection, HELLO replies with it
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The state of a connection
+struct Client {
+    id: u64,
+    /// The transaction started by MULTI
+    transaction: Option<Transaction>,
+    /// The keys watched for the next transaction
+    watch: Watch,
+    /// The channels and patterns the client subscribed to, `None` when there are none
+    subscription: Option<Subscription>,
+}
+
+/// The commands queued since MULTI, each with its request to log to the append-only file
+#[derive(Default)]
+struct Transaction {
+    commands: Vec<(Command, Option<Frame>)>,
+    /// A command could not be queued, EXEC discards the transaction
+    failed: bool,
+}
+
+/// The outcome of a command
+enum Reply {
+    Frame(Frame),
+    /// EXEC runs the transaction
+    Exec(Transaction),
+    /// A command that runs on the database
+    Execute(Command),
+    /// HELLO switches the protocol of the connection, `None` keeps it
+    Hello(Option<Protocol>),
+    /// (P)SUBSCRIBE and (P)UNSUBSCRIBE change the subscriptions of the connection
+    Subscribe(Command),
+}
+
+/// Execute the command in `frame`, the error is the message of the error frame sent to the client
+async fn dispatch(
+    frame: Frame,
+    request: Option<Frame>,
+    context: &Context,
+    client: &mut Client,
+) -> std::result::Result<Reply, String> {
+    let cmd = Command::from_frame(frame).map_err(|err| {
+        // 命令有错误时 EXEC 会放弃整个事务
+        if let Some(transaction) = &mut client.transaction {
+            transaction.failed = true;
+        }
+        err.to_string()
+    })?;
+    debug!(?cmd, "dispatch");
+
+    if let Some(transaction) = &mut client.transaction {
+        match cmd {
+            Command::Multi | Command::Exec | Command::Discard | Command::Watch { .. } => {}
+            Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Psubscribe { .. }
+            | Command::Punsubscribe { .. }
+            | Command::Hello { .. }
+            | Command::Save
+            | Command::Bgsave
+            | Command::Bgrewriteaof => {
+                transaction.failed = true;
+                return Err("ERR Command not allowed inside a transaction".to_string());
+            }
+            cmd => {
+                transaction.commands.push((cmd, request));
+                return Ok(Reply::Frame(Frame::Simple("QUEUED".to_string())));
+            }
+        }
+    }
+
+    let response = match cmd {
+        Command::Multi => match client.transaction {
+            Some(_) => return Err("ERR MULTI calls can not be nested".to_string()),
+            None => {
+                client.transaction = Some(Transaction::default());
+                Frame::ok()
+            }
+        },
+        Command::Exec => match client.transaction.take() {
+            Some(transaction) => return Ok(Reply::Exec(transaction)),
+            None => return Err("ERR EXEC without MULTI".to_string()),
+        },
+        Command::Discard => match client.transaction.take() {
+            Some(_) => {
+                client.watch.clear();
+                Frame::ok()
+            }
+            None => return Err("ERR DISCARD without MULTI".to_string()),
+        },
+        Command::Watch { keys } => {
+            if client.transaction.is_some() {
+                return Err("ERR WATCH inside MULTI is not allowed".to_string());
+            }
+            for key in keys {
+                client.watch.add(key);
+            }
+            Frame::ok()
+        }
+        Command::Unwatch => {
+            client.watch.clear();
+            Frame::ok()
+        }
+        Command::Publish { channel, message } => {
+            Frame::Integer(context.pubsub.publish(&channel, message) as i64)
+        }
+        Command::Hello { protocol } => return Ok(Reply::Hello(protocol)),
+        cmd @ (Command::Subscribe { .. }
+        | Command::Psubscribe { .. }
+        | Command::Unsubscribe { .. }
+        | Command::Punsubscribe { .. }) => return Ok(Reply::Subscribe(cmd)),
+        Command::Save => {
+            let saved = snapshotter(context)?.save().await;
+            saved.map_err(|err| err.to_string())?;
+            Frame::ok()
+        }
+        Command::Bgrewriteaof => {
+            let aof = context.aof.as_ref();
+            let aof = aof.ok_or_else(|| "ERR append only file is not enabled".to_string())?;
+            aof.rewrite(&context.db)
+                .await
+                .map_err(|err| err.to_string())?;
+            Frame::Simple("Background append only file rewriting started".to_string())
+        }
+        Command::Bgsave => {
+            snapshotter(context)?
+                .bgsave()
+                .map_err(|err| err.to_string())?;
+            Frame::Simple("Background saving started".to_string())
+        }
+        cmd => return Ok(Reply::Execute(cmd)),
+    };
+
+    Ok(Reply::Frame(response))
+}
+
+/// Run the commands of a transaction with the shards of all their keys locked, so that no other
+/// command runs in between. Replies nil without running them if a watched key changed.
+async fn exec(context: &Context, client: &mut Client, transaction: Transaction) -> Result<Frame> {
+    // EXEC 之后不再监视任何键，`watch` 在函数返回时释放
+    let watch = mem::replace(&mut client.watch, Watch::new(context.db.clone()));
+    if transaction.failed {
+        let err = "EXECABORT Transaction discarded because of previous errors.";
+        return Ok(Frame::Error(err.to_string()));
+    }
+
+    // 有命令要访问所有的键时锁住所有分片
+    let mut keys: Option<Vec<String>> = Some(watch.keys().map(String::from).collect());
+    for (cmd, _) in &transaction.commands {
+        match (cmd.keys(), &mut keys) {
+            (Some(cmd_keys), Some(keys)) => keys.extend(cmd_keys.into_iter().map(String::from)),
+            _ => keys = None,
+        }
+    }
+
+    // 先拿到日志锁，事务的命令在日志里也是连续的
+    let log = match &context.aof {
+        Some(aof) => Some((aof, aof.lock().await)),
+        None => None,
+    };
+    let mut logged = Vec::new();
+    let responses = context.db.with_locked(keys.as_deref(), |locked| {
+        if !watch.unchanged(locked) {
+            return None;
+        }
+        let responses = transaction.commands.into_iter().map(|(cmd, request)| {
+            // 和 `execute` 一样只记录写命令，重放时不接受其他命令
+            let entry = request
+                .filter(|_| cmd.is_write())
+                .map(|request| Entry::new(&cmd, request));
+            let response = match cmd {
+                Command::Publish { channel, message } => {
+                    Frame::Integer(context.pubsub.publish(&channel, message) as i64)
+                }
+                Command::Unwatch => Frame::ok(),
+                cmd => cmd.apply_locked(locked),
+            };
+            if let Some(entry) = entry {
+                logged.extend(entry.frames(&response));
+                logged.extend(aof::handoffs(&context.db));
+            }
+            response
+        });
+        Some(responses.collect())
+    });
+    // 事务结束之后才移动的 BLMOVE 的元素也写在事务里
+    logged.extend(aof::handoffs(&context.db));
+    let Some(responses) = responses else {
+        return Ok(Frame::NullArray);
+    };
+
+    if let Some((aof, mut log)) = log.filter(|_| !logged.is_empty()) {
+        // 和 redis 一样用 MULTI 和 EXEC 包起来，重放时不会只执行事务的一部分
+        logged.insert(0, Frame::bulks([Bytes::from_static(b"MULTI")]));
+        logged.push(Frame::bulks([Bytes::from_static(b"EXEC")]));
+        log.append(&logged)?;
+        drop(log);
+        sync_always(aof).await?;
+    }
+    Ok(Frame::Array(responses))
+}
+
+fn snapshotter(context: &Context) -> std::result::Result<&Snapshotter, String> {
+    let snapshotter = context.snapshotter.as_ref();
+    snapshotter.ok_or_else(|| "ERR snapshots are not enabled".to_string())
+}
+
+/// The lowercase name of the command in `frame`, if it has one
+fn command_name(frame: &Frame) -> Option<String> {
+    match frame {
+        Frame::Array(entries) => match entries.first() {
+            Some(Frame::Bulk(name)) => Some(String::from_utf8_lossy(name).to_lowercase()),
+            Some(Frame::Simple(name)) => Some(name.to_lowercase()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The error replied to a command that a RESP2 connection cannot run while it is subscribed
+fn subscriber_mode_error(frame: &Frame) -> Option<String> {
+    let name = command_name(frame).unwrap_or_default();
+    if matches!(
+        &name[..],
+        "subscribe" | "unsubscribe" | "psubscribe" | "punsubscribe" | "ping"
+    ) {
+        return None;
+    }
+    Some(format!(
+        "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context",
+        name
+    ))
+}
+
+/// Run a (P)SUBSCRIBE or (P)UNSUBSCRIBE command. The connection then receives the messages
+/// published on its channels and patterns until it has unsubscribed from all of them, see
+/// `next_frame`.
+async fn subscribe<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    client: &mut Client,
+    pubsub: &PubSub,
+    cmd: Command,
+) -> Result<()> {
+    let subscription = client
+        .subscription
+        .get_or_insert_with(|| pubsub.subscriber());
+    match cmd {
+        Command::Subscribe { channels } => {
+            each(
+                connection,
+                subscription,
+                "subscribe",
+                channels,
+                Subscription::subscribe,
+            )
+            .await?
+        }
+        Command::Psubscribe { patterns } => {
+            each(
+                connection,
+                subscription,
+                "psubscribe",
+                patterns,
+                Subscription::psubscribe,
+            )
+            .await?
+        }
+        Command::Unsubscribe { channels } => {
+            // 没有指定频道时取消所有频道的订阅
+            let channels = match channels.is_empty() {
+                true => subscription.channels().cloned().collect(),
+                false => channels,
+            };
+            each(
+                connection,
+                subscription,
+                "unsubscribe",
+                channels,
+                |subscription, channel| subscription.unsubscribe(&channel),
+            )
+            .await?
+        }
+        Command::Punsubscribe { patterns } => {
+            let patterns = match patterns.is_empty() {
+                true => subscription.patterns().cloned().collect(),
+                false => patterns,
+            };
+            each(
+                connection,
+                subscription,
+                "punsubscribe",
+                patterns,
+                |subscription, pattern| subscription.punsubscribe(&pattern),
+            )
+            .await?
+        }
+        _ => unreachable!(),
+    }
+
+    // 取消了所有订阅，退出订阅模式
+    if client
+        .subscription
+        .as_ref()
+        .is_some_and(|subscription| subscription.count() == 0)
+    {
+        client.subscription = None;
+    }
+    Ok(())
+}
+
+/// Apply `f` to each channel or pattern of `names`, replying to each with the number of
+/// subscriptions after it
+async fn each<S: AsyncRead + AsyncWrite + Unpin>(
+    connection: &mut Connection<S>,
+    subscription: &mut Subscription,
+    kind: &'static str,
+    names: Vec<String>,
+    f: fn(&mut Subscription, String) -> usize,
+) -> Result<()> {
+    // 和 redis 一样，没有可以取消的订阅时也回复一条消息
+    if names.is_empty() {
+        let frame = subscription_frame(kind, None, subscription.count());
+        return connection.write_frame_buffered(&frame).await;
+    }
+    for name in names {
+        let count = f(subscription, name.clone());
+        let frame = subscription_frame(kind, Some(name), count);
+        connection.write_frame_buffered(&frame).await?;
+    }
+    Ok(())
+}
+
+/// The push frame confirming a (P)SUBSCRIBE or (P)UNSUBSCRIBE, a RESP2 connection receives it as
+/// an array
+fn subscription_frame(kind: &'static str, channel: Option<String>, count: usize) -> Frame {
+    Frame::Push(vec![
+        Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+        channel.map_or(Frame::Null, |channel| Frame::Bulk(Bytes::from(channel))),
+        Frame::Integer(count as i64),
+    ])
+}
+
+/// The reply to HELLO, a map of the server properties
+fn hello_frame(client: &Client, protocol: Protocol) -> Frame {
+    let proto = match protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    let bulk = |val: &'static str| Frame::Bulk(Bytes::from_static(val.as_bytes()));
+    Frame::Map(vec![
+        (bulk("server"), bulk("myredis")),
+        (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+        (bulk("proto"), Frame::Integer(proto)),
+        (bulk("id"), Frame::Integer(client.id as i64)),
+        (bulk("mode"), bulk("standalone")),
+        (bulk("role"), bulk("master")),
+        (bulk("modules"), Frame::Array(Vec::new())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::TcpStream;
+
+    use super::*;
+    use crate::{myredis::db::DEFAULT_SHARDS, timer_future::Clock};
+
+    /// Start a server with `config` on a free port
+    async fn start(config: Config) -> SocketAddr {
+        start_with(Db::new(), config).await
+    }
+
+    /// Start a server serving `db`
+    async fn start_with(db: Db, config: Config) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { run_with(&listener, db, config).await });
+        addr
+    }
+
+    /// Let the server run until `key` has `count` blocked clients
+    async fn wait_blocked(db: &Db, key: &str, count: usize) {
+        while db.blocked_clients(key) < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn connect(addr: SocketAddr) -> Connection {
+        Connection::new(TcpStream::connect(addr).await.unwrap())
+    }
+
+    /// Send a command without waiting for its reply
+    async fn send(connection: &mut Connection, args: &[&str]) {
+        let args = args
+            .iter()
+            .map(|arg| Bytes::copy_from_slice(arg.as_bytes()));
+        connection.write_frame(&Frame::bulks(args)).await.unwrap();
+    }
+
+    /// Read the next frame, which must arrive
+    async fn read(connection: &mut Connection) -> Frame {
+        let frame = connection.read_frame().await.unwrap();
+        frame.expect("the server closed the connection")
+    }
+
+    /// Send a command and wait for its reply
+    async fn request(connection: &mut Connection, args: &[&str]) -> Frame {
+        send(connection, args).await;
+        read(connection).await
+    }
+
+    fn array(elements: &[&str]) -> Frame {
+        Frame::bulks(
+            elements
+                .iter()
+                .map(|element| Bytes::copy_from_slice(element.as_bytes())),
+        )
+    }
+
+    #[tokio::test]
+    async fn publish_to_resp2_subscribers() {
+        let addr = start(Config::default()).await;
+        let mut subscriber = connect(addr).await;
+        let mut publisher = connect(addr).await;
+
+        let subscribed = request(&mut subscriber, &["SUBSCRIBE", "news"]).await;
+        assert_eq!(
+            subscribed,
+            Frame::Array(vec![
+                Frame::Bulk("subscribe".into()),
+                Frame::Bulk("news".into()),
+                Frame::Integer(1)
+            ])
+        );
+        // RESP2 的订阅者的 PING 回复一个数组
+        assert_eq!(
+            request(&mut subscriber, &["PING"]).await,
+            array(&["pong", ""])
+        );
+        assert_eq!(
+            request(&mut subscriber, &["PING", "hi"]).await,
+            array(&["pong", "hi"])
+        );
+        assert_eq!(
+            request(&mut subscriber, &["GET", "k"]).await,
+            Frame::Error(
+                "ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING are allowed in this context"
+                    .to_string()
+            )
+        );
+
+        assert_eq!(
+            request(&mut publisher, &["PUBLISH", "news", "hello"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            read(&mut subscriber).await,
+            array(&["message", "news", "hello"])
+        );
+
+        request(&mut subscriber, &["PSUBSCRIBE", "news.*"]).await;
+        assert_eq!(
+            request(&mut publisher, &["PUBLISH", "news.it", "ciao"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            read(&mut subscriber).await,
+            array(&["pmessage", "news.*", "news.it", "ciao"])
+        );
+
+        // 取消所有订阅之后回到普通模式
+        request(&mut subscriber, &["UNSUBSCRIBE"]).await;
+        assert_eq!(
+            request(&mut subscriber, &["PUNSUBSCRIBE"]).await,
+            Frame::Array(vec![
+                Frame::Bulk("punsubscribe".into()),
+                Frame::Bulk("news.*".into()),
+                Frame::Integer(0)
+            ])
+        );
+        assert_eq!(
+            request(&mut subscriber, &["PING"]).await,
+            Frame::Simple("PONG".to_string())
+        );
+        assert_eq!(
+            request(&mut publisher, &["PUBLISH", "news", "bye"]).await,
+            Frame::Integer(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn resp3_subscribers_run_any_command() {
+        let addr = start(Config::default()).await;
+        let mut subscriber = connect(addr).await;
+        request(&mut subscriber, &["HELLO", "3"]).await;
+
+        assert_eq!(
+            request(&mut subscriber, &["SUBSCRIBE", "news"]).await,
+            Frame::Push(vec![
+                Frame::Bulk("subscribe".into()),
+                Frame::Bulk("news".into()),
+                Frame::Integer(1)
+            ])
+        );
+        assert_eq!(
+            request(&mut subscriber, &["PING"]).await,
+            Frame::Simple("PONG".to_string())
+        );
+        assert_eq!(request(&mut subscriber, &["GET", "k"]).await, Frame::Null);
+        assert_eq!(
+            request(&mut subscriber, &["PUBLISH", "news", "self"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            read(&mut subscriber).await,
+            Frame::Push(vec![
+                Frame::Bulk("message".into()),
+                Frame::Bulk("news".into()),
+                Frame::Bulk("self".into())
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_is_disconnected() {
+        let pubsub = PubSub::with_capacity(2);
+        // 客户端从不读取，第一条消息就写不完
+        let (stream, _peer) = tokio::io::duplex(64);
+        let mut connection = Connection::new(stream);
+        let mut client = Client {
+            id: 1,
+            transaction: None,
+            watch: Watch::new(Db::new()),
+            subscription: Some(pubsub.subscriber()),
+        };
+        client
+            .subscription
+            .as_mut()
+            .unwrap()
+            .subscribe("news".to_string());
+
+        let payload = Bytes::from("x".repeat(1024));
+        assert_eq!(pubsub.publish("news", payload.clone()), 1);
+        let next = next_frame(&mut connection, &mut client);
+        tokio::pin!(next);
+        assert!(futures::poll!(&mut next).is_pending());
+
+        // 写入等待期间队列满了，连接被断开，不会一直等待客户端读取
+        assert_eq!(pubsub.publish("news", payload.clone()), 1);
+        assert_eq!(pubsub.publish("news", payload.clone()), 1);
+        assert_eq!(pubsub.publish("news", payload), 0);
+        let res = tokio::time::timeout(Duration::from_secs(1), next).await;
+        let err = res
+            .expect("the subscriber was not disconnected")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "subscriber is too slow, its 2 queued messages were not read"
+        );
+    }
+
+    /// The commands of `requests` as they are written in the append-only file
+    fn logged(requests: &[&[&str]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for args in requests {
+            array(args).encode(Protocol::Resp2, &mut buf);
+        }
+        buf
+    }
+
+    /// Run a command directly on `db`, as the server would
+    async fn apply(db: &Db, args: &[&str]) -> Frame {
+        let args = args.iter().map(|arg| Bytes::from(arg.to_string()));
+        let cmd = Command::from_frame(Frame::bulks(args)).unwrap();
+        cmd.apply(db).await
+    }
+
+    #[tokio::test]
+    async fn served_blocked_clients_are_logged_with_the_push() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let db = Db::new();
+        let config = Config {
+            appendonly: Some(path.clone()),
+            appendfsync: Fsync::Always,
+            ..Config::default()
+        };
+        let addr = start_with(db.clone(), config).await;
+        let mut popper = connect(addr).await;
+        let mut mover = connect(addr).await;
+        let mut pusher = connect(addr).await;
+
+        send(&mut popper, &["BLPOP", "l", "0"]).await;
+        send(&mut mover, &["BLMOVE", "src", "dst", "LEFT", "RIGHT", "0"]).await;
+        wait_blocked(&db, "l", 1).await;
+        wait_blocked(&db, "src", 1).await;
+
+        // 弹出紧跟在推入之后写入，后面的命令不会插到它们中间
+        request(&mut pusher, &["RPUSH", "l", "a"]).await;
+        request(&mut pusher, &["LPUSH", "l", "b"]).await;
+        request(&mut pusher, &["RPUSH", "src", "x"]).await;
+        assert_eq!(read(&mut popper).await, array(&["l", "a"]));
+        assert_eq!(read(&mut mover).await, Frame::Bulk("x".into()));
+        // 列表不为空时立即弹出
+        request(&mut popper, &["BRPOP", "l", "0"]).await;
+
+        let expected = logged(&[
+            &["RPUSH", "l", "a"],
+            &["LPOP", "l"],
+            &["LPUSH", "l", "b"],
+            &["RPUSH", "src", "x"],
+            &["LPOP", "src"],
+            &["RPUSH", "dst", "x"],
+            &["RPOP", "l"],
+        ]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        let db = Db::new();
+        let replayed = aof::replay(&db, &path, &Limits::default()).await.unwrap();
+        assert_eq!(replayed, 7);
+        for key in ["l", "src", "dst"] {
+            let lrange = ["LRANGE", key, "0", "-1"];
+            let live = request(&mut pusher, &lrange).await;
+            assert_eq!(apply(&db, &lrange).await, live);
+        }
+    }
+
+    fn simple(value: &str) -> Frame {
+        Frame::Simple(value.to_string())
+    }
+
+    fn error(msg: &str) -> Frame {
+        Frame::Error(msg.to_string())
+    }
+
+    #[tokio::test]
+    async fn transaction_runs_queued_commands() {
+        let addr = start(Config::default()).await;
+        let mut client = connect(addr).await;
+
+        assert_eq!(request(&mut client, &["MULTI"]).await, simple("OK"));
+        for args in [&["SET", "a", "1"][..], &["INCR", "a"], &["LPUSH", "a", "x"]] {
+            assert_eq!(request(&mut client, args).await, simple("QUEUED"));
+        }
+        // 执行时出错的命令不影响其他命令
+        assert_eq!(
+            request(&mut client, &["EXEC"]).await,
+            Frame::Array(vec![
+                simple("OK"),
+                Frame::Integer(2),
+                error("WRONGTYPE Operation against a key holding the wrong kind of value"),
+            ])
+        );
+        assert_eq!(
+            request(&mut client, &["GET", "a"]).await,
+            Frame::Bulk("2".into())
+        );
+
+        assert_eq!(request(&mut client, &["MULTI"]).await, simple("OK"));
+        request(&mut client, &["SET", "a", "3"]).await;
+        assert_eq!(request(&mut client, &["DISCARD"]).await, simple("OK"));
+        assert_eq!(
+            request(&mut client, &["GET", "a"]).await,
+            Frame::Bulk("2".into())
+        );
+        assert_eq!(request(&mut client, &["MULTI"]).await, simple("OK"));
+        assert_eq!(request(&mut client, &["EXEC"]).await, Frame::Array(vec![]));
+    }
+
+    #[tokio::test]
+    async fn queue_time_errors_abort_the_transaction() {
+        let addr = start(Config::default()).await;
+        let mut client = connect(addr).await;
+        let aborted = error("EXECABORT Transaction discarded because of previous errors.");
+
+        for bad in [
+            &["SET", "a"][..],
+            &["NOSUCHCOMMAND", "a"],
+            &["SUBSCRIBE", "news"],
+        ] {
+            request(&mut client, &["MULTI"]).await;
+            assert_eq!(
+                request(&mut client, &["SET", "a", "1"]).await,
+                simple("QUEUED")
+            );
+            assert!(matches!(request(&mut client, bad).await, Frame::Error(_)));
+            assert_eq!(
+                request(&mut client, &["SET", "b", "1"]).await,
+                simple("QUEUED")
+            );
+            assert_eq!(request(&mut client, &["EXEC"]).await, aborted);
+            assert_eq!(
+                request(&mut client, &["EXISTS", "a", "b"]).await,
+                Frame::Integer(0)
+            );
+        }
+
+        assert_eq!(
+            request(&mut client, &["EXEC"]).await,
+            error("ERR EXEC without MULTI")
+        );
+        assert_eq!(
+            request(&mut client, &["DISCARD"]).await,
+            error("ERR DISCARD without MULTI")
+        );
+        request(&mut client, &["MULTI"]).await;
+        assert_eq!(
+            request(&mut client, &["MULTI"]).await,
+            error("ERR MULTI calls can not be nested")
+        );
+        assert_eq!(
+            request(&mut client, &["WATCH", "a"]).await,
+            error("ERR WATCH inside MULTI is not allowed")
+        );
+        // 这两个错误不会放弃事务
+        request(&mut client, &["SET", "a", "1"]).await;
+        assert_eq!(
+            request(&mut client, &["EXEC"]).await,
+            Frame::Array(vec![simple("OK")])
+        );
+    }
+
+    #[tokio::test]
+    async fn changed_watched_keys_abort_exec() {
+        let addr = start(Config::default()).await;
+        let mut client = connect(addr).await;
+        let mut other = connect(addr).await;
+
+        // 监视的键被其他客户端修改了
+        assert_eq!(request(&mut client, &["WATCH", "k"]).await, simple("OK"));
+        request(&mut other, &["SET", "k", "theirs"]).await;
+        request(&mut client, &["MULTI"]).await;
+        request(&mut client, &["SET", "k", "mine"]).await;
+        assert_eq!(request(&mut client, &["EXEC"]).await, Frame::NullArray);
+        assert_eq!(
+            request(&mut client, &["GET", "k"]).await,
+            Frame::Bulk("theirs".into())
+        );
+
+        // EXEC 之后不再监视
+        request(&mut other, &["SET", "k", "again"]).await;
+        request(&mut client, &["MULTI"]).await;
+        request(&mut client, &["SET", "k", "mine"]).await;
+        assert_eq!(
+            request(&mut client, &["EXEC"]).await,
+            Frame::Array(vec![simple("OK")])
+        );
+
+        // 没有改变的键不影响 EXEC，事务自己修改监视的键也不影响
+        request(&mut client, &["WATCH", "k", "other"]).await;
+        request(&mut client, &["MULTI"]).await;
+        request(&mut client, &["SET", "k", "mine"]).await;
+        request(&mut client, &["DEL", "other"]).await;
+        assert_eq!(
+            request(&mut client, &["EXEC"]).await,
+            Frame::Array(vec![simple("OK"), Frame::Integer(0)])
+        );
+
+        // UNWATCH 和 DISCARD 都会停止监视
+        for stop in [&["UNWATCH"][..], &["DISCARD"]] {
+            request(&mut client, &["WATCH", "k"]).await;
+            if stop == ["DISCARD"] {
+                request(&mut client, &["MULTI"]).await;
+            }
+            assert_eq!(request(&mut client, stop).await, simple("OK"));
+            request(&mut other, &["SET", "k", "theirs"]).await;
+            request(&mut client, &["MULTI"]).await;
+            request(&mut client, &["GET", "k"]).await;
+            assert_eq!(
+                request(&mut client, &["EXEC"]).await,
+                Frame::Array(vec![Frame::Bulk("theirs".into())])
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_watched_keys_abort_exec() {
+        let clock = Clock::paused();
+        let db = Db::with_shards(DEFAULT_SHARDS, clock.clone());
+        let addr = start_with(db, Config::default()).await;
+        let mut client = connect(addr).await;
+
+        request(&mut client, &["SET", "k", "v", "PX", "20"]).await;
+        request(&mut client, &["WATCH", "k"]).await;
+        clock.advance(Duration::from_millis(20));
+        request(&mut client, &["MULTI"]).await;
+        request(&mut client, &["SET", "k", "v"]).await;
+        assert_eq!(request(&mut client, &["EXEC"]).await, Frame::NullArray);
+    }
+
+    #[tokio::test]
+    async fn blocking_pop_timeouts_reply_with_a_null_array() {
+        let clock = Clock::paused();
+        let db = Db::with_shards(DEFAULT_SHARDS, clock.clone());
+        let addr = start_with(db.clone(), Config::default()).await;
+        let mut client = connect(addr).await;
+
+        // BLPOP 回复 `*-1`，BLMOVE 和 redis 一样回复 `$-1`
+        for (args, reply) in [
+            (&["BLPOP", "l", "1"][..], Frame::NullArray),
+            (&["BRPOP", "l", "1"][..], Frame::NullArray),
+            (&["BLMOVE", "l", "m", "LEFT", "LEFT", "1"][..], Frame::Null),
+        ] {
+            send(&mut client, args).await;
+            wait_blocked(&db, "l", 1).await;
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(read(&mut client).await, reply, "{:?}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn hello_negotiates_the_protocol() {
+        let addr = start(Config::default()).await;
+        let mut client = connect(addr).await;
+        request(&mut client, &["HSET", "h", "f", "v"]).await;
+
+        // 默认是 RESP2，HELLO 的回复是键值交替的数组
+        let Frame::Array(hello) = request(&mut client, &["HELLO"]).await else {
+            panic!("HELLO did not reply an array in RESP2");
+        };
+        assert_eq!(
+            hello[..2],
+            [Frame::Bulk("server".into()), Frame::Bulk("myredis".into())]
+        );
+        assert_eq!(
+            hello[4..6],
+            [Frame::Bulk("proto".into()), Frame::Integer(2)]
+        );
+        assert_eq!(
+            request(&mut client, &["HGETALL", "h"]).await,
+            array(&["f", "v"])
+        );
+        assert_eq!(
+            request(&mut client, &["ZADD", "z", "INCR", "1.5", "m"]).await,
+            Frame::Bulk("1.5".into())
+        );
+        assert_eq!(request(&mut client, &["GET", "none"]).await, Frame::Null);
+
+        // HELLO 的回复已经用新的协议
+        let Frame::Map(hello) = request(&mut client, &["HELLO", "3"]).await else {
+            panic!("HELLO 3 did not reply a map");
+        };
+        assert_eq!(hello[2], (Frame::Bulk("proto".into()), Frame::Integer(3)));
+        assert_eq!(
+            request(&mut client, &["HGETALL", "h"]).await,
+            Frame::Map(vec![(Frame::Bulk("f".into()), Frame::Bulk("v".into()))])
+        );
+        assert_eq!(
+            request(&mut client, &["ZADD", "z", "INCR", "1.5", "m"]).await,
+            Frame::Double(3.0)
+        );
+
+        // 不支持的版本不改变协议
+        assert_eq!(
+            request(&mut client, &["HELLO", "4"]).await,
+            Frame::Error("NOPROTO unsupported protocol version".to_string())
+        );
+        assert!(matches!(
+            request(&mut client, &["HGETALL", "h"]).await,
+            Frame::Map(_)
+        ));
+        assert!(matches!(
+            request(&mut client, &["HELLO", "2"]).await,
+            Frame::Array(_)
+        ));
+        assert_eq!(
+            request(&mut client, &["HGETALL", "h"]).await,
+            array(&["f", "v"])
+        );
+
+        // 每个连接有自己的协议
+        let mut other = connect(addr).await;
+        request(&mut client, &["HELLO", "3"]).await;
+        assert_eq!(
+            request(&mut other, &["HGETALL", "h"]).await,
+            array(&["f", "v"])
+        );
+    }
+
+    #[tokio::test]
+    async fn inline_commands() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let addr = start(Config::default()).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // 和 nc 里手动输入的一样，可以和 RESP 的命令混在一起
+        stream
+            .write_all(
+                b"PING\r\nSET k \"hello world\"\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\n\nget 'k'\n",
+            )
+            .await
+            .unwrap();
+        let expected = "+PONG\r\n+OK\r\n$11\r\nhello world\r\n$11\r\nhello world\r\n";
+        let mut buf = vec![0; expected.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), expected);
+
+        // 引号不配对时回复错误并关闭连接
+        stream.write_all(b"SET k \"oops\r\n").await.unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        assert!(reply.starts_with("-ERR Protocol error"), "{}", reply);
+        assert!(reply.contains("unbalanced quotes in request"), "{}", reply);
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_limits_are_rejected() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let addr = start(Config {
+            limits: Limits {
+                max_bulk_len: 16,
+                max_buffer_len: 64,
+                ..Limits::default()
+            },
+            ..Config::default()
+        })
+        .await;
+
+        // 回复错误之后才关闭连接
+        for (request, err) in [
+            (
+                &b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1000000\r\n"[..],
+                "-ERR Protocol error: invalid bulk length\r\n",
+            ),
+            (&[b'a'; 100][..], "-ERR Protocol error: too big request\r\n"),
+        ] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"PING\r\n").await.unwrap();
+            stream.write_all(request).await.unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            assert_eq!(reply, format!("+PONG\r\n{}", err));
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_writes_of_a_transaction_are_logged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("appendonly.aof");
+        let addr = start(Config {
+            appendonly: Some(path.clone()),
+            appendfsync: Fsync::Always,
+            ..Config::default()
+        })
+        .await;
+        let mut client = connect(addr).await;
+
+        request(&mut client, &["SET", "k", "v"]).await;
+        request(&mut client, &["MULTI"]).await;
+        for args in [
+            &["GET", "k"][..],
+            &["SET", "k", "w"],
+            &["PUBLISH", "c", "m"],
+            &["UNWATCH"],
+            &["RPUSH", "l", "a"],
+        ] {
+            request(&mut client, args).await;
+        }
+        request(&mut client, &["EXEC"]).await;
+        // 只有读命令的事务什么都不记录
+        request(&mut client, &["MULTI"]).await;
+        request(&mut client, &["GET", "k"]).await;
+        request(&mut client, &["EXEC"]).await;
+
+        let expected = logged(&[
+            &["SET", "k", "v"],
+            &["MULTI"],
+            &["SET", "k", "w"],
+            &["RPUSH", "l", "a"],
+            &["EXEC"],
+        ]);
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
+
+        let db = Db::new();
+        let replayed = aof::replay(&db, &path, &Limits::default()).await.unwrap();
+        assert_eq!(replayed, 3);
+        assert_eq!(apply(&db, &["GET", "k"]).await, Frame::Bulk("w".into()));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn multi_key_commands_do_not_deadlock() {
+        let addr = start(Config::default()).await;
+        let keys = ["k0", "k1", "k2", "k3", "k4", "k5", "k6", "k7"];
+        let mut tasks = Vec::new();
+        // 每个客户端按不同的顺序给出同一组键
+        for i in 0..8 {
+            let mut keys = keys;
+            if i % 2 == 1 {
+                keys.reverse();
+            }
+            tasks.push(tokio::spawn(async move {
+                let mut client = connect(addr).await;
+                for _ in 0..200 {
+                    let mut mset = vec!["MSET"];
+                    for key in keys {
+                        mset.extend([key, "v"]);
+                    }
+                    request(&mut client, &mset).await;
+                    request(&mut client, &["RENAME", keys[0], keys[7]]).await;
+                    request(&mut client, &["MULTI"]).await;
+                    for key in keys {
+                        request(&mut client, &["SET", key, "w"]).await;
+                    }
+                    request(&mut client, &["EXEC"]).await;
+                }
+            }));
+        }
+        let all = futures::future::try_join_all(tasks);
+        let res = tokio::time::timeout(Duration::from_secs(30), all).await;
+        res.expect("deadlocked").unwrap();
+    }
+}
@@ -0,0 +1,687 @@
+//! Snapshot persistence.
+//! 快照是数据库的二进制拷贝，格式和 redis 的 RDB 文件类似：文件头、每个键一条记录、结束标记，
+//! 最后是前面所有字节的 CRC-64 校验和，加载时校验和不对的快照会被拒绝。
+//! 写快照时先写到同一目录下的临时文件，刷到磁盘之后再原子地重命名，崩溃时不会留下写了一半的快照。
+//! 每次保存的临时文件名都不同，同时进行的两次保存不会写到同一个文件里。
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use tracing::{error, info};
+
+use super::{
+    db::{Db, Record, Value},
+    zset::SortedSet,
+    Result,
+};
+
+const MAGIC: &[u8] = b"MYREDIS";
+const VERSION: u8 = 1;
+
+// 记录前面的操作码
+const OP_EXPIRE_MS: u8 = 0xfc;
+const OP_EOF: u8 = 0xff;
+
+// 值的类型
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_HASH: u8 = 2;
+const TYPE_SET: u8 = 3;
+const TYPE_ZSET: u8 = 4;
+
+/// Saves snapshots of a database to a file, one save at a time.
+///
+/// Cloning it gives another handle that shares the same file and the same save in progress.
+#[derive(Clone)]
+pub struct Snapshotter {
+    db: Db,
+    path: Arc<PathBuf>,
+    saving: Arc<AtomicBool>,
+}
+
+impl Snapshotter {
+    pub fn new(db: Db, path: PathBuf) -> Snapshotter {
+        Snapshotter {
+            db,
+            path: Arc::new(path),
+            saving: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Save a snapshot and wait for it to be on disk, returning the number of saved keys
+    pub async fn save(&self) -> Result<usize> {
+        let guard = self.start()?;
+        let (db, path) = (self.db.clone(), self.path.clone());
+        let saved = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            save(&db, &path)
+        })
+        .await??;
+        Ok(saved)
+    }
+
+    /// Start saving a snapshot in the background, commands keep running meanwhile
+    pub fn bgsave(&self) -> Result<()> {
+        let guard = self.start()?;
+        let (db, path) = (self.db.clone(), self.path.clone());
+        tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            match save(&db, &path) {
+                Ok(saved) => info!(saved, path = %path.display(), "background saving terminated"),
+                Err(err) => error!(cause = %err, "background saving failed"),
+            }
+        });
+        Ok(())
+    }
+
+    fn start(&self) -> Result<SaveGuard> {
+        if self.saving.swap(true, Ordering::AcqRel) {
+            return Err("ERR Background save already in progress".into());
+        }
+        Ok(SaveGuard(self.saving.clone()))
+    }
+}
+
+/// Marks the save in progress as done when dropped
+struct SaveGuard(Arc<AtomicBool>);
+
+impl Drop for SaveGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Write a snapshot of `db` to `path` through a temporary file that replaces `path` once complete,
+/// returning the number of saved keys
+pub fn save(db: &Db, path: &Path) -> io::Result<usize> {
+    let tmp = temp_path(path);
+
+    let written = (|| {
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        let saved = write(db, &mut file)?;
+        file.into_inner()?.sync_all()?;
+        Ok(saved)
+    })();
+    let saved = match written {
+        Ok(saved) => saved,
+        Err(err) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(err);
+        }
+    };
+
+    fs::rename(&tmp, path)?;
+    sync_dir(path)?;
+    Ok(saved)
+}
+
+/// A temporary file next to `path`, unique to each save: the snapshot saved on shutdown can run
+/// while a BGSAVE of the same file is in progress, they must not write to the same file
+fn temp_path(path: &Path) -> PathBuf {
+    static NEXT_SAVE: AtomicU64 = AtomicU64::new(0);
+    let mut tmp = OsString::from(path.as_os_str());
+    let save = NEXT_SAVE.fetch_add(1, Ordering::Relaxed);
+    tmp.push(format!(".{}-{}.tmp", process::id(), save));
+    PathBuf::from(tmp)
+}
+
+/// Flush the directory of `path` to disk, so that a file renamed into it is not lost on a crash
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Write a snapshot of `db`, returning the number of saved keys
+pub fn write(db: &Db, writer: impl Write) -> io::Result<usize> {
+    let mut writer = Writer {
+        inner: writer,
+        crc: Crc64::new(),
+    };
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])?;
+
+    let mut saved = 0;
+    for records in db.dump() {
+        for record in &records {
+            writer.record(record)?;
+        }
+        saved += records.len();
+    }
+
+    writer.write_all(&[OP_EOF])?;
+    let checksum = writer.crc.finish();
+    writer.inner.write_all(&checksum.to_le_bytes())?;
+    writer.inner.flush()?;
+    Ok(saved)
+}
+
+/// Load the snapshot at `path` into `db`, returning the number of loaded keys. Keys that expired
+/// since the snapshot was saved are skipped.
+pub fn load(db: &Db, path: &Path) -> io::Result<usize> {
+    let records = read(Bytes::from(fs::read(path)?))?;
+    // 已经过期的键不会被加载，不计入加载的键数
+    let loaded = records
+        .into_iter()
+        .map(|record| db.restore(record))
+        .filter(|&restored| restored)
+        .count();
+    Ok(loaded)
+}
+
+/// Decode a snapshot, checking its checksum first
+pub fn read(data: Bytes) -> io::Result<Vec<Record>> {
+    if data.len() < MAGIC.len() + 1 + 8 || !data.starts_with(MAGIC) {
+        return Err(invalid("not a snapshot"));
+    }
+    let (body, trailer) = data.split_at(data.len() - 8);
+    let mut crc = Crc64::new();
+    crc.update(body);
+    if crc.finish().to_le_bytes() != trailer {
+        return Err(invalid("snapshot checksum mismatch"));
+    }
+
+    let mut reader = Reader {
+        data: data.slice(MAGIC.len()..data.len() - 8),
+    };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(invalid(&format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+
+    let mut records = Vec::new();
+    loop {
+        let mut expires_at = None;
+        let mut op = reader.byte()?;
+        if op == OP_EXPIRE_MS {
+            expires_at = Some(UNIX_EPOCH + Duration::from_millis(reader.u64()?));
+            op = reader.byte()?;
+        }
+        if op == OP_EOF {
+            break;
+        }
+
+        let key = String::from_utf8(reader.bytes()?.to_vec())
+            .map_err(|_| invalid("snapshot key is not valid UTF-8"))?;
+        let value = reader.value(op)?;
+        records.push(Record {
+            key,
+            value,
+            expires_at,
+        });
+    }
+
+    if !reader.data.is_empty() {
+        return Err(invalid("trailing bytes after the end of the snapshot"));
+    }
+    Ok(records)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Encodes records and computes the checksum of everything written
+struct Writer<W> {
+    inner: W,
+    crc: Crc64,
+}
+
+impl<W: Write> Writer<W> {
+    fn record(&mut self, record: &Record) -> io::Result<()> {
+        if let Some(expires_at) = record.expires_at {
+            let millis = expires_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64);
+            self.write_all(&[OP_EXPIRE_MS])?;
+            self.write_all(&millis.to_le_bytes())?;
+        }
+
+        match &record.value {
+            Value::String(value) => {
+                self.header(TYPE_STRING, &record.key)?;
+                self.bytes(value)?;
+            }
+            Value::List(list) => {
+                self.header(TYPE_LIST, &record.key)?;
+                self.len(list.len())?;
+                for element in list {
+                    self.bytes(element)?;
+                }
+            }
+            Value::Hash(hash) => {
+                self.header(TYPE_HASH, &record.key)?;
+                self.len(hash.len())?;
+                for (field, value) in hash {
+                    self.bytes(field)?;
+                    self.bytes(value)?;
+                }
+            }
+            Value::Set(set) => {
+                self.header(TYPE_SET, &record.key)?;
+                self.len(set.len())?;
+                for member in set {
+                    self.bytes(member)?;
+                }
+            }
+            Value::SortedSet(zset) => {
+                self.header(TYPE_ZSET, &record.key)?;
+                self.len(zset.len())?;
+                for (member, score) in zset.iter_from_rank(0, false) {
+                    self.bytes(member)?;
+                    self.write_all(&score.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn header(&mut self, kind: u8, key: &str) -> io::Result<()> {
+        self.write_all(&[kind])?;
+        self.bytes(key.as_bytes())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.len(bytes.len())?;
+        self.write_all(bytes)
+    }
+
+    /// A length as a LEB128 varint: 7 bits per byte, the high bit is set on all but the last byte
+    fn len(&mut self, len: usize) -> io::Result<()> {
+        let mut len = len as u64;
+        loop {
+            let byte = (len & 0x7f) as u8;
+            len >>= 7;
+            if len == 0 {
+                return self.write_all(&[byte]);
+            }
+            self.write_all(&[byte | 0x80])?;
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.crc.update(bytes);
+        self.inner.write_all(bytes)
+    }
+}
+
+/// Decodes records, strings are slices of the snapshot without copying
+struct Reader {
+    data: Bytes,
+}
+
+impl Reader {
+    fn value(&mut self, kind: u8) -> io::Result<Value> {
+        Ok(match kind {
+            TYPE_STRING => Value::String(self.bytes()?),
+            TYPE_LIST => {
+                let len = self.len()?;
+                let mut list = VecDeque::new();
+                for _ in 0..len {
+                    list.push_back(self.bytes()?);
+                }
+                Value::List(list)
+            }
+            TYPE_HASH => {
+                let len = self.len()?;
+                let mut hash = HashMap::new();
+                for _ in 0..len {
+                    hash.insert(self.bytes()?, self.bytes()?);
+                }
+                Value::Hash(hash)
+            }
+            TYPE_SET => {
+                let len = self.len()?;
+                let mut set = HashSet::new();
+                for _ in 0..len {
+                    set.insert(self.bytes()?);
+                }
+                Value::Set(set)
+            }
+            TYPE_ZSET => {
+                let len = self.len()?;
+                let mut zset = SortedSet::new();
+                for _ in 0..len {
+                    let member = self.bytes()?;
+                    let score = f64::from_le_bytes(self.array()?);
+                    if score.is_nan() {
+                        return Err(invalid("NaN score in snapshot"));
+                    }
+                    zset.insert(member, score);
+                }
+                Value::SortedSet(zset)
+            }
+            kind => return Err(invalid(&format!("unknown value type {} in snapshot", kind))),
+        })
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self.take(N)?;
+        Ok(bytes[..].try_into().unwrap())
+    }
+
+    fn bytes(&mut self) -> io::Result<Bytes> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let mut len = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            len |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(len).map_err(|_| invalid("length out of range"));
+            }
+        }
+        Err(invalid("length out of range"))
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<Bytes> {
+        if self.data.len() < len {
+            return Err(invalid("unexpected end of snapshot"));
+        }
+        Ok(self.data.split_to(len))
+    }
+}
+
+/// CRC-64/XZ, the CRC-64 of the xz file format
+struct Crc64(u64);
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc64_table() -> [u64; 256] {
+    // ECMA-182 多项式按位反转后的值
+    const POLY: u64 = 0xc96c_5795_d787_0f42;
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+impl Crc64 {
+    fn new() -> Crc64 {
+        Crc64(!0)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = CRC64_TABLE[((self.0 ^ byte as u64) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        !self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// A database holding a key of each type, and a string that expires in an hour
+    fn db() -> Db {
+        let db = Db::new();
+        let string = |value: &'static str| Value::String(Bytes::from(value));
+        let mut zset = SortedSet::new();
+        zset.insert(Bytes::from("a"), 1.5);
+        zset.insert(Bytes::from("b"), f64::NEG_INFINITY);
+        let records = [
+            ("string", string("value"), None),
+            ("empty", string(""), None),
+            (
+                "expiring",
+                string("soon"),
+                Some(SystemTime::now() + Duration::from_secs(3600)),
+            ),
+            (
+                "list",
+                Value::List(["x", "y", "x"].map(Bytes::from).into()),
+                None,
+            ),
+            (
+                "hash",
+                Value::Hash(HashMap::from([(Bytes::from("f"), Bytes::from("v"))])),
+                None,
+            ),
+            ("set", Value::Set(HashSet::from([Bytes::from("m")])), None),
+            ("zset", Value::SortedSet(zset), None),
+        ];
+        for (key, value, expires_at) in records {
+            db.restore(Record {
+                key: key.to_string(),
+                value,
+                expires_at,
+            });
+        }
+        db
+    }
+
+    /// The records of `db` sorted by key
+    fn records(db: &Db) -> Vec<Record> {
+        let mut records: Vec<Record> = db.dump().flatten().collect();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        records
+    }
+
+    fn snapshot(db: &Db) -> Vec<u8> {
+        let mut data = Vec::new();
+        write(db, &mut data).unwrap();
+        data
+    }
+
+    /// A snapshot of `records` as they are, even if they have expired
+    fn encode(records: &[Record]) -> Vec<u8> {
+        let mut writer = Writer {
+            inner: Vec::new(),
+            crc: Crc64::new(),
+        };
+        writer.write_all(MAGIC).unwrap();
+        writer.write_all(&[VERSION]).unwrap();
+        for record in records {
+            writer.record(record).unwrap();
+        }
+        writer.write_all(&[OP_EOF]).unwrap();
+        let checksum = writer.crc.finish();
+        writer.inner.extend_from_slice(&checksum.to_le_bytes());
+        writer.inner
+    }
+
+    fn error(data: Vec<u8>) -> String {
+        read(Bytes::from(data)).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let db = db();
+        assert_eq!(save(&db, &path).unwrap(), 7);
+        // 临时文件已经被重命名
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let loaded = Db::new();
+        assert_eq!(load(&loaded, &path).unwrap(), 7);
+        let (saved, loaded) = (records(&db), records(&loaded));
+        for (saved, loaded) in saved.iter().zip(&loaded) {
+            assert_eq!((&saved.key, &saved.value), (&loaded.key, &loaded.value));
+            assert_eq!(saved.expires_at.is_some(), loaded.expires_at.is_some());
+        }
+        assert_eq!(saved.len(), loaded.len());
+        let expiring = loaded
+            .iter()
+            .find(|record| record.key == "expiring")
+            .unwrap();
+        let ttl = expiring
+            .expires_at
+            .unwrap()
+            .duration_since(SystemTime::now());
+        assert!(ttl.unwrap() > Duration::from_secs(3590));
+
+        // 再次保存时覆盖之前的快照
+        let db = Db::new();
+        assert_eq!(save(&db, &path).unwrap(), 0);
+        assert_eq!(load(&Db::new(), &path).unwrap(), 0);
+    }
+
+    #[test]
+    fn expired_keys_are_not_loaded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dump.rdb");
+        let string = |key: &str, expires_at| Record {
+            key: key.to_string(),
+            value: Value::String(Bytes::from("v")),
+            expires_at,
+        };
+        let data = encode(&[
+            string("expired", Some(UNIX_EPOCH + Duration::from_secs(1))),
+            string("live", None),
+            string("later", Some(SystemTime::now() + Duration::from_secs(60))),
+        ]);
+        fs::write(&path, data).unwrap();
+
+        let db = Db::new();
+        assert_eq!(load(&db, &path).unwrap(), 2);
+        let keys: Vec<String> = records(&db).into_iter().map(|record| record.key).collect();
+        assert_eq!(keys, ["later", "live"]);
+    }
+
+    #[test]
+    fn corrupt_snapshots_are_rejected() {
+        let data = snapshot(&db());
+        assert!(read(Bytes::from(data.clone())).is_ok());
+
+        // 任何一个字节被改动，校验和都不对
+        for i in [MAGIC.len() + 1, data.len() / 2, data.len() - 9] {
+            let mut corrupt = data.clone();
+            corrupt[i] ^= 0x01;
+            assert_eq!(error(corrupt), "snapshot checksum mismatch");
+        }
+        let mut corrupt = data.clone();
+        *corrupt.last_mut().unwrap() ^= 0x80;
+        assert_eq!(error(corrupt), "snapshot checksum mismatch");
+        assert_eq!(
+            error(data[..data.len() - 1].to_vec()),
+            "snapshot checksum mismatch"
+        );
+        assert_eq!(error(b"MYREDIS".to_vec()), "not a snapshot");
+        assert_eq!(error(b"REDIS0011aaaaaaaaaaa".to_vec()), "not a snapshot");
+
+        // 校验和正确但内容不对
+        let mut other_version = encode(&[]);
+        other_version[MAGIC.len()] = 2;
+        let body = other_version.len() - 8;
+        let mut crc = Crc64::new();
+        crc.update(&other_version[..body]);
+        other_version[body..].copy_from_slice(&crc.finish().to_le_bytes());
+        assert_eq!(error(other_version), "unsupported snapshot version 2");
+    }
+
+    #[test]
+    fn corrupt_file_is_not_loaded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dump.rdb");
+        save(&db(), &path).unwrap();
+        let mut data = fs::read(&path).unwrap();
+        data.truncate(data.len() - 3);
+        fs::write(&path, data).unwrap();
+
+        let db = Db::new();
+        let err = load(&db, &path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(records(&db), []);
+        assert_eq!(
+            load(&db, &dir.path().join("missing.rdb"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn concurrent_saves_use_their_own_temporary_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("dump.rdb");
+        assert_ne!(temp_path(&path), temp_path(&path));
+
+        let db = db();
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| assert_eq!(save(&db, &path).unwrap(), 7));
+            }
+        });
+        assert_eq!(load(&Db::new(), &path).unwrap(), 7);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn one_save_at_a_time() {
+        let dir = TempDir::new().unwrap();
+        let snapshotter = Snapshotter::new(db(), dir.path().join("dump.rdb"));
+        let in_progress = "ERR Background save already in progress";
+
+        // 有保存正在进行时，SAVE 和 BGSAVE 都会失败
+        let saving = snapshotter.start().unwrap();
+        assert_eq!(snapshotter.bgsave().unwrap_err().to_string(), in_progress);
+        let other = snapshotter.clone();
+        assert_eq!(other.save().await.unwrap_err().to_string(), in_progress);
+        drop(saving);
+
+        assert_eq!(snapshotter.save().await.unwrap(), 7);
+        assert_eq!(load(&Db::new(), snapshotter.path()).unwrap(), 7);
+    }
+
+    #[test]
+    fn crc64() {
+        // CRC-64/XZ 的标准校验值
+        let mut crc = Crc64::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0x995d_c9bb_df19_39fa);
+    }
+}
@@ -0,0 +1,271 @@
+//! TLS.
+//! 服务端和客户端的 TLS 配置，证书和私钥都从 PEM 文件读取。握手完成以后的加密流和 TCP 流一样交给
+//! `Connection` 读写帧。
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+use super::Result;
+
+/// The TLS configuration of a server
+#[derive(Debug, Clone)]
+pub struct ServerTls {
+    /// The certificate chain of the server, the server certificate first
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_auth: ClientAuth,
+}
+
+/// Whether a server asks the clients for a certificate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// The clients are not authenticated
+    #[default]
+    No,
+    /// A client may send a certificate, signed by one of the certificate authorities of the file.
+    /// A client without a certificate is accepted.
+    Optional(PathBuf),
+    /// A client must send a certificate, signed by one of the certificate authorities of the file
+    Required(PathBuf),
+}
+
+/// The TLS configuration of a client
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    /// The certificate authorities the server certificate is verified against
+    pub ca: PathBuf,
+    /// The certificate chain and the private key the client authenticates with, when the server
+    /// asks for them
+    pub identity: Option<(PathBuf, PathBuf)>,
+}
+
+impl ServerTls {
+    /// Load the certificates and the key, the acceptor performs the server side of the handshake
+    pub fn acceptor(&self) -> Result<TlsAcceptor> {
+        let builder = rustls::ServerConfig::builder();
+        let builder = match &self.client_auth {
+            ClientAuth::No => builder.with_no_client_auth(),
+            ClientAuth::Optional(ca) => {
+                let verifier = WebPkiClientVerifier::builder(roots(ca)?)
+                    .allow_unauthenticated()
+                    .build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            ClientAuth::Required(ca) => {
+                let verifier = WebPkiClientVerifier::builder(roots(ca)?).build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+        let config = builder.with_single_cert(certs(&self.cert)?, key(&self.key)?)?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl ClientTls {
+    /// Load the certificates and the key, the connector performs the client side of the handshake
+    pub fn connector(&self) -> Result<TlsConnector> {
+        let builder = rustls::ClientConfig::builder().with_root_certificates(roots(&self.ca)?);
+        let config = match &self.identity {
+            Some((cert_file, key_file)) => {
+                builder.with_client_auth_cert(certs(cert_file)?, key(key_file)?)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+fn certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|err| format!("failed to load {}: {}", path.display(), err))?;
+    if certs.is_empty() {
+        return Err(format!("no certificate in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+fn key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|err| format!("failed to load {}: {}", path.display(), err).into())
+}
+
+fn roots(path: &Path) -> Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots.add(cert)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use bytes::Bytes;
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair};
+    use tempfile::TempDir;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::myredis::{
+        client::Client,
+        db::Db,
+        server::{self, Config},
+    };
+
+    /// A certificate authority generated for a test, its files are written in a temporary
+    /// directory
+    struct Authority {
+        dir: TempDir,
+        cert: Certificate,
+        key: KeyPair,
+    }
+
+    impl Authority {
+        fn new(name: &str) -> Authority {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let cert = params.self_signed(&key).unwrap();
+            let dir = tempfile::tempdir().unwrap();
+            std::fs::write(dir.path().join("ca.pem"), cert.pem()).unwrap();
+            Authority { dir, cert, key }
+        }
+
+        fn ca(&self) -> PathBuf {
+            self.dir.path().join("ca.pem")
+        }
+
+        /// Sign a certificate for `names`, returns the paths of the certificate and its key
+        fn issue(&self, file: &str, names: &[&str]) -> (PathBuf, PathBuf) {
+            let key = KeyPair::generate().unwrap();
+            let names = names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            let mut params = CertificateParams::new(names).unwrap();
+            params.distinguished_name.push(DnType::CommonName, file);
+            let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+
+            let cert_path = self.dir.path().join(format!("{}.pem", file));
+            let key_path = self.dir.path().join(format!("{}.key", file));
+            std::fs::write(&cert_path, cert.pem()).unwrap();
+            std::fs::write(&key_path, key.serialize_pem()).unwrap();
+            (cert_path, key_path)
+        }
+    }
+
+    /// Start a TLS server with a certificate for `localhost` signed by `authority`
+    async fn start(authority: &Authority, client_auth: ClientAuth) -> SocketAddr {
+        let (cert, key) = authority.issue("server", &["localhost"]);
+        let config = Config {
+            tls: Some(ServerTls {
+                cert,
+                key,
+                client_auth,
+            }),
+            ..Config::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { server::run_with(&listener, Db::new(), config).await });
+        addr
+    }
+
+    /// Connect to `addr` with `tls` and send a PING
+    async fn ping(addr: SocketAddr, tls: &ClientTls) -> Result<Bytes> {
+        let mut client = Client::connect_tls(addr, "localhost", tls).await?;
+        client.ping(None).await
+    }
+
+    #[tokio::test]
+    async fn commands_over_tls() {
+        let authority = Authority::new("myredis test ca");
+        let addr = start(&authority, ClientAuth::No).await;
+        let tls = ClientTls {
+            ca: authority.ca(),
+            identity: None,
+        };
+
+        let mut client = Client::connect_tls(addr, "localhost", &tls).await.unwrap();
+        assert_eq!(client.ping(None).await.unwrap(), "PONG");
+        client.set("k", Bytes::from("v")).await.unwrap();
+        assert_eq!(client.get("k").await.unwrap(), Some(Bytes::from("v")));
+
+        // 明文连接在握手时就失败了
+        let mut plain = Client::connect(addr).await.unwrap();
+        assert!(plain.ping(None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn untrusted_server_certificate_is_rejected() {
+        let authority = Authority::new("myredis test ca");
+        let addr = start(&authority, ClientAuth::No).await;
+
+        let other = Authority::new("other ca");
+        let tls = ClientTls {
+            ca: other.ca(),
+            identity: None,
+        };
+        let err = ping(addr, &tls).await.unwrap_err();
+        assert!(err.to_string().contains("UnknownIssuer"), "{}", err);
+
+        // 证书不是签给这个名字的
+        let tls = ClientTls {
+            ca: authority.ca(),
+            identity: None,
+        };
+        let res = Client::connect_tls(addr, "127.0.0.1", &tls).await;
+        let err = res.err().expect("connected to a server of another name");
+        assert!(err.to_string().contains("not valid for name"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn client_certificates() {
+        let authority = Authority::new("myredis test ca");
+        let addr = start(&authority, ClientAuth::Required(authority.ca())).await;
+
+        let tls = ClientTls {
+            ca: authority.ca(),
+            identity: Some(authority.issue("client", &[])),
+        };
+        assert_eq!(ping(addr, &tls).await.unwrap(), "PONG");
+
+        // 没有证书或者证书不是服务端信任的机构签发的，服务端拒绝连接
+        let other = Authority::new("other ca");
+        for identity in [None, Some(other.issue("client", &[]))] {
+            let tls = ClientTls {
+                ca: authority.ca(),
+                identity,
+            };
+            assert!(ping(addr, &tls).await.is_err());
+        }
+
+        // 证书可选时没有证书的客户端也可以连接
+        let addr = start(&authority, ClientAuth::Optional(authority.ca())).await;
+        let tls = ClientTls {
+            ca: authority.ca(),
+            identity: None,
+        };
+        assert_eq!(ping(addr, &tls).await.unwrap(), "PONG");
+        let tls = ClientTls {
+            ca: authority.ca(),
+            identity: Some(other.issue("client", &[])),
+        };
+        assert!(ping(addr, &tls).await.is_err());
+    }
+}
@@ -0,0 +1,663 @@
+//! Sorted set.
+//! 和 redis 一样，有序集合由一个从成员到分数的哈希表和一个按 (分数, 成员) 排序的跳表组成：
+//! 哈希表用来 O(1) 地查找成员的分数，跳表用来 O(log n) 地插入、删除、按排名或分数查找。
+//! 跳表的每一层指针都记录了它跨过的节点数(span)，这样在查找的同时可以算出节点的排名。
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::BuildHasher,
+    ops::Bound,
+};
+
+use bytes::Bytes;
+
+/// The maximum number of levels of the skip list, enough for 2^64 elements with p = 1/4
+const MAX_LEVEL: usize = 32;
+
+/// The index of the head node in the arena
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<Bytes, f64>,
+    list: SkipList,
+}
+
+/// A bound of a lexicographical range, as given to `ZRANGE ... BYLEX`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    /// `-`: lower than any member
+    Min,
+    /// `+`: greater than any member
+    Max,
+    /// `[member`
+    Included(Bytes),
+    /// `(member`
+    Excluded(Bytes),
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add `member` or update its score, returning whether it was added.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `score` is NaN.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        assert!(
+            !score.is_nan(),
+            "the score of a sorted set member can't be NaN"
+        );
+
+        let added = match self.scores.insert(member.clone(), score) {
+            Some(current) if current == score => return false,
+            Some(current) => {
+                self.list.remove(current, &member);
+                false
+            }
+            None => true,
+        };
+        self.list.insert(score, member);
+        added
+    }
+
+    /// Remove `member`, returning whether it was in the set
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.list.remove(score, member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The 0-based rank of `member` ordered by score
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    /// The members from the one at `rank`, towards higher scores or towards lower scores with `rev`
+    pub fn iter_from_rank(&self, rank: usize, rev: bool) -> Iter<'_> {
+        let rank = if rev {
+            self.len().checked_sub(rank + 1)
+        } else {
+            Some(rank)
+        };
+        Iter {
+            list: &self.list,
+            next: rank.and_then(|rank| self.list.by_rank(rank)),
+            rev,
+        }
+    }
+
+    /// The members whose score is within `min` and `max`, from the highest score with `rev`
+    pub fn range_by_score(
+        &self,
+        min: Bound<f64>,
+        max: Bound<f64>,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start = if rev {
+            self.list.last_where(|node| below(&max, &node.score))
+        } else {
+            self.list.first_where(|node| above(&min, &node.score))
+        };
+        let iter = Iter {
+            list: &self.list,
+            next: start,
+            rev,
+        };
+        iter.take_while(move |(_, score)| {
+            if rev {
+                above(&min, score)
+            } else {
+                below(&max, score)
+            }
+        })
+    }
+
+    /// The members within `min` and `max` in lexicographical order, from the greatest with `rev`.
+    ///
+    /// Like in redis, the result is only meaningful when all the members have the same score.
+    pub fn range_by_lex(
+        &self,
+        min: LexBound,
+        max: LexBound,
+        rev: bool,
+    ) -> impl Iterator<Item = (&Bytes, f64)> {
+        let start = if rev {
+            self.list.last_where(|node| max.is_above(&node.member))
+        } else {
+            self.list.first_where(|node| min.is_below(&node.member))
+        };
+        let iter = Iter {
+            list: &self.list,
+            next: start,
+            rev,
+        };
+        iter.take_while(move |(member, _)| {
+            if rev {
+                min.is_below(member)
+            } else {
+                max.is_above(member)
+            }
+        })
+    }
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl LexBound {
+    /// Whether `member` is on the upper side of the bound, when it is used as a minimum
+    fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Included(bound) => member >= &bound[..],
+            LexBound::Excluded(bound) => member > &bound[..],
+        }
+    }
+
+    /// Whether `member` is on the lower side of the bound, when it is used as a maximum
+    fn is_above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Included(bound) => member <= &bound[..],
+            LexBound::Excluded(bound) => member < &bound[..],
+        }
+    }
+}
+
+fn above<T: PartialOrd>(min: &Bound<T>, value: &T) -> bool {
+    match min {
+        Bound::Included(min) => value >= min,
+        Bound::Excluded(min) => value > min,
+        Bound::Unbounded => true,
+    }
+}
+
+fn below<T: PartialOrd>(max: &Bound<T>, value: &T) -> bool {
+    match max {
+        Bound::Included(max) => value <= max,
+        Bound::Excluded(max) => value < max,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Iterator over the members of a sorted set and their scores
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    next: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Bytes, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.next?];
+        self.next = if self.rev {
+            node.backward
+        } else {
+            node.levels[0].next
+        };
+        Some((&node.member, node.score))
+    }
+}
+
+/// A skip list ordered by score then member, whose nodes live in an arena indexed by position
+#[derive(Debug, Clone)]
+struct SkipList {
+    /// `nodes[HEAD]` is the head, it has `MAX_LEVEL` levels and no member
+    nodes: Vec<Node>,
+    /// Slots of removed nodes, reused by the next insertions
+    free: Vec<usize>,
+    /// The number of levels in use
+    level: usize,
+    len: usize,
+    tail: Option<usize>,
+    /// Picks the level of a new node from its member
+    hasher: RandomState,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    /// The previous node, `None` for the first node
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    next: Option<usize>,
+    /// The number of nodes between this node and `next`, counting `next`
+    span: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let head = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            level: 1,
+            len: 0,
+            tail: None,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl SkipList {
+    /// Insert a member that is not in the list yet
+    fn insert(&mut self, score: f64, member: Bytes) {
+        let mut update = [HEAD; MAX_LEVEL];
+        // 每一层上 `update[i]` 的排名
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i + 1 == self.level { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if !self.nodes[next].is_before(score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level(&member);
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = self.alloc(Node {
+            member,
+            score,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![
+                Link {
+                    next: None,
+                    span: 0
+                };
+                level
+            ],
+        });
+        for i in 0..level {
+            let prev = self.nodes[update[i]].levels[i];
+            self.nodes[node].levels[i] = Link {
+                next: prev.next,
+                span: prev.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                next: Some(node),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        // 更高的层跨过了新节点
+        for (i, &prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[prev].levels[i].span += 1;
+        }
+
+        match self.nodes[node].levels[0].next {
+            Some(next) => self.nodes[next].backward = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    /// Remove a member, returning whether it was found
+    fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if !self.nodes[next].is_before(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let Some(node) = self.nodes[x].levels[0].next else {
+            return false;
+        };
+        if self.nodes[node].score != score || self.nodes[node].member != member {
+            return false;
+        }
+
+        for (i, &prev) in update.iter().enumerate().take(self.level) {
+            if self.nodes[prev].levels[i].next == Some(node) {
+                let removed = self.nodes[node].levels[i];
+                let link = &mut self.nodes[prev].levels[i];
+                link.span = link.span + removed.span - 1;
+                link.next = removed.next;
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        match self.nodes[node].levels[0].next {
+            Some(next) => self.nodes[next].backward = self.nodes[node].backward,
+            None => self.tail = self.nodes[node].backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].next.is_none() {
+            self.level -= 1;
+        }
+        self.len -= 1;
+
+        self.nodes[node].member = Bytes::new();
+        self.free.push(node);
+        true
+    }
+
+    /// The 0-based rank of a member
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if self.nodes[next].is_after(score, member) {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].score == score && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// The node with the 0-based `rank`
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        let target = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if traversed + self.nodes[x].levels[i].span > target {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == target {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    /// The first node matching `pred`, which must be false then true along the list
+    fn first_where(&self, pred: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if pred(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.nodes[x].levels[0].next
+    }
+
+    /// The last node matching `pred`, which must be true then false along the list
+    fn last_where(&self, pred: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].next {
+                if !pred(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        (x != HEAD).then_some(x)
+    }
+
+    /// A level between 1 and `MAX_LEVEL`, each level being 4 times less likely than the one below
+    fn random_level(&self, member: &[u8]) -> usize {
+        // 用成员的随机哈希值代替随机数，每两个为 0 的低位增加一层
+        let hash = self.hasher.hash_one(member);
+        (1 + hash.trailing_zeros() as usize / 2).min(MAX_LEVEL)
+    }
+
+    fn alloc(&mut self, node: Node) -> usize {
+        match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+}
+
+impl Node {
+    /// Whether the node orders before `(score, member)`
+    fn is_before(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && &self.member[..] < member)
+    }
+
+    /// Whether the node orders after `(score, member)`
+    fn is_after(&self, score: f64, member: &[u8]) -> bool {
+        self.score > score || (self.score == score && &self.member[..] > member)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The members of `zset` in order, checked against the order of `(score, member)`
+    fn members(zset: &SortedSet) -> Vec<(Bytes, f64)> {
+        let mut expected: Vec<_> = zset
+            .scores
+            .iter()
+            .map(|(member, score)| (member.clone(), *score))
+            .collect();
+        expected.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        expected
+    }
+
+    /// Check the ranks and both directions of iteration against the sorted members
+    fn check(zset: &SortedSet) {
+        let expected = members(zset);
+        assert_eq!(zset.list.len, expected.len());
+        for (rank, (member, score)) in expected.iter().enumerate() {
+            assert_eq!(zset.rank(member), Some(rank));
+            // 从每个排名开始遍历都要经过 span 的计算
+            assert_eq!(
+                zset.iter_from_rank(rank, false).next(),
+                Some((member, *score))
+            );
+        }
+        let forward: Vec<_> = zset
+            .iter_from_rank(0, false)
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+        assert_eq!(forward, expected);
+        let mut backward: Vec<_> = zset
+            .iter_from_rank(0, true)
+            .map(|(member, score)| (member.clone(), score))
+            .collect();
+        backward.reverse();
+        assert_eq!(backward, expected);
+        assert_eq!(zset.iter_from_rank(expected.len(), false).next(), None);
+        assert_eq!(zset.iter_from_rank(expected.len(), true).next(), None);
+    }
+
+    /// A small deterministic pseudo-random generator
+    fn lcg(state: &mut u64) -> u64 {
+        *state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        *state >> 33
+    }
+
+    #[test]
+    fn insert_update_and_remove() {
+        let mut zset = SortedSet::new();
+        assert!(zset.insert("b".into(), 2.0));
+        assert!(zset.insert("a".into(), 2.0));
+        assert!(zset.insert("c".into(), 1.0));
+        assert!(!zset.insert("c".into(), 1.0));
+        // 分数相同时按成员排序
+        assert_eq!(zset.rank(b"c"), Some(0));
+        assert_eq!(zset.rank(b"a"), Some(1));
+        assert_eq!(zset.rank(b"b"), Some(2));
+
+        assert!(!zset.insert("c".into(), 3.0));
+        assert_eq!(zset.rank(b"c"), Some(2));
+        assert_eq!(zset.score(b"c"), Some(3.0));
+        assert!(zset.remove(b"a"));
+        assert!(!zset.remove(b"a"));
+        assert_eq!(zset.rank(b"a"), None);
+        assert_eq!(zset.len(), 2);
+        check(&zset);
+    }
+
+    #[test]
+    fn random_operations_keep_ranks() {
+        let mut zset = SortedSet::new();
+        let mut state = 42;
+        for round in 0..2000u32 {
+            let member = Bytes::from(format!("m{}", lcg(&mut state) % 300));
+            if lcg(&mut state).is_multiple_of(3) {
+                zset.remove(&member);
+            } else {
+                // 分数的范围很小，很多成员的分数相同
+                let score = (lcg(&mut state) % 50) as f64 - 25.0;
+                zset.insert(member, score);
+            }
+            if round.is_multiple_of(100) {
+                check(&zset);
+            }
+        }
+        check(&zset);
+
+        // 删除的节点被重新使用
+        let slots = zset.list.nodes.len();
+        let all: Vec<Bytes> = zset.scores.keys().cloned().collect();
+        for member in &all {
+            assert!(zset.remove(member));
+        }
+        assert!(zset.is_empty());
+        assert_eq!(zset.list.level, 1);
+        check(&zset);
+        for member in all {
+            zset.insert(member, 0.0);
+        }
+        assert_eq!(zset.list.nodes.len(), slots);
+        check(&zset);
+    }
+
+    #[test]
+    fn range_by_score() {
+        let mut zset = SortedSet::new();
+        for i in 0..100 {
+            zset.insert(Bytes::from(format!("m{:02}", i)), (i / 2) as f64);
+        }
+        let range = |min, max, rev| -> Vec<f64> {
+            zset.range_by_score(min, max, rev)
+                .map(|(_, score)| score)
+                .collect()
+        };
+
+        assert_eq!(
+            range(Bound::Included(10.0), Bound::Excluded(12.0), false),
+            [10.0, 10.0, 11.0, 11.0]
+        );
+        assert_eq!(
+            range(Bound::Excluded(10.0), Bound::Included(12.0), true),
+            [12.0, 12.0, 11.0, 11.0]
+        );
+        assert_eq!(
+            range(Bound::Unbounded, Bound::Excluded(1.0), false),
+            [0.0; 2]
+        );
+        assert_eq!(
+            range(Bound::Excluded(48.0), Bound::Unbounded, true),
+            [49.0; 2]
+        );
+        assert_eq!(range(Bound::Unbounded, Bound::Unbounded, false).len(), 100);
+        assert!(range(Bound::Included(10.5), Bound::Included(10.9), false).is_empty());
+        assert!(range(Bound::Excluded(10.0), Bound::Excluded(11.0), true).is_empty());
+        assert!(range(Bound::Included(60.0), Bound::Unbounded, false).is_empty());
+        assert!(range(Bound::Unbounded, Bound::Excluded(0.0), true).is_empty());
+    }
+
+    #[test]
+    fn range_by_lex() {
+        let mut zset = SortedSet::new();
+        for member in ["a", "b", "ba", "bb", "c", "d"] {
+            zset.insert(member.into(), 0.0);
+        }
+        let range = |min, max, rev| -> Vec<&Bytes> {
+            zset.range_by_lex(min, max, rev)
+                .map(|(member, _)| member)
+                .collect()
+        };
+        let included = |member: &'static str| LexBound::Included(member.into());
+        let excluded = |member: &'static str| LexBound::Excluded(member.into());
+
+        assert_eq!(
+            range(included("b"), excluded("c"), false),
+            ["b", "ba", "bb"]
+        );
+        assert_eq!(range(excluded("b"), included("c"), true), ["c", "bb", "ba"]);
+        assert_eq!(range(LexBound::Min, excluded("b"), false), ["a"]);
+        assert_eq!(range(included("bb"), LexBound::Max, true), ["d", "c", "bb"]);
+        assert_eq!(range(LexBound::Min, LexBound::Max, false).len(), 6);
+        assert!(range(LexBound::Max, LexBound::Min, false).is_empty());
+        assert!(range(excluded("bb"), excluded("c"), false).is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+//! Clock: the source of time for timers.
+//!
+//! 默认的系统时钟使用真实的时间；暂停的时钟只会在调用 `advance` 时前进，
+//! 并在其中确定性地唤醒到期的定时器，这样依赖超时的测试不需要真的睡眠。
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{driver::Driver, Interval, Timeout, TimerFuture};
+
+/// A handle to a source of time, timers created from a clock use its time.
+///
+/// Cloning a `Clock` is cheap, the clones share the same time.
+#[derive(Clone)]
+pub struct Clock {
+    driver: Arc<Driver>,
+}
+
+impl Clock {
+    /// The real time clock used by `TimerFuture::new` and the free functions of this module
+    pub fn system() -> Self {
+        Clock {
+            driver: Driver::global().clone(),
+        }
+    }
+
+    /// A clock that is paused at the current instant, its time only moves when `advance` is called
+    pub fn paused() -> Self {
+        Clock {
+            driver: Driver::paused(),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.driver.is_paused()
+    }
+
+    pub fn now(&self) -> Instant {
+        self.driver.now()
+    }
+
+    /// The wall-clock time matching `now`: a paused clock starts at the wall-clock time it was
+    /// created at and only moves when advanced
+    pub fn system_time(&self) -> SystemTime {
+        self.driver.system_time()
+    }
+
+    /// Move the time forward by `duration`, the timers that become due are woken before this returns.
+    ///
+    /// # Panics
+    ///
+    /// The function will panic if the clock is not paused.
+    pub fn advance(&self, duration: Duration) {
+        self.driver.advance(duration);
+    }
+
+    /// Create a `TimerFuture` that completes after `duration`
+    pub fn sleep(&self, duration: Duration) -> TimerFuture {
+        self.sleep_until(self.now() + duration)
+    }
+
+    /// Create a `TimerFuture` that completes at `deadline`
+    pub fn sleep_until(&self, deadline: Instant) -> TimerFuture {
+        TimerFuture::with_driver(self.driver.clone(), deadline)
+    }
+
+    /// Create an `Interval` whose first tick completes immediately, then every `period`.
+    ///
+    /// # Panics
+    ///
+    /// The function will panic if `period` is zero.
+    pub fn interval(&self, period: Duration) -> Interval {
+        self.interval_at(self.now(), period)
+    }
+
+    /// Create an `Interval` whose first tick completes at `start`, then every `period`.
+    ///
+    /// # Panics
+    ///
+    /// The function will panic if `period` is zero.
+    pub fn interval_at(&self, start: Instant, period: Duration) -> Interval {
+        Interval::new(self.sleep_until(start), period)
+    }
+
+    /// Require `future` to complete within `duration`
+    pub fn timeout<F: Future>(&self, duration: Duration, future: F) -> Timeout<F> {
+        Timeout::new(future, self.sleep(duration))
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::system()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    };
+
+    use futures::{
+        task::{self, ArcWake},
+        Stream,
+    };
+
+    use super::*;
+    use crate::timer_future::{Elapsed, MissedTickBehavior};
+
+    const MS: Duration = Duration::from_millis(1);
+
+    /// Counts how many times it was woken
+    #[derive(Default)]
+    struct Wakes(AtomicUsize);
+
+    impl ArcWake for Wakes {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(task::noop_waker_ref()))
+    }
+
+    fn tick(interval: &mut Interval) -> Poll<Instant> {
+        let mut cx = Context::from_waker(task::noop_waker_ref());
+        Pin::new(interval).poll_next(&mut cx).map(Option::unwrap)
+    }
+
+    #[test]
+    fn sleep_completes_when_advanced_to_deadline() {
+        let clock = Clock::paused();
+        let start = clock.now();
+        let mut sleep = clock.sleep(10 * MS);
+        assert_eq!(sleep.deadline(), start + 10 * MS);
+
+        let wakes = Arc::new(Wakes::default());
+        let waker = task::waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut sleep).poll(&mut cx).is_pending());
+
+        clock.advance(9 * MS);
+        assert_eq!(clock.now(), start + 9 * MS);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 0);
+        assert!(Pin::new(&mut sleep).poll(&mut cx).is_pending());
+
+        // 到期的定时器在 advance 返回之前就被唤醒了
+        clock.advance(MS);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 1);
+        assert!(poll(&mut sleep).is_ready());
+    }
+
+    #[test]
+    fn sleep_in_the_past_completes_immediately() {
+        let clock = Clock::paused();
+        assert!(poll(&mut clock.sleep(Duration::ZERO)).is_ready());
+        clock.advance(5 * MS);
+        assert!(poll(&mut clock.sleep_until(clock.now() - 2 * MS)).is_ready());
+    }
+
+    #[test]
+    fn reset_sleep() {
+        let clock = Clock::paused();
+        let mut sleep = clock.sleep(10 * MS);
+        sleep.reset(clock.now() + 20 * MS);
+        clock.advance(10 * MS);
+        assert!(poll(&mut sleep).is_pending());
+        clock.advance(10 * MS);
+        assert!(poll(&mut sleep).is_ready());
+
+        // 已经完成的定时器也可以重置
+        sleep.reset(clock.now() + 5 * MS);
+        assert!(poll(&mut sleep).is_pending());
+        clock.advance(5 * MS);
+        assert!(poll(&mut sleep).is_ready());
+    }
+
+    #[test]
+    fn interval_ticks_every_period() {
+        let clock = Clock::paused();
+        let start = clock.now();
+        let mut interval = clock.interval(10 * MS);
+
+        assert_eq!(tick(&mut interval), Poll::Ready(start));
+        assert!(tick(&mut interval).is_pending());
+        for i in 1..=3 {
+            clock.advance(10 * MS);
+            assert_eq!(tick(&mut interval), Poll::Ready(start + i * 10 * MS));
+            assert!(tick(&mut interval).is_pending());
+        }
+    }
+
+    /// An interval whose consumer missed the ticks at 10, 20 and 30ms, it polls again at 35ms
+    fn late_interval(behavior: MissedTickBehavior) -> (Clock, Instant, Interval) {
+        let clock = Clock::paused();
+        let start = clock.now();
+        let mut interval = clock.interval(10 * MS);
+        interval.set_missed_tick_behavior(behavior);
+        assert_eq!(interval.missed_tick_behavior(), behavior);
+        assert_eq!(tick(&mut interval), Poll::Ready(start));
+
+        clock.advance(35 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 10 * MS));
+        (clock, start, interval)
+    }
+
+    #[test]
+    fn interval_burst_yields_missed_ticks() {
+        let (clock, start, mut interval) = late_interval(MissedTickBehavior::Burst);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 20 * MS));
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 30 * MS));
+        assert!(tick(&mut interval).is_pending());
+
+        clock.advance(5 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 40 * MS));
+    }
+
+    #[test]
+    fn interval_delay_restarts_from_late_tick() {
+        let (clock, start, mut interval) = late_interval(MissedTickBehavior::Delay);
+        assert!(tick(&mut interval).is_pending());
+
+        clock.advance(5 * MS);
+        assert!(tick(&mut interval).is_pending());
+        clock.advance(5 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 45 * MS));
+        clock.advance(10 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 55 * MS));
+    }
+
+    #[test]
+    fn interval_skip_keeps_schedule() {
+        let (clock, start, mut interval) = late_interval(MissedTickBehavior::Skip);
+        assert!(tick(&mut interval).is_pending());
+
+        clock.advance(5 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 40 * MS));
+        clock.advance(10 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 50 * MS));
+    }
+
+    #[test]
+    fn interval_tolerates_slightly_late_ticks() {
+        let clock = Clock::paused();
+        let start = clock.now();
+        let mut interval = clock.interval(10 * MS);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        assert_eq!(tick(&mut interval), Poll::Ready(start));
+
+        // 晚了不到 MISSED_TICK_TOLERANCE 不算错过
+        clock.advance(12 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 10 * MS));
+        clock.advance(8 * MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 20 * MS));
+    }
+
+    #[test]
+    fn interval_reset() {
+        let clock = Clock::paused();
+        let start = clock.now();
+        let mut interval = clock.interval_at(start + 10 * MS, 10 * MS);
+        clock.advance(5 * MS);
+        interval.reset();
+        clock.advance(9 * MS);
+        assert!(tick(&mut interval).is_pending());
+        clock.advance(MS);
+        assert_eq!(tick(&mut interval), Poll::Ready(start + 15 * MS));
+    }
+
+    #[test]
+    fn timeout_elapses() {
+        let clock = Clock::paused();
+        let mut timeout = clock.timeout(10 * MS, clock.sleep(20 * MS));
+        assert!(poll(&mut timeout).is_pending());
+        clock.advance(9 * MS);
+        assert!(poll(&mut timeout).is_pending());
+        clock.advance(MS);
+        assert!(matches!(
+            poll(&mut timeout),
+            Poll::Ready(Err(Elapsed { .. }))
+        ));
+    }
+
+    #[test]
+    fn timeout_completes_before_deadline() {
+        let clock = Clock::paused();
+        let mut timeout = clock.timeout(20 * MS, clock.sleep(10 * MS));
+        assert!(poll(&mut timeout).is_pending());
+        clock.advance(10 * MS);
+        assert_eq!(poll(&mut timeout), Poll::Ready(Ok(())));
+
+        let inner = clock.timeout(20 * MS, clock.sleep(30 * MS)).into_inner();
+        assert_eq!(inner.deadline(), clock.now() + 30 * MS);
+    }
+
+    #[test]
+    fn timeout_prefers_output_at_deadline() {
+        // 内部的 future 和定时器同时到期时，不丢弃已经完成的结果
+        let clock = Clock::paused();
+        let mut timeout = clock.timeout(10 * MS, clock.sleep(10 * MS));
+        clock.advance(10 * MS);
+        assert_eq!(poll(&mut timeout), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    #[should_panic(expected = "only a paused clock can be advanced")]
+    fn system_clock_cannot_be_advanced() {
+        Clock::system().advance(MS);
+    }
+
+    #[test]
+    fn paused_system_time() {
+        let clock = Clock::paused();
+        let start = clock.system_time();
+        assert_eq!(clock.system_time(), start);
+        clock.advance(1500 * MS);
+        assert_eq!(clock.system_time(), start + 1500 * MS);
+
+        let system = Clock::system();
+        let before = std::time::SystemTime::now();
+        assert!(system.system_time() >= before);
+    }
+}
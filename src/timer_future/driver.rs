@@ -0,0 +1,194 @@
+//! Timer driver.
+//! 所有的 `TimerFuture` 都注册到同一个时间轮上，由一个后台线程负责推进时间轮并唤醒到期的定时器，
+//! 这样无论创建多少个定时器，都只需要一个线程。
+//!
+//! 暂停的驱动没有后台线程，时间只会在调用 `advance` 时前进，到期的定时器在 `advance` 中被同步唤醒。
+
+use std::{
+    sync::{Arc, Condvar, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use super::{
+    wheel::{TimerId, Wheel},
+    SharedState,
+};
+
+pub(crate) struct Driver {
+    /// The instant corresponding to tick 0 of the wheel
+    start: Instant,
+    /// The wall-clock time at `start`
+    start_time: SystemTime,
+    source: TimeSource,
+    state: Mutex<State>,
+    /// Used to wake the driver thread when a timer earlier than the one it is sleeping for is registered
+    condvar: Condvar,
+}
+
+enum TimeSource {
+    /// Real time, driven by the driver thread
+    System,
+    /// Time only moves when `advance` is called, holds the time elapsed since `start`
+    Paused(Mutex<Duration>),
+}
+
+struct State {
+    wheel: Wheel<Arc<SharedState>>,
+    /// The tick the driver thread is sleeping until, `None` if it is sleeping without timeout
+    parked_until: Option<u64>,
+}
+
+impl Driver {
+    /// The global driver, the driver thread is started the first time it is used
+    pub(crate) fn global() -> &'static Arc<Driver> {
+        static DRIVER: OnceLock<Arc<Driver>> = OnceLock::new();
+
+        DRIVER.get_or_init(|| {
+            let driver = Arc::new(Driver::new(TimeSource::System));
+
+            let thread_driver = driver.clone();
+            thread::Builder::new()
+                .name("timer-driver".to_string())
+                .spawn(move || thread_driver.run())
+                .expect("failed to spawn the timer driver thread");
+            driver
+        })
+    }
+
+    /// A driver whose time only moves when `advance` is called
+    pub(crate) fn paused() -> Arc<Driver> {
+        Arc::new(Driver::new(TimeSource::Paused(Mutex::new(Duration::ZERO))))
+    }
+
+    fn new(source: TimeSource) -> Self {
+        Driver {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            source,
+            state: Mutex::new(State {
+                wheel: Wheel::new(),
+                parked_until: None,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        matches!(self.source, TimeSource::Paused(_))
+    }
+
+    /// The current time as seen by the timers of this driver
+    pub(crate) fn now(&self) -> Instant {
+        match &self.source {
+            TimeSource::System => Instant::now(),
+            TimeSource::Paused(elapsed) => self.start + *elapsed.lock().unwrap(),
+        }
+    }
+
+    /// The wall-clock time matching `now`
+    pub(crate) fn system_time(&self) -> SystemTime {
+        match &self.source {
+            TimeSource::System => SystemTime::now(),
+            TimeSource::Paused(elapsed) => self.start_time + *elapsed.lock().unwrap(),
+        }
+    }
+
+    /// Move the time of a paused driver forward by `duration`, waking all the timers that become due.
+    ///
+    /// # Panics
+    ///
+    /// The function will panic if the driver is not paused.
+    pub(crate) fn advance(&self, duration: Duration) {
+        let TimeSource::Paused(elapsed) = &self.source else {
+            panic!("only a paused clock can be advanced");
+        };
+        *elapsed.lock().unwrap() += duration;
+
+        let mut fired = Vec::new();
+        let now = self.now_tick();
+        self.state.lock().unwrap().wheel.poll(now, &mut fired);
+        for shared_state in fired {
+            shared_state.complete();
+        }
+    }
+
+    /// Register a timer that completes `state` once `deadline` is reached
+    pub(crate) fn register(&self, deadline: Instant, state: Arc<SharedState>) -> Option<TimerId> {
+        let when = self.deadline_to_tick(deadline);
+
+        let mut driver_state = self.state.lock().unwrap();
+        match driver_state.wheel.insert(when, state) {
+            Ok(id) => {
+                // 新的定时器比驱动线程正在等待的时间更早到期，需要唤醒驱动线程重新计算等待时间
+                if driver_state.parked_until.is_none_or(|tick| when < tick) {
+                    self.condvar.notify_one();
+                }
+                Some(id)
+            }
+            Err(state) => {
+                // 已经到期，直接完成
+                drop(driver_state);
+                state.complete();
+                None
+            }
+        }
+    }
+
+    /// Deregister a timer, it is a no-op if the timer has already fired
+    pub(crate) fn cancel(&self, id: TimerId) {
+        let mut driver_state = self.state.lock().unwrap();
+        // 被取消的定时器可能正是驱动线程在等待的那个，这里不唤醒驱动线程，
+        // 它醒来后发现没有到期的定时器，会重新计算等待时间
+        driver_state.wheel.remove(id);
+    }
+
+    fn run(&self) {
+        let mut fired = Vec::new();
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = self.now_tick();
+            state.wheel.poll(now, &mut fired);
+
+            if !fired.is_empty() {
+                // 唤醒任务时不持有锁，被唤醒的任务可能会马上注册新的定时器
+                drop(state);
+                for shared_state in fired.drain(..) {
+                    shared_state.complete();
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state.parked_until = state.wheel.next_deadline();
+            state = match state.parked_until {
+                Some(tick) => {
+                    let timeout = self
+                        .tick_to_instant(tick)
+                        .saturating_duration_since(Instant::now());
+                    self.condvar.wait_timeout(state, timeout).unwrap().0
+                }
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+    }
+
+    fn now_tick(&self) -> u64 {
+        self.now().saturating_duration_since(self.start).as_millis() as u64
+    }
+
+    /// Convert a deadline into a tick, rounding up so that timers never fire early
+    fn deadline_to_tick(&self, deadline: Instant) -> u64 {
+        let since_start = deadline.saturating_duration_since(self.start);
+        let tick = since_start.as_millis() as u64;
+        if since_start > Duration::from_millis(tick) {
+            tick + 1
+        } else {
+            tick
+        }
+    }
+
+    fn tick_to_instant(&self, tick: u64) -> Instant {
+        self.start + Duration::from_millis(tick)
+    }
+}
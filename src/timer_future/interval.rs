@@ -0,0 +1,121 @@
+//! Interval: a stream that yields at a fixed period.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::Stream;
+
+use super::TimerFuture;
+
+/// A tick is only considered missed if it is yielded later than this after its deadline,
+/// the driver works at millisecond resolution so a tick is almost always a little late.
+const MISSED_TICK_TOLERANCE: Duration = Duration::from_millis(5);
+
+/// Create an `Interval` whose first tick completes immediately, then every `period`.
+///
+/// # Panics
+///
+/// The function will panic if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(Instant::now(), period)
+}
+
+/// Create an `Interval` whose first tick completes at `start`, then every `period`.
+///
+/// # Panics
+///
+/// The function will panic if `period` is zero.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    Interval::new(TimerFuture::at(start), period)
+}
+
+/// What an `Interval` does when ticks were missed, e.g. because the consumer was busy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// Yield the missed ticks as fast as possible until the interval has caught up
+    #[default]
+    Burst,
+    /// Schedule the next tick one `period` after the moment the late tick was yielded
+    Delay,
+    /// Drop the missed ticks and continue with the next tick on the original schedule
+    Skip,
+}
+
+impl MissedTickBehavior {
+    /// The deadline of the tick following the late tick `deadline`, which was yielded at `now`
+    fn next_deadline(&self, deadline: Instant, now: Instant, period: Duration) -> Instant {
+        match self {
+            MissedTickBehavior::Burst => deadline + period,
+            MissedTickBehavior::Delay => now + period,
+            MissedTickBehavior::Skip => {
+                let behind = (now - deadline).as_nanos() % period.as_nanos();
+                now + period - Duration::from_nanos(behind as u64)
+            }
+        }
+    }
+}
+
+/// A `Stream` that yields the deadline of each tick
+pub struct Interval {
+    timer: TimerFuture,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+impl Interval {
+    /// Create an `Interval` whose first tick completes when `timer` does
+    pub(super) fn new(timer: TimerFuture, period: Duration) -> Self {
+        assert!(period > Duration::ZERO, "`period` must be non-zero");
+
+        Interval {
+            timer,
+            period,
+            missed_tick_behavior: MissedTickBehavior::default(),
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Restart the interval so that the next tick completes one `period` from now
+    pub fn reset(&mut self) {
+        let now = self.timer.driver.now();
+        self.timer.reset(now + self.period);
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if Pin::new(&mut self.timer).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let deadline = self.timer.deadline();
+        let now = self.timer.driver.now();
+        let next = if now > deadline + MISSED_TICK_TOLERANCE {
+            self.missed_tick_behavior
+                .next_deadline(deadline, now, self.period)
+        } else {
+            deadline + self.period
+        };
+        self.timer.reset(next);
+
+        // 定时器是无限的，永远不会返回 None
+        Poll::Ready(Some(deadline))
+    }
+}
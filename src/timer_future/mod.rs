@@ -0,0 +1,154 @@
+//! Timer future.
+//! When the timer is created, it is registered with the global timer driver, a single background thread
+//! that drives a hierarchical timer wheel and notifies the Future once its deadline is reached.
+
+mod clock;
+mod driver;
+mod interval;
+mod timeout;
+mod wheel;
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use driver::Driver;
+use futures::task::AtomicWaker;
+use wheel::TimerId;
+
+pub use clock::Clock;
+pub use interval::{interval, interval_at, Interval, MissedTickBehavior};
+pub use timeout::{timeout, Elapsed, Timeout};
+
+/// Create a `TimerFuture` that completes at `deadline`
+pub fn sleep_until(deadline: Instant) -> TimerFuture {
+    TimerFuture::at(deadline)
+}
+
+/// A future that completes once its deadline is reached.
+///
+/// Dropping a `TimerFuture` deregisters it from the timer driver.
+pub struct TimerFuture {
+    driver: Arc<Driver>,
+    shared_state: Arc<SharedState>,
+    deadline: Instant,
+    /// The registration in the timer driver, `None` once the timer has been found already expired
+    id: Option<TimerId>,
+}
+
+/// Share state between Future and the timer driver, both sides access it without locking
+struct SharedState {
+    /// Whether the deadline has been reached
+    completed: AtomicBool,
+    /// When the deadline is reached, the driver can use `waker` to notify `TimerFuture` to wake up the task
+    waker: AtomicWaker,
+}
+
+impl SharedState {
+    fn new() -> Self {
+        SharedState {
+            completed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Mark the timer as completed and wake the task waiting on it
+    fn complete(&self) {
+        self.completed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let state = &self.shared_state;
+        if state.completed.load(Ordering::Acquire) {
+            // 睡眠结束，在当前 poll 中， Future 可以被完成，则会返回 Poll::Ready(result)
+            return Poll::Ready(());
+        }
+
+        // 设置`waker`，这样驱动线程在定时器到期后可以唤醒当前的任务，接着再次对`Future`进行`poll`操作,
+        //
+        // `TimerFuture`可以在执行器的不同任务间移动，因此每次`poll`都要检查`waker`是否仍然指向当前任务。
+        // `AtomicWaker::register`通过`Waker::will_wake`判断，只有在`waker`变化时才会`clone`，
+        // 而不是每次`poll`都`clone`一次
+        state.waker.register(cx.waker());
+
+        // 注册`waker`之前定时器可能刚好到期，此时驱动线程拿不到新的`waker`，需要再检查一次
+        if state.completed.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl TimerFuture {
+    /// 创建一个新的`TimerFuture`，在指定的时间结束后，该`TimerFuture`可以完成
+    pub fn new(duration: Duration) -> Self {
+        Self::at(Instant::now() + duration)
+    }
+
+    /// 创建一个新的`TimerFuture`，在到达`deadline`时完成
+    fn at(deadline: Instant) -> Self {
+        Self::with_driver(Driver::global().clone(), deadline)
+    }
+
+    /// 创建一个注册在`driver`上的`TimerFuture`，它使用`driver`的时间
+    fn with_driver(driver: Arc<Driver>, deadline: Instant) -> Self {
+        let state = Arc::new(SharedState::new());
+
+        // 所有定时器共享同一个驱动线程，而不是每个定时器一个线程
+        let id = driver.register(deadline, state.clone());
+        TimerFuture {
+            driver,
+            shared_state: state,
+            deadline,
+            id,
+        }
+    }
+
+    /// The instant at which the timer completes
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Move the deadline of the timer to `deadline`, whether or not it has already completed.
+    ///
+    /// This is useful for idle timeouts that get pushed forward on activity.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.cancel();
+
+        // 旧的定时器可能已经被驱动线程取出、正在被触发，因此不能复用旧的共享状态，
+        // 否则它可能会把重置后的定时器标记为完成。这里换一个新的共享状态，并把 waker 转移过来
+        let shared_state = SharedState::new();
+        if let Some(waker) = self.shared_state.waker.take() {
+            shared_state.waker.register(&waker);
+        }
+        self.shared_state = Arc::new(shared_state);
+        self.deadline = deadline;
+        self.id = self.driver.register(deadline, self.shared_state.clone());
+    }
+
+    fn cancel(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.driver.cancel(id);
+        }
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        // 从驱动中注销定时器，驱动不会再访问共享状态
+        self.cancel();
+    }
+}
@@ -0,0 +1,70 @@
+//! Timeout: require a future to complete before a deadline.
+
+use std::{
+    error::Error,
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use super::TimerFuture;
+
+/// Require `future` to complete within `duration`.
+///
+/// The returned future resolves to `Err(Elapsed)` if the time runs out first, in which case
+/// `future` is dropped without being completed.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout::new(future, TimerFuture::new(duration))
+}
+
+pub struct Timeout<F> {
+    future: F,
+    delay: TimerFuture,
+}
+
+/// The error returned by `Timeout` when the deadline has elapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+impl<F> Timeout<F> {
+    /// Create a `Timeout` that fails once `delay` completes
+    pub(super) fn new(future: F, delay: TimerFuture) -> Self {
+        Timeout { future, delay }
+    }
+
+    /// Consume the `Timeout`, returning the inner future
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of a pinned `Timeout`, `delay` is `Unpin`
+        // and does not need to be pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        // 先 poll 内部的 future，这样即使定时器同时到期，已经完成的结果也不会被丢弃
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match Pin::new(&mut this.delay).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
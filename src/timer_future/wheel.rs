@@ -0,0 +1,217 @@
+//! Hierarchical hashed timer wheel.
+//!
+//! 时间轮按层级组织，每层 64 个槽位，第 `n` 层的一个槽位覆盖 `64^n` 个 tick(1 tick = 1 毫秒)。
+//! 定时器按照到期时间与当前时间的差异被放入合适的层级中，当高层级的槽位到期后，
+//! 其中的定时器会被重新放入更低的层级，直到最终在第 0 层到期。
+//! 插入与删除都只需要常数时间。
+
+use std::collections::{HashMap, HashSet};
+
+/// Number of bits used to index the slots in one level
+const LEVEL_BITS: usize = 6;
+
+/// Number of slots in one level
+const SLOTS: usize = 1 << LEVEL_BITS;
+
+const SLOT_MASK: u64 = (SLOTS - 1) as u64;
+
+/// Number of levels, 6 levels of 64 slots cover about two years at millisecond resolution
+const NUM_LEVELS: usize = 6;
+
+/// The maximum distance (in ticks) between now and a timer's deadline that the wheel can represent
+pub(crate) const MAX_TICKS: u64 = 1 << (LEVEL_BITS * NUM_LEVELS);
+
+/// Identifies a timer registered in the wheel
+pub(crate) type TimerId = u64;
+
+pub(crate) struct Wheel<T> {
+    /// The tick up to which the wheel has been processed
+    elapsed: u64,
+    levels: Vec<Level>,
+    entries: HashMap<TimerId, Entry<T>>,
+    next_id: TimerId,
+}
+
+struct Level {
+    /// Bit `n` is set when slot `n` holds at least one timer
+    occupied: u64,
+    slots: Vec<HashSet<TimerId>>,
+}
+
+struct Entry<T> {
+    when: u64,
+    level: usize,
+    slot: usize,
+    value: T,
+}
+
+/// The next slot that needs to be processed
+struct Expiration {
+    level: usize,
+    slot: usize,
+    deadline: u64,
+}
+
+impl<T> Wheel<T> {
+    pub(crate) fn new() -> Self {
+        Wheel {
+            elapsed: 0,
+            levels: (0..NUM_LEVELS).map(|_| Level::new()).collect(),
+            entries: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Register `value` to expire at tick `when`.
+    ///
+    /// If `when` is not later than the processed time, the timer is already due and
+    /// `value` is handed back to the caller.
+    pub(crate) fn insert(&mut self, when: u64, value: T) -> Result<TimerId, T> {
+        if when <= self.elapsed {
+            return Err(value);
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (level, slot) = self.place(id, when);
+        self.entries.insert(
+            id,
+            Entry {
+                when,
+                level,
+                slot,
+                value,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Deregister a timer, returning its value if it had not fired yet
+    pub(crate) fn remove(&mut self, id: TimerId) -> Option<T> {
+        let entry = self.entries.remove(&id)?;
+        self.levels[entry.level].remove(entry.slot, id);
+        Some(entry.value)
+    }
+
+    /// The tick at which the wheel next needs to be polled, `None` if it is empty
+    pub(crate) fn next_deadline(&self) -> Option<u64> {
+        self.next_expiration().map(|expiration| expiration.deadline)
+    }
+
+    /// Advance the wheel to tick `now`, pushing the values of all expired timers into `fired`
+    pub(crate) fn poll(&mut self, now: u64, fired: &mut Vec<T>) {
+        while let Some(expiration) = self.next_expiration() {
+            if expiration.deadline > now {
+                break;
+            }
+            self.process_expiration(expiration, fired);
+        }
+        self.elapsed = self.elapsed.max(now);
+    }
+
+    fn process_expiration(&mut self, expiration: Expiration, fired: &mut Vec<T>) {
+        let ids = self.levels[expiration.level].take(expiration.slot);
+        // 先推进时间，这样未到期的定时器会被重新放入更低的层级
+        self.elapsed = expiration.deadline;
+
+        for id in ids {
+            let when = self.entries[&id].when;
+            if when <= self.elapsed {
+                let entry = self.entries.remove(&id).unwrap();
+                fired.push(entry.value);
+            } else {
+                let (level, slot) = self.place(id, when);
+                let entry = self.entries.get_mut(&id).unwrap();
+                entry.level = level;
+                entry.slot = slot;
+            }
+        }
+    }
+
+    /// Put the timer `id` into the slot that matches `when`, relative to the processed time
+    fn place(&mut self, id: TimerId, when: u64) -> (usize, usize) {
+        // 超出时间轮表示范围的定时器先放在最高层，到期时再重新放置
+        let when = when.min(self.elapsed + MAX_TICKS - 1);
+        let level = level_for(self.elapsed, when);
+        let slot = slot_for(when, level);
+        self.levels[level].add(slot, id);
+        (level, slot)
+    }
+
+    fn next_expiration(&self) -> Option<Expiration> {
+        // 低层级的槽位总是比高层级的槽位先到期
+        for (level, lvl) in self.levels.iter().enumerate() {
+            if let Some(slot) = lvl.next_occupied_slot(self.elapsed, level) {
+                let slot_range = slot_range(level);
+                let level_range = slot_range << LEVEL_BITS;
+                let level_start = self.elapsed & !(level_range - 1);
+                let mut deadline = level_start + slot as u64 * slot_range;
+                if deadline <= self.elapsed {
+                    // 槽位位于当前位置之前，说明它属于下一轮
+                    deadline += level_range;
+                }
+                return Some(Expiration {
+                    level,
+                    slot,
+                    deadline,
+                });
+            }
+        }
+        None
+    }
+}
+
+impl Level {
+    fn new() -> Self {
+        Level {
+            occupied: 0,
+            slots: (0..SLOTS).map(|_| HashSet::new()).collect(),
+        }
+    }
+
+    fn add(&mut self, slot: usize, id: TimerId) {
+        self.slots[slot].insert(id);
+        self.occupied |= 1 << slot;
+    }
+
+    fn remove(&mut self, slot: usize, id: TimerId) {
+        self.slots[slot].remove(&id);
+        if self.slots[slot].is_empty() {
+            self.occupied &= !(1 << slot);
+        }
+    }
+
+    fn take(&mut self, slot: usize) -> HashSet<TimerId> {
+        self.occupied &= !(1 << slot);
+        std::mem::take(&mut self.slots[slot])
+    }
+
+    fn next_occupied_slot(&self, now: u64, level: usize) -> Option<usize> {
+        if self.occupied == 0 {
+            return None;
+        }
+        let now_slot = ((now / slot_range(level)) & SLOT_MASK) as u32;
+        let occupied = self.occupied.rotate_right(now_slot);
+        let zeros = occupied.trailing_zeros() as usize;
+        Some((zeros + now_slot as usize) % SLOTS)
+    }
+}
+
+fn slot_range(level: usize) -> u64 {
+    1 << (level * LEVEL_BITS)
+}
+
+fn level_for(elapsed: u64, when: u64) -> usize {
+    // 找出 elapsed 与 when 第一个不同的比特位，它决定了定时器应该放在哪一层
+    let mut masked = (elapsed ^ when) | SLOT_MASK;
+    if masked >= MAX_TICKS {
+        masked = MAX_TICKS - 1;
+    }
+    let significant = 63 - masked.leading_zeros() as usize;
+    significant / LEVEL_BITS
+}
+
+fn slot_for(when: u64, level: usize) -> usize {
+    ((when >> (level * LEVEL_BITS)) & SLOT_MASK) as usize
+}
@@ -0,0 +1,71 @@
+//! 100k outstanding timers are driven by the single timer driver thread, without a thread per
+//! timer, and their memory is bounded.
+//!
+//! The test counts the threads of the process, it lives in its own test binary so that no other
+//! test runs concurrently.
+
+#![cfg(target_os = "linux")]
+
+use std::{fs, time::Duration};
+
+use futures::{executor::block_on, future::join_all};
+use mini_projects::timer_future::TimerFuture;
+
+const TIMERS: usize = 100_000;
+
+/// The number of rounds of timers registered and cancelled after the first ones
+const ROUNDS: usize = 10;
+
+/// The number of threads of this process
+fn threads() -> usize {
+    fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+/// The resident memory of this process, in bytes
+fn rss() -> usize {
+    let statm = fs::read_to_string("/proc/self/statm").unwrap();
+    let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+    pages * 4096
+}
+
+#[test]
+fn one_driver_thread_for_many_timers() {
+    let threads_before = threads();
+    let rss_before = rss();
+
+    let timers: Vec<_> = (0..TIMERS)
+        .map(|i| TimerFuture::new(Duration::from_secs(3600 + i as u64)))
+        .collect();
+    // 第一个定时器启动了驱动线程，之后不再创建线程
+    assert_eq!(threads(), threads_before + 1);
+    let grown = rss() - rss_before;
+    assert!(
+        grown < TIMERS * 512,
+        "{} bytes for {} timers",
+        grown,
+        TIMERS
+    );
+    drop(timers);
+
+    // 同时到期的定时器也由同一个线程唤醒
+    let timers = (0..TIMERS).map(|i| TimerFuture::new(Duration::from_millis(i as u64 % 50)));
+    block_on(join_all(timers));
+    assert_eq!(threads(), threads_before + 1);
+
+    // 注销的和到期的定时器占用的内存可以被之后的定时器复用。到期时间落到新的槽位时内存会涨一次，
+    // 泄漏的话每一轮都会涨
+    let rss_after_first = rss();
+    for _ in 0..ROUNDS {
+        let timers: Vec<_> = (0..TIMERS)
+            .map(|_| TimerFuture::new(Duration::from_secs(3600)))
+            .collect();
+        drop(timers);
+    }
+    let grown = rss().saturating_sub(rss_after_first);
+    assert!(
+        grown < TIMERS * 128,
+        "{} bytes leaked in {} rounds",
+        grown,
+        ROUNDS
+    );
+}